@@ -0,0 +1,79 @@
+//! Measures checkout throughput with many tasks contending for a small pool on a
+//! multi-threaded runtime, for increasing numbers of tasks.
+//!
+//! Every checkout takes the pool's lock at least twice, once to take a connection and
+//! once to return it, so this mostly measures how long the lock is held on those paths.
+//!
+//! Run with `cargo bench -p bb8 --bench contention`.
+
+use std::convert::Infallible;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use bb8::{ManageConnection, Pool};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+fn contention(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let mut group = c.benchmark_group("contention");
+    for getters in [4, 16, 64] {
+        group.throughput(Throughput::Elements(getters as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(getters),
+            &getters,
+            |b, &getters| b.iter_custom(|iters| runtime.block_on(run(getters, iters))),
+        );
+    }
+    group.finish();
+}
+
+/// Time `getters` tasks each checking out a connection `iters` times
+async fn run(getters: usize, iters: u64) -> Duration {
+    let pool = Pool::builder()
+        .max_size(4)
+        .min_idle(Some(2))
+        .build(Manager)
+        .await
+        .unwrap();
+
+    let start = Instant::now();
+    let tasks = (0..getters).map(|_| {
+        let pool = pool.clone();
+        tokio::spawn(async move {
+            for _ in 0..iters {
+                drop(pool.get().await.unwrap());
+            }
+        })
+    });
+    for task in futures_util::future::join_all(tasks).await {
+        task.unwrap();
+    }
+    start.elapsed()
+}
+
+struct Manager;
+
+#[async_trait]
+impl ManageConnection for Manager {
+    type Connection = ();
+    type Error = Infallible;
+
+    async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+        Ok(())
+    }
+
+    async fn is_valid(&self, _: &mut Self::Connection) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn has_broken(&self, _: &mut Self::Connection) -> bool {
+        false
+    }
+}
+
+criterion_group!(benches, contention);
+criterion_main!(benches);
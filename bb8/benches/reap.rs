@@ -0,0 +1,100 @@
+//! Measures how long checkouts stall while the reaper closes many connections at once, for
+//! increasing numbers of connections.
+//!
+//! The pool is filled with connections that are slow to drop, all of which reach their
+//! maximum lifetime together. A single task keeps checking out connections meanwhile, and
+//! the slowest checkout shows how long the reaper kept the pool's lock. Each iteration
+//! reports that slowest checkout.
+//!
+//! Run with `cargo bench -p bb8 --bench reap`.
+
+use std::convert::Infallible;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use bb8::{ManageConnection, Pool};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+const DROP_TIME: Duration = Duration::from_micros(20);
+
+fn reap(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let mut group = c.benchmark_group("reap");
+    group.sample_size(10);
+    for connections in [500, 2_000] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(connections),
+            &connections,
+            |b, &connections| {
+                b.iter_custom(|iters| {
+                    (0..iters)
+                        .map(|_| runtime.block_on(slowest_checkout(connections)))
+                        .sum()
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+/// The slowest checkout while `connections` expire together
+async fn slowest_checkout(connections: u32) -> Duration {
+    let lifetime = Duration::from_millis(500);
+    let pool = Pool::builder()
+        .max_size(connections + 1)
+        .min_idle(Some(connections))
+        .max_lifetime(Some(lifetime))
+        .reaper_rate(Duration::from_millis(100))
+        .build(Manager)
+        .await
+        .unwrap();
+
+    let mut slowest = Duration::from_secs(0);
+    let end = Instant::now() + lifetime * 2;
+    while Instant::now() < end {
+        let start = Instant::now();
+        drop(pool.get().await.unwrap());
+        slowest = slowest.max(start.elapsed());
+        tokio::task::yield_now().await;
+    }
+    slowest
+}
+
+struct Manager;
+
+#[async_trait]
+impl ManageConnection for Manager {
+    type Connection = SlowDrop;
+    type Error = Infallible;
+
+    async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+        Ok(SlowDrop)
+    }
+
+    async fn is_valid(&self, _: &mut Self::Connection) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn has_broken(&self, _: &mut Self::Connection) -> bool {
+        false
+    }
+}
+
+/// A connection that takes a while to close, like one that says goodbye to its server
+struct SlowDrop;
+
+impl Drop for SlowDrop {
+    fn drop(&mut self) {
+        let start = Instant::now();
+        while start.elapsed() < DROP_TIME {
+            std::hint::spin_loop();
+        }
+    }
+}
+
+criterion_group!(benches, reap);
+criterion_main!(benches);
@@ -0,0 +1,107 @@
+//! Measures the tail latency of checkouts while the reaper closes connections that are slow
+//! to drop, with the reaper on the runtime and with `Builder::reaper_on_dedicated_thread`.
+//!
+//! The runtime has a single worker, which the reaper on the runtime shares with the caller,
+//! including for dropping the connections it closed. Each iteration reports the slowest
+//! checkout over the lifetime of the connections, during which they are all replaced once.
+//!
+//! Run with `cargo bench -p bb8 --bench reaper_thread`.
+
+use std::convert::Infallible;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use bb8::{ManageConnection, Pool};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+const CONNECTIONS: u32 = 500;
+const DROP_TIME: Duration = Duration::from_micros(20);
+const LIFETIME: Duration = Duration::from_millis(100);
+
+fn reaper_thread(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let mut group = c.benchmark_group("reaper_thread");
+    group.sample_size(20);
+    for on_thread in [false, true] {
+        let pool = runtime.block_on(build(on_thread));
+        let id = BenchmarkId::new("dedicated", on_thread);
+        group.bench_with_input(id, &pool, |b, pool| {
+            b.iter_custom(|iters| {
+                (0..iters)
+                    .map(|_| runtime.block_on(slowest_checkout(pool)))
+                    .sum()
+            })
+        });
+    }
+    group.finish();
+}
+
+/// A full pool whose connections keep expiring and being replaced
+async fn build(on_thread: bool) -> Pool<Manager> {
+    let pool = Pool::builder()
+        .max_size(CONNECTIONS + 1)
+        .min_idle(Some(CONNECTIONS))
+        .max_lifetime(Some(LIFETIME))
+        .reaper_rate(Duration::from_millis(10))
+        .reaper_on_dedicated_thread(on_thread)
+        .build(Manager)
+        .await
+        .unwrap();
+    while pool.state().idle_connections < CONNECTIONS {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    pool
+}
+
+/// The slowest checkout over a connection lifetime, letting other tasks run in between
+async fn slowest_checkout(pool: &Pool<Manager>) -> Duration {
+    let mut slowest = Duration::from_secs(0);
+    let end = Instant::now() + LIFETIME;
+    while Instant::now() < end {
+        let start = Instant::now();
+        drop(pool.get().await.unwrap());
+        slowest = slowest.max(start.elapsed());
+        tokio::task::yield_now().await;
+    }
+    slowest
+}
+
+struct Manager;
+
+#[async_trait]
+impl ManageConnection for Manager {
+    type Connection = SlowDrop;
+    type Error = Infallible;
+
+    async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+        Ok(SlowDrop)
+    }
+
+    async fn is_valid(&self, _: &mut Self::Connection) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn has_broken(&self, _: &mut Self::Connection) -> bool {
+        false
+    }
+}
+
+/// A connection that takes a while to close, like one that says goodbye to its server
+struct SlowDrop;
+
+impl Drop for SlowDrop {
+    fn drop(&mut self) {
+        let start = Instant::now();
+        while start.elapsed() < DROP_TIME {
+            std::hint::spin_loop();
+        }
+    }
+}
+
+criterion_group!(benches, reaper_thread);
+criterion_main!(benches);
@@ -0,0 +1,84 @@
+//! Compares checkout throughput of a pool with a single lock against the same pool split
+//! into shards with `Builder::shards`, with many tasks contending for it on a
+//! multi-threaded runtime.
+//!
+//! Connections are plentiful, so callers rarely wait for one and the time spent on each
+//! checkout is dominated by taking the lock of the shard it goes to.
+//!
+//! Run with `cargo bench -p bb8 --bench shards`.
+
+use std::convert::Infallible;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use bb8::{ManageConnection, Pool};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+const GETTERS: usize = 64;
+
+fn shards(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let mut group = c.benchmark_group("shards");
+    group.throughput(Throughput::Elements(GETTERS as u64));
+    for shards in [1, 8] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(shards),
+            &shards,
+            |b, &shards| b.iter_custom(|iters| runtime.block_on(run(shards, iters))),
+        );
+    }
+    group.finish();
+}
+
+/// Time `GETTERS` tasks each checking out a connection `iters` times from a pool split into
+/// `shards` shards
+async fn run(shards: u32, iters: u64) -> Duration {
+    let pool = Pool::builder()
+        .max_size(32)
+        .min_idle(Some(32))
+        .shards(shards)
+        .build(Manager)
+        .await
+        .unwrap();
+
+    let start = Instant::now();
+    let tasks = (0..GETTERS).map(|_| {
+        let pool = pool.clone();
+        tokio::spawn(async move {
+            for _ in 0..iters {
+                drop(pool.get().await.unwrap());
+            }
+        })
+    });
+    for task in futures_util::future::join_all(tasks).await {
+        task.unwrap();
+    }
+    start.elapsed()
+}
+
+struct Manager;
+
+#[async_trait]
+impl ManageConnection for Manager {
+    type Connection = ();
+    type Error = Infallible;
+
+    async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+        Ok(())
+    }
+
+    async fn is_valid(&self, _: &mut Self::Connection) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn has_broken(&self, _: &mut Self::Connection) -> bool {
+        false
+    }
+}
+
+criterion_group!(benches, shards);
+criterion_main!(benches);
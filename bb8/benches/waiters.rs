@@ -0,0 +1,173 @@
+//! Counts the allocations made while bursts of callers queue up for a saturated pool, with
+//! and without `Builder::waiter_capacity_hint`.
+//!
+//! Without the hint, the waiter queue reallocates as it grows during the first burst. The
+//! remaining allocations are made per waiter, mostly for the channel each one waits on.
+//! Criterion reports allocations instead of time here, for the first burst on a new pool.
+//!
+//! Run with `cargo bench -p bb8 --bench waiters`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use async_trait::async_trait;
+use bb8::{ManageConnection, Pool};
+use criterion::measurement::{Measurement, ValueFormatter};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+
+const WAITERS: usize = 1024;
+
+fn waiters(c: &mut Criterion<Allocations>) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let mut group = c.benchmark_group("waiters");
+    group.throughput(Throughput::Elements(WAITERS as u64));
+    for hint in [0, WAITERS] {
+        // Every burst is the first on a new pool, which is when the waiter queue grows
+        let build = || {
+            runtime.block_on(async {
+                let builder = Pool::builder().max_size(1).waiter_capacity_hint(hint);
+                builder.build(Manager).await.unwrap()
+            })
+        };
+        group.bench_function(BenchmarkId::new("capacity_hint", hint), |b| {
+            b.iter_batched(
+                build,
+                |pool| {
+                    runtime.block_on(burst(&pool));
+                    pool
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+/// Queue up `WAITERS` callers behind a checked out connection, then serve them in turn
+async fn burst(pool: &Pool<Manager>) {
+    let conn = pool.get().await.unwrap();
+    let waiters = futures_util::future::join_all((0..WAITERS).map(|_| async move {
+        drop(pool.get().await.unwrap());
+    }));
+
+    // Queue up every waiter before the connection is returned
+    let release = async move {
+        tokio::task::yield_now().await;
+        drop(conn);
+    };
+    futures_util::future::join(waiters, release).await;
+}
+
+struct Manager;
+
+#[async_trait]
+impl ManageConnection for Manager {
+    type Connection = ();
+    type Error = Infallible;
+
+    async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+        Ok(())
+    }
+
+    async fn is_valid(&self, _: &mut Self::Connection) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn has_broken(&self, _: &mut Self::Connection) -> bool {
+        false
+    }
+}
+
+/// Measures the allocations made by the global allocator
+struct Allocations;
+
+impl Measurement for Allocations {
+    type Intermediate = usize;
+    type Value = usize;
+
+    fn start(&self) -> Self::Intermediate {
+        ALLOCATIONS.load(Ordering::Relaxed)
+    }
+
+    fn end(&self, start: Self::Intermediate) -> Self::Value {
+        ALLOCATIONS.load(Ordering::Relaxed) - start
+    }
+
+    fn add(&self, v1: &Self::Value, v2: &Self::Value) -> Self::Value {
+        v1 + v2
+    }
+
+    fn zero(&self) -> Self::Value {
+        0
+    }
+
+    fn to_f64(&self, value: &Self::Value) -> f64 {
+        *value as f64
+    }
+
+    fn formatter(&self) -> &dyn ValueFormatter {
+        &AllocationsFormatter
+    }
+}
+
+struct AllocationsFormatter;
+
+impl ValueFormatter for AllocationsFormatter {
+    fn scale_values(&self, _typical: f64, _values: &mut [f64]) -> &'static str {
+        "allocs"
+    }
+
+    fn scale_throughputs(
+        &self,
+        _typical: f64,
+        throughput: &Throughput,
+        values: &mut [f64],
+    ) -> &'static str {
+        if let Throughput::Elements(elements) = throughput {
+            for value in values {
+                *value /= *elements as f64;
+            }
+        }
+        "allocs/waiter"
+    }
+
+    fn scale_for_machines(&self, _values: &mut [f64]) -> &'static str {
+        "allocs"
+    }
+}
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+criterion_group! {
+    name = benches;
+    // Allocations are counted exactly, and plots of values that do not vary fail
+    config = Criterion::default().with_measurement(Allocations).without_plots();
+    targets = waiters
+}
+criterion_main!(benches);
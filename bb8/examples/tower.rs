@@ -0,0 +1,69 @@
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bb8::{ManageConnection, Pool, PoolService};
+use tower::{ServiceBuilder, ServiceExt};
+
+// Select some static data from a "database" using a pool exposed as a `tower::Service`,
+// with the concurrency limit applied by tower middleware.
+//
+// Run with `cargo run -p bb8 --example tower --features tower`.
+#[tokio::main]
+async fn main() {
+    let pool = Pool::builder()
+        .max_size(4)
+        .build(CountingManager::default())
+        .await
+        .unwrap();
+
+    let service = ServiceBuilder::new()
+        .concurrency_limit(2)
+        .timeout(Duration::from_secs(1))
+        .service(PoolService::new(pool.clone()));
+
+    let mut handles = Vec::new();
+    for i in 0..8 {
+        let service = service.clone();
+        handles.push(tokio::spawn(async move {
+            let conn = service.oneshot(()).await.unwrap();
+            println!("request {} got connection {}", i, conn.id);
+        }));
+    }
+
+    for handle in handles {
+        handle.await.unwrap();
+    }
+
+    println!("{:?}", pool.state());
+}
+
+#[derive(Debug, Default)]
+struct CountingManager {
+    created: AtomicUsize,
+}
+
+#[derive(Debug)]
+struct Connection {
+    id: usize,
+}
+
+#[async_trait]
+impl ManageConnection for CountingManager {
+    type Connection = Connection;
+    type Error = Infallible;
+
+    async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+        let id = self.created.fetch_add(1, Ordering::SeqCst);
+        Ok(Connection { id })
+    }
+
+    async fn is_valid(&self, _: &mut Self::Connection) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn has_broken(&self, _: &mut Self::Connection) -> bool {
+        false
+    }
+}
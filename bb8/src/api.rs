@@ -1,17 +1,34 @@
 use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
 use std::error;
 use std::fmt;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
 use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut};
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
+use futures_util::future::{pending, BoxFuture};
+use futures_util::stream::Stream;
+use parking_lot::Mutex;
+use tokio::sync::{watch, OwnedSemaphorePermit, Semaphore};
 
-use crate::inner::PoolInner;
+use crate::inner::{CheckoutOptions, GetOptions, PoolInner, WeakPoolInner};
 use crate::internals::Conn;
-pub use crate::internals::State;
+pub use crate::internals::{
+    ConnInfo, Histogram, PoolConfig, PoolEvent, PoolHealth, PoolInfo, ReapSummary, State,
+    Statistics, TimestampedState, WarmSummary,
+};
 
 /// A generic connection pool.
+///
+/// A `Pool` is a single reference-counted handle to the pool's shared state. Cloning it is
+/// cheap, and all clones share the same connections, configuration and statistics, so types
+/// built on top of bb8 can hold a `Pool` directly rather than wrapping it in another `Arc`.
+/// This is a stable guarantee; use `Pool::downgrade` for handles that should not keep the
+/// pool alive.
 pub struct Pool<M>
 where
     M: ManageConnection,
@@ -30,6 +47,41 @@ where
     }
 }
 
+/// A handle to a `Pool` that does not keep it alive, created by `Pool::downgrade`.
+pub struct WeakPool<M>
+where
+    M: ManageConnection,
+{
+    inner: WeakPoolInner<M>,
+}
+
+impl<M: ManageConnection> WeakPool<M> {
+    /// Returns a `Pool` handle, if the pool has not shut down yet.
+    pub fn upgrade(&self) -> Option<Pool<M>> {
+        self.inner.upgrade().map(|inner| Pool { inner })
+    }
+}
+
+impl<M> Clone for WeakPool<M>
+where
+    M: ManageConnection,
+{
+    fn clone(&self) -> Self {
+        WeakPool {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<M> fmt::Debug for WeakPool<M>
+where
+    M: ManageConnection,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("WeakPool")
+    }
+}
+
 impl<M> fmt::Debug for Pool<M>
 where
     M: ManageConnection,
@@ -50,11 +102,481 @@ impl<M: ManageConnection> Pool<M> {
         self.inner.state()
     }
 
+    /// Returns the configuration of the pool along with its current state.
+    pub fn info(&self) -> PoolInfo {
+        self.inner.info()
+    }
+
+    /// Replaces the manager used to open and check connections, for instance to pick up
+    /// rotated credentials.
+    ///
+    /// This is safe to call concurrently with any other use of the pool. Connection
+    /// attempts already in progress complete with the manager they started with, while
+    /// existing connections are checked with the new manager from then on. They are kept
+    /// until they are reaped or closed; call
+    /// `Pool::invalidate_generation` as well to replace them as soon as possible.
+    pub fn update_manager(&self, manager: M) {
+        self.inner.update_manager(manager);
+    }
+
+    /// Replaces the manager and retires every connection opened with the previous one, for
+    /// instance to move the pool to a new endpoint without downtime.
+    ///
+    /// The manager and the pool's generation (see `Pool::invalidate_generation`) are swapped
+    /// in one step, so no connection from the new manager is ever retired and no connection
+    /// from the old one is kept:
+    ///
+    /// - Idle connections from the old manager are closed instead of being handed out.
+    /// - Connections checked out when this is called stay usable by their callers, and are
+    ///   closed when they are returned rather than going back to the pool.
+    /// - Connection attempts already in progress complete with the old manager, and the
+    ///   connections they open are retired like the other old ones; a caller waiting for
+    ///   one is served by a connection from the new manager instead.
+    /// - Every connection attempt that starts after this call uses the new manager.
+    ///
+    /// Callers of `Pool::get` are served throughout, by new connections as the old ones drain.
+    pub fn reconfigure(&self, manager: M) {
+        self.inner.reconfigure(manager);
+    }
+
+    /// Returns a readiness signal for the pool, for use in health checks.
+    ///
+    /// This packages the pool's state into one of a few coarse outcomes; use `Pool::state`
+    /// and `Pool::statistics` for a finer picture.
+    pub fn health(&self) -> PoolHealth {
+        self.inner.health()
+    }
+
+    /// Returns statistics about the activity of the pool since it was created.
+    pub fn statistics(&self) -> Statistics {
+        self.inner.statistics()
+    }
+
+    /// Reports the state and statistics of the pool to OpenTelemetry through `meter`.
+    ///
+    /// Registers observable gauges for the connections managed by the pool, the idle and
+    /// pending connections and the waiting callers, and observable counters for the
+    /// connections created and closed, the checkouts and the checkouts that timed out, all
+    /// named `bb8.pool.*` and recorded with `attributes`. The callbacks read counters that
+    /// are kept up to date as the pool is used, without taking the lock of the pool, and do
+    /// not keep the pool alive.
+    ///
+    /// Requires the `opentelemetry` feature.
+    #[cfg(feature = "opentelemetry")]
+    pub fn register_otel(
+        &self,
+        meter: &opentelemetry::metrics::Meter,
+        attributes: &[opentelemetry::KeyValue],
+    ) {
+        self.inner.register_otel(meter, attributes)
+    }
+
+    /// Returns the samples recorded with `Builder::metrics_history`, oldest first.
+    ///
+    /// Empty if the pool was built without it.
+    pub fn history(&self) -> Vec<TimestampedState> {
+        self.inner.history()
+    }
+
+    /// Returns the number of connections the pool is establishing in the background.
+    ///
+    /// Unlike `State::establishing`, this leaves out approved connections that are
+    /// waiting for a slot under `Builder::max_replenish_inflight`.
+    pub fn replenishing(&self) -> u32 {
+        self.inner.replenishing()
+    }
+
+    /// Returns the number of connections the pool needs to get back to `min_idle`.
+    ///
+    /// This counts connections already being established towards the target, and is zero
+    /// while the pool is suspended or closed. Unlike replenishing the pool, this does not
+    /// reserve any slots.
+    pub fn wanted(&self) -> u32 {
+        self.inner.wanted()
+    }
+
+    /// Returns how many of `num` new connections the pool would approve right now.
+    ///
+    /// This is limited by the slots left under `max_size`, but does not reserve them, so
+    /// it can be used to reason about the headroom of the pool without perturbing it.
+    pub fn would_approve(&self, num: u32) -> u32 {
+        self.inner.would_approve(num)
+    }
+
+    /// Returns a handle to this pool that does not keep it alive.
+    ///
+    /// Once every `Pool` handle has been dropped, the pool shuts down and
+    /// `WeakPool::upgrade` returns `None`.
+    pub fn downgrade(&self) -> WeakPool<M> {
+        WeakPool {
+            inner: self.inner.downgrade(),
+        }
+    }
+
+    /// Returns the maximum number of connections managed by the pool.
+    pub fn max_size(&self) -> u32 {
+        self.inner.export_config().max_size
+    }
+
+    /// Returns the minimum idle connection count the pool tries to maintain, if any.
+    pub fn min_idle(&self) -> Option<u32> {
+        self.inner.export_config().min_idle
+    }
+
+    /// Returns whether connections are validated before being checked out.
+    pub fn test_on_check_out(&self) -> bool {
+        self.inner.statics().test_on_check_out
+    }
+
+    /// Returns the maximum lifetime of connections in the pool, if any.
+    pub fn max_lifetime(&self) -> Option<Duration> {
+        self.inner.export_config().max_lifetime
+    }
+
+    /// Returns the duration after which idle connections may be closed, if any.
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.inner.export_config().idle_timeout
+    }
+
+    /// Returns how long `Pool::get` waits for a connection before giving up.
+    pub fn connection_timeout(&self) -> Duration {
+        self.inner.connection_timeout()
+    }
+
+    /// Returns the configuration currently in effect, including changes made through
+    /// `Pool::apply_config` and by `Builder::autoscale`.
+    pub fn export_config(&self) -> PoolConfig {
+        self.inner.export_config()
+    }
+
+    /// Replaces the configuration in effect, for instance with one exported from another pool.
+    ///
+    /// All settings are updated at once. Raising `max_size` or `min_idle` opens connections
+    /// as needed. Lowering `max_size` closes idle connections as needed, and connections
+    /// checked out at the time are closed as they are returned until the pool fits. With
+    /// `Builder::autoscale`, the new `max_size` is the current cap, which continues to be
+    /// adjusted within the autoscale bounds.
+    ///
+    /// The reaper only runs if the pool was built with `max_lifetime` or `idle_timeout` set,
+    /// so setting either has no effect on a pool that was built without them.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `max_size` is 0, or if `min_idle` is larger than `max_size`.
+    pub fn apply_config(&self, config: PoolConfig) {
+        self.inner.apply_config(config)
+    }
+
+    /// Raises `min_idle` to `target` for `duration`, for instance ahead of a scheduled batch
+    /// job, opening connections as needed.
+    ///
+    /// While boosts are running, the highest of their targets and the configured `min_idle`
+    /// is in effect, capped at `max_size`, so overlapping boosts each last for their own
+    /// duration without cutting another one short. Once a boost ends, connections are no
+    /// longer replenished for it; idle connections in excess of `min_idle` are then closed by
+    /// the reaper as they exceed the idle timeout.
+    pub fn boost_min_idle(&self, target: u32, duration: Duration) {
+        self.inner.boost_min_idle(target, duration)
+    }
+
+    /// Returns how long until the reaper next runs, or `None` if the pool has no reaper
+    /// or was closed.
+    ///
+    /// This can be used to schedule maintenance so that it does not coincide with reaping.
+    /// A reaper that fell behind runs right away, in which case this returns zero.
+    pub fn next_reap_in(&self) -> Option<Duration> {
+        self.inner.next_reap_in()
+    }
+
+    /// Returns a receiver that is updated with the pool's `State` whenever it changes.
+    ///
+    /// This allows reacting to changes in the composition of the pool (for example with
+    /// `watch::Receiver::changed`) without repeatedly calling `Pool::state`.
+    pub fn subscribe(&self) -> watch::Receiver<State> {
+        self.inner.subscribe()
+    }
+
+    /// Returns a stream of the events in the pool from now on, for debugging.
+    ///
+    /// Every connection created, closed, checked out and returned is reported, as well as
+    /// every run of the reaper. Events are buffered per stream; a stream that falls too far
+    /// behind skips the oldest events and reports how many with `PoolEvent::Lagged`. The
+    /// stream ends once the pool is dropped.
+    pub fn events(&self) -> impl Stream<Item = PoolEvent> {
+        self.inner.events()
+    }
+
+    /// Opens up to `num` new connections and waits for them to be established.
+    ///
+    /// No more connections are opened than `max_size` allows. Unlike replenishment in the
+    /// background, errors are not sent to the error sink but returned, so that callers can
+    /// decide whether to fail when the backend is unreachable. Attempts cut short by the
+    /// connection timeout have no error to return, and are counted as failed in the returned
+    /// summary of how many connections were approved and established.
+    pub async fn warm(&self, num: u32) -> Result<WarmSummary, Vec<M::Error>> {
+        self.inner.warm(num).await
+    }
+
+    /// Adds `conn` to the pool as if it had been established by the manager.
+    ///
+    /// This allows tests to set up specific pool states without going through
+    /// `ManageConnection::connect`. The connection is handed to a waiter if there is one, and
+    /// otherwise becomes idle. If the pool is already at `max_size`, the connection is returned.
+    #[cfg(feature = "test-util")]
+    pub fn insert_test_connection(&self, conn: M::Connection) -> Result<(), M::Connection> {
+        self.inner.adopt_connection(conn)
+    }
+
     /// Retrieves a connection from the pool.
     pub async fn get(&self) -> Result<PooledConnection<'_, M>, RunError<M::Error>> {
         self.inner.get().await
     }
 
+    /// Retrieves a connection from the pool, giving up early if `cancel` resolves.
+    ///
+    /// If `cancel` completes while waiting for a connection, this resolves to
+    /// `RunError::Cancelled` and the wait is removed from the pool's queue.
+    pub async fn get_with_cancel<C>(
+        &self,
+        cancel: C,
+    ) -> Result<PooledConnection<'_, M>, RunError<M::Error>>
+    where
+        C: Future<Output = ()>,
+    {
+        self.inner.get_with(GetOptions::default(), cancel).await
+    }
+
+    /// Retrieves a connection from the pool on behalf of `tenant`.
+    ///
+    /// If no connection is immediately available, callers are queued per tenant: connections
+    /// that become available are handed out round-robin across the tenants that are waiting,
+    /// and in FIFO order within a tenant. This keeps a single busy tenant from starving the
+    /// others. Callers using `Pool::get` are treated as one more tenant.
+    ///
+    /// Tenants are identified by the hash of `tenant`, so distinct keys with colliding hashes
+    /// share a queue. Queueing by tenant costs an extra map lookup per waiter.
+    pub async fn get_fair<K>(
+        &self,
+        tenant: &K,
+    ) -> Result<PooledConnection<'_, M>, RunError<M::Error>>
+    where
+        K: Hash + ?Sized,
+    {
+        let mut hasher = DefaultHasher::new();
+        tenant.hash(&mut hasher);
+        let options = GetOptions {
+            tenant: Some(hasher.finish()),
+            ..GetOptions::default()
+        };
+        self.inner.get_with(options, pending()).await
+    }
+
+    /// Retrieves a connection from the subset of the pool registered as `label`.
+    ///
+    /// Only connections established for the subset are handed out, and callers wait for
+    /// one of those if none is idle. See `Builder::subset`.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if no subset was registered as `label`.
+    pub async fn get_labeled(
+        &self,
+        label: &'static str,
+    ) -> Result<PooledConnection<'_, M>, RunError<M::Error>> {
+        let options = GetOptions {
+            label: Some(label),
+            ..GetOptions::default()
+        };
+        self.inner.get_with(options, pending()).await
+    }
+
+    /// Retrieves a connection from the pool, even if `Builder::max_checkouts` connections are
+    /// already checked out.
+    ///
+    /// This lets latency-sensitive or otherwise important callers use the headroom that the
+    /// limit keeps clear of other traffic. It otherwise behaves like `Pool::get`.
+    pub async fn get_priority(&self) -> Result<PooledConnection<'_, M>, RunError<M::Error>> {
+        let options = GetOptions {
+            priority: true,
+            ..GetOptions::default()
+        };
+        self.inner.get_with(options, pending()).await
+    }
+
+    /// Retrieves a connection from the pool without validating it, even if the pool was built
+    /// with `test_on_check_out` enabled.
+    ///
+    /// This saves the latency of `ManageConnection::is_valid` for operations that are cheap to
+    /// retry. The caller accepts that the connection may be stale or broken.
+    pub async fn get_unvalidated(&self) -> Result<PooledConnection<'_, M>, RunError<M::Error>> {
+        let options = GetOptions {
+            skip_validation: true,
+            ..GetOptions::default()
+        };
+        self.inner.get_with(options, pending()).await
+    }
+
+    /// Retrieves a connection from the pool, waiting only on connections that are already
+    /// being established.
+    ///
+    /// If no connection is available right away but the pool is establishing some, as when
+    /// replenishment is underway, this gives up with `RunError::ConnectFailed` as soon as
+    /// the pool gives up on establishing a connection, rather than waiting out the connection
+    /// timeout. Connections that are established are handed to waiters in order as usual, so
+    /// if callers are waiting ahead of this one, it may keep waiting for a later one. If the
+    /// pool is not establishing any connections when the caller starts waiting, this waits
+    /// like `get`.
+    pub async fn get_await_pending(&self) -> Result<PooledConnection<'_, M>, RunError<M::Error>> {
+        let options = GetOptions {
+            await_pending: true,
+            ..GetOptions::default()
+        };
+        self.inner.get_with(options, pending()).await
+    }
+
+    /// Retrieves a connection from the pool, calling `on_queued` if the caller has to wait.
+    ///
+    /// If no connection is available right away, `on_queued` is called once, as the caller
+    /// starts waiting, with the number of callers that were already waiting. This is a
+    /// snapshot: it is not updated as the queue drains, and with tenants or
+    /// `Builder::deadline_scheduling` callers are not necessarily served in queue order.
+    pub async fn get_with_position<F>(
+        &self,
+        on_queued: F,
+    ) -> Result<PooledConnection<'_, M>, RunError<M::Error>>
+    where
+        F: FnOnce(usize) + Send + 'static,
+    {
+        let options = GetOptions {
+            on_queued: Some(Callback(Box::new(on_queued))),
+            ..GetOptions::default()
+        };
+        self.inner.get_with(options, pending()).await
+    }
+
+    /// Retrieves a connection from the pool, giving up at `deadline`.
+    ///
+    /// Waiting for a connection, and establishing one with `Builder::connect_inline`, is
+    /// limited to the time remaining until `deadline` or the connection timeout, whichever
+    /// is shorter. Returns `RunError::TimedOut` without queueing if `deadline` has passed.
+    pub async fn get_deadline(
+        &self,
+        deadline: Instant,
+    ) -> Result<PooledConnection<'_, M>, RunError<M::Error>> {
+        let options = GetOptions {
+            deadline: Some(deadline),
+            ..GetOptions::default()
+        };
+        self.inner.get_with(options, pending()).await
+    }
+
+    /// Retrieves a connection for an operation that must complete within `total`, including
+    /// the time taken to acquire the connection.
+    ///
+    /// Acquisition gives up once `total` has elapsed, as with `Pool::get_deadline`. The
+    /// connection then carries what is left of the budget, see
+    /// `PooledConnection::remaining_budget`, so that the code using it can derive its own
+    /// timeouts, such as a statement timeout, without threading the deadline through.
+    pub async fn get_with_budget(
+        &self,
+        total: Duration,
+    ) -> Result<PooledConnection<'_, M>, RunError<M::Error>> {
+        let deadline = Instant::now() + total;
+        let mut conn = self.get_deadline(deadline).await?;
+        conn.deadline = Some(deadline);
+        Ok(conn)
+    }
+
+    /// Retrieves a connection from the pool, reporting a slow acquisition after `soft` and
+    /// giving up after `hard`.
+    ///
+    /// If no connection was handed out within `soft`, the `Builder::on_slow_acquire`
+    /// callback is invoked and the caller keeps waiting. If none was handed out within
+    /// `hard`, it gives up with a timeout error. As with `Pool::get_deadline`, waiting is also
+    /// limited by the connection timeout.
+    pub async fn get_tiered(
+        &self,
+        soft: Duration,
+        hard: Duration,
+    ) -> Result<PooledConnection<'_, M>, RunError<M::Error>> {
+        self.inner.get_tiered(soft, hard).await
+    }
+
+    /// Retrieves a connection from the pool within `timeout`, or else runs `fallback`.
+    ///
+    /// Returns `Ok(Err(value))` with the value returned by `fallback` if no connection could
+    /// be had in time: when waiting timed out, or when `Builder::max_checkouts` connections
+    /// were already checked out. Other errors, such as those returned by the manager or
+    /// from a closed pool, are returned as they are. Every use of the fallback is counted in
+    /// `Statistics::fallbacks`, to keep track of how often the caller ran degraded.
+    pub async fn get_or_else<F, T>(
+        &self,
+        timeout: Duration,
+        fallback: F,
+    ) -> Result<Result<PooledConnection<'_, M>, T>, RunError<M::Error>>
+    where
+        F: FnOnce() -> T,
+    {
+        match self.get_deadline(Instant::now() + timeout).await {
+            Ok(conn) => Ok(Ok(conn)),
+            Err(
+                RunError::TimedOut | RunError::TimedOutWithState(_) | RunError::TooManyCheckouts,
+            ) => {
+                self.inner.record_fallback();
+                Ok(Err(fallback()))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Retrieves a connection for a caller in `role`, along with the role of the pool it
+    /// came from.
+    ///
+    /// `Role::ReadWrite` callers are only ever served by this pool, as by `Pool::get`.
+    /// `Role::ReadOnly` callers are served by this pool too, unless it is saturated (no idle
+    /// connection and no room for a new one), is not `PoolHealth::Healthy`, or fails to hand
+    /// out a connection, in which case they are served by the pool set with
+    /// `Builder::replica`. A reader gives up on this pool as soon as an attempt to open a
+    /// connection fails, rather than waiting out the connection timeout. If the replica
+    /// fails as well, its error is returned. Without a replica, both roles behave the same.
+    ///
+    /// Replicas usually apply the primary's writes with some delay, so a connection from
+    /// the replica may not yet see writes that were just made through this pool, even by
+    /// the same caller. Callers that must read their own writes should ask for
+    /// `Role::ReadWrite`, or check the returned role and retry on the primary.
+    pub async fn get_role(
+        &self,
+        role: Role,
+    ) -> Result<(PooledConnection<'_, M>, Role), RunError<M::Error>> {
+        let replica = match (role, &self.inner.statics().replica) {
+            (Role::ReadOnly, Some(replica)) => replica,
+            _ => return Ok((self.get().await?, Role::ReadWrite)),
+        };
+
+        let saturated = self.state().idle_connections == 0 && self.would_approve(1) == 0;
+        if !saturated && self.health() == PoolHealth::Healthy {
+            if let Ok(conn) = self.inner.get_until_connect_error().await {
+                return Ok((conn, Role::ReadWrite));
+            }
+        }
+        Ok((replica.get().await?, Role::ReadOnly))
+    }
+
+    /// Retrieves a connection from the pool, preferring an idle connection that matches
+    /// `preferred`.
+    ///
+    /// This is best-effort: if no idle connection matches, the connection that `Pool::get`
+    /// would have returned is handed out instead, in the usual first-in, first-out order.
+    pub async fn get_preferred<F>(
+        &self,
+        preferred: F,
+    ) -> Result<PooledConnection<'_, M>, RunError<M::Error>>
+    where
+        F: Fn(&ConnInfo<'_, M::Connection>) -> bool,
+    {
+        self.inner.get_preferred(preferred).await
+    }
+
     /// Retrieves an owned connection from the pool
     ///
     /// Using an owning `PooledConnection` makes it easier to leak the connection pool. Therefore, [`Pool::get`]
@@ -63,6 +585,95 @@ impl<M: ManageConnection> Pool<M> {
         self.inner.get_owned().await
     }
 
+    /// Retrieves a connection from the pool that may be used concurrently by other callers.
+    ///
+    /// Connections already checked out through this method are handed out again as long as
+    /// they have fewer users than `ManageConnection::max_concurrent` allows. Otherwise, a
+    /// connection is retrieved as with [`Pool::get_owned`].
+    ///
+    /// To limit the queries in flight on a connection used by a single caller instead, see
+    /// [`Pool::get_with_permit`].
+    pub async fn get_shared(&self) -> Result<SharedConnection<M>, RunError<M::Error>>
+    where
+        M::Connection: Sync,
+    {
+        self.inner.get_shared().await
+    }
+
+    /// Retrieves a connection from the pool along with a permit to run a query on it.
+    ///
+    /// Each connection has `ManageConnection::query_concurrency` permits, kept for as long as
+    /// the connection lives. The returned guard holds one of them; further permits for
+    /// pipelined queries can be taken with [`PermittedConnection::acquire`]. Permits taken
+    /// through an earlier checkout that are still held count against the connection, in which
+    /// case this waits for one to be released. If none is released within the connection
+    /// timeout, the connection is returned to the pool and [`RunError::TimedOut`] is returned.
+    ///
+    /// Dropping the guard releases its permit and returns the connection to the pool.
+    pub async fn get_with_permit(&self) -> Result<PermittedConnection<'_, M>, RunError<M::Error>> {
+        self.inner.get_with_permit().await
+    }
+
+    /// Runs `f` with a connection from the pool, retrying on a fresh connection if it fails.
+    ///
+    /// If `f` returns an error that `ManageConnection::is_transient` classifies as transient,
+    /// the connection it was given is discarded and `f` is run again with another connection,
+    /// up to `retries` times. Any other error, or the error from the final attempt, is returned;
+    /// connections are always discarded after a transient error.
+    pub async fn run_with_retry<T, F>(&self, retries: u32, f: F) -> Result<T, RunError<M::Error>>
+    where
+        F: for<'c> FnMut(&'c mut M::Connection) -> BoxFuture<'c, Result<T, M::Error>>,
+    {
+        self.inner.run_with_retry(retries, f).await
+    }
+
+    /// Runs `f` with a connection from the pool, discarding the connection if `f` fails.
+    ///
+    /// A connection on which an operation failed may be left in an unknown state, so it is
+    /// closed rather than returned to the pool, and the error is returned as
+    /// `RunError::User`. Errors checking out the connection are returned as usual.
+    pub async fn get_scoped<T, F>(&self, f: F) -> Result<T, RunError<M::Error>>
+    where
+        F: for<'c> FnOnce(&'c mut M::Connection) -> BoxFuture<'c, Result<T, M::Error>>,
+    {
+        self.inner.get_scoped(f).await
+    }
+
+    /// Runs `f` on each connection that is idle when this is called.
+    ///
+    /// Connections are taken out of the pool one at a time, so checkouts can proceed while
+    /// the sweep is underway; connections checked out in the meantime are skipped. Visiting a
+    /// connection does not count as checking it out. Connections for which `f` returns an
+    /// error are dropped, with the error forwarded to the error sink.
+    pub async fn for_each_idle<F>(&self, f: F)
+    where
+        F: for<'c> FnMut(&'c mut M::Connection) -> BoxFuture<'c, Result<(), M::Error>>,
+    {
+        self.inner.for_each_idle(f).await
+    }
+
+    /// Invalidates all connections established so far.
+    ///
+    /// Idle connections from before this call are closed instead of being handed out, and
+    /// connections that are currently checked out are closed when they are returned. They are
+    /// replaced as needed like broken connections. Connections established after this call
+    /// (including ones whose connection attempt was already underway) are not affected.
+    pub fn invalidate_generation(&self) {
+        self.inner.invalidate_generation()
+    }
+
+    /// Close the pool, waiting up to `timeout` for checked-out connections to be returned.
+    ///
+    /// Once this is called, idle connections are closed, callers waiting for a connection
+    /// fail, and further attempts to get a connection fail with `RunError::Closed`. The
+    /// background reaping tasks stop. Connections that are still checked out when the
+    /// timeout expires are closed when they are eventually returned.
+    ///
+    /// Returns true if all connections were closed before the timeout expired.
+    pub async fn drain_timeout(&self, timeout: Duration) -> bool {
+        self.inner.drain_timeout(timeout).await
+    }
+
     /// Get a new dedicated connection that will not be managed by the pool.
     /// An application may want a persistent connection (e.g. to do a
     /// postgres LISTEN) that will not be closed or repurposed by the pool.
@@ -83,18 +694,111 @@ pub struct Builder<M: ManageConnection> {
     pub(crate) min_idle: Option<u32>,
     /// Whether or not to test the connection on checkout.
     pub(crate) test_on_check_out: bool,
+    /// Whether or not to validate connections only after a user reported an error on them.
+    pub(crate) validate_on_error: bool,
+    /// Whether or not to check returned connections with `ManageConnection::has_broken`.
+    pub(crate) check_broken_on_return: bool,
+    /// Whether or not to run `ManageConnection::reset` on returned connections.
+    pub(crate) reset_on_return: bool,
+    /// Whether connections are returned to the pool from a spawned task.
+    pub(crate) async_return: bool,
+    /// The number of idle connections failing validation a checkout moves past, if bounded.
+    pub(crate) max_checkout_validation_retries: Option<u32>,
+    /// How often, and how long apart, a checkout looks for an idle connection again before
+    /// waiting for one.
+    pub(crate) spin_retries: (u32, Duration),
     /// The maximum lifetime, if any, that a connection is allowed.
     pub(crate) max_lifetime: Option<Duration>,
     /// The duration, if any, after which idle_connections in excess of `min_idle` are closed.
     pub(crate) idle_timeout: Option<Duration>,
+    /// The age below which connections are not closed for exceeding the idle timeout.
+    pub(crate) min_connection_age_before_reap: Duration,
+    /// The duration, if any, after which a checked out connection is considered abandoned.
+    pub(crate) max_checkout_duration: Option<Duration>,
+    /// The maximum number of connections checked out at once through `Pool::get`, if any.
+    pub(crate) max_checkouts: Option<u32>,
+    /// The `ManageConnection::resource_usage` above which returned connections are closed.
+    pub(crate) max_connection_resource: Option<u64>,
     /// The duration to wait to start a connection before giving up.
     pub(crate) connection_timeout: Duration,
+    /// The minimum delay between attempts to establish a connection.
+    pub(crate) min_connect_backoff: Duration,
+    /// How many times a connection a caller is waiting on is retried, if limited.
+    pub(crate) demand_connect_retries: Option<u32>,
+    /// The minimum delay between attempts to establish a connection for `min_idle`, if
+    /// different from `min_connect_backoff`.
+    pub(crate) maintenance_connect_backoff: Option<Duration>,
+    /// The number of connections established in the background at once, if bounded.
+    pub(crate) max_replenish_inflight: Option<u32>,
+    /// The number of connection attempts allowed across the pool per period, if bounded.
+    pub(crate) global_connect_budget: Option<(u32, Duration)>,
     /// The error sink.
     pub(crate) error_sink: Box<dyn ErrorSink<M::Error>>,
+    /// The admission controller.
+    pub(crate) admission_controller: Box<dyn AdmissionController>,
     /// The time interval used to wake up and reap connections.
     pub(crate) reaper_rate: Duration,
+    /// Whether the reaper runs on its own thread rather than as a task.
+    pub(crate) reaper_on_dedicated_thread: bool,
+    /// The bounds within which the connection cap follows contention, if enabled.
+    pub(crate) autoscale: Option<(u32, u32)>,
+    /// The duration without checkouts after which all idle connections are closed.
+    pub(crate) pool_idle_shutdown: Option<Duration>,
+    /// The interval at which idle connections are validated in the background, if enabled.
+    pub(crate) background_validation: Option<Duration>,
+    /// The interval at which the pool is sampled for `Pool::history`, and how many samples
+    /// are kept, if enabled.
+    pub(crate) metrics_history: Option<(Duration, usize)>,
     /// User-supplied trait object responsible for initializing connections
     pub(crate) connection_customizer: Option<Box<dyn CustomizeConnection<M::Connection, M::Error>>>,
+    /// Whether to always replace connections that are dropped for being broken.
+    pub(crate) replace_broken_eagerly: bool,
+    /// Whether to replace connections dropped for being broken while callers are waiting.
+    pub(crate) replace_broken_for_waiters: bool,
+    /// Whether waiters are failed once every connection attempt made on their behalf failed.
+    pub(crate) fail_waiters_on_connect_exhaustion: bool,
+    /// Whether returned connections are reused before connections that have been idle longer.
+    pub(crate) return_to_front: bool,
+    /// The number of idle connections kept ready, beyond which they are kept cold, if tiered.
+    pub(crate) hot_idle: Option<u32>,
+    /// The number of idle connections beyond which returned connections are closed, if any.
+    pub(crate) max_idle: Option<u32>,
+    /// How idle connections are picked for a checkout.
+    pub(crate) selection_strategy: SelectionStrategy,
+    /// Whether checkouts skip replenishing the pool when `min_idle` is zero.
+    pub(crate) lazy_only: bool,
+    /// Whether checkouts establish new connections on the calling task.
+    pub(crate) connect_inline: bool,
+    /// Whether waiters are served earliest deadline first.
+    pub(crate) deadline_scheduling: bool,
+    /// The number of waiters to reserve room for up front.
+    pub(crate) waiter_capacity_hint: usize,
+    /// The number of independently locked shards the connections are split across.
+    pub(crate) shards: u32,
+    /// The label, `min_idle` and `max_size` of every labeled subset of connections.
+    pub(crate) subsets: Vec<(&'static str, u32, u32)>,
+    /// Called whenever a returned connection is found to be broken.
+    pub(crate) on_broken: Option<Callback<dyn Fn() + Send + Sync>>,
+    /// Callback invoked with the checkout duration whenever a connection is returned.
+    pub(crate) on_release: Option<Callback<dyn Fn(Duration) + Send + Sync>>,
+    /// Called when `Pool::get_tiered` waits past its soft timeout
+    pub(crate) on_slow_acquire: Option<Callback<dyn Fn(Duration) + Send + Sync>>,
+    /// Called with the outcome of every attempt to hand a connection that became available
+    /// to a waiter
+    pub(crate) on_waiter_delivery: Option<Callback<dyn Fn(WaiterDeliveryOutcome) + Send + Sync>>,
+    /// Callback invoked with the connections closed after each run of the reaper.
+    pub(crate) on_reap: Option<Callback<dyn Fn(ReapSummary) + Send + Sync>>,
+    /// Callback invoked with the idle count and `min_idle` when reaping leaves too few idle.
+    pub(crate) on_min_idle_underflow: Option<Callback<dyn Fn(u32, u32) + Send + Sync>>,
+    /// Callback invoked once the pool has established its first connection.
+    pub(crate) on_first_connection: Option<Callback<dyn Fn() + Send + Sync>>,
+    /// Consulted before every attempt to establish a connection in the background.
+    #[cfg(feature = "test-util")]
+    pub(crate) connect_hook: Option<Callback<ConnectHook<M::Error>>>,
+    /// Rewrites the error of a connection that could not be established, if set.
+    pub(crate) map_connect_error: Option<Callback<MapConnectError<M::Error>>>,
+    /// The pool `Pool::get_role` falls back to for read-only connections, if any.
+    pub(crate) replica: Option<Pool<M>>,
     _p: PhantomData<M>,
 }
 
@@ -104,12 +808,57 @@ impl<M: ManageConnection> Default for Builder<M> {
             max_size: 10,
             min_idle: None,
             test_on_check_out: true,
+            check_broken_on_return: true,
+            reset_on_return: false,
+            async_return: false,
+            validate_on_error: false,
+            max_checkout_validation_retries: None,
+            spin_retries: (0, Duration::from_secs(0)),
             max_lifetime: Some(Duration::from_secs(30 * 60)),
             idle_timeout: Some(Duration::from_secs(10 * 60)),
+            min_connection_age_before_reap: Duration::from_secs(0),
+            max_checkout_duration: None,
+            max_checkouts: None,
+            max_connection_resource: None,
             connection_timeout: Duration::from_secs(30),
+            min_connect_backoff: Duration::from_millis(200),
+            demand_connect_retries: None,
+            maintenance_connect_backoff: None,
+            max_replenish_inflight: None,
+            global_connect_budget: None,
             error_sink: Box::new(NopErrorSink),
+            admission_controller: Box::new(AdmitAll),
             reaper_rate: Duration::from_secs(30),
+            reaper_on_dedicated_thread: false,
+            autoscale: None,
+            pool_idle_shutdown: None,
+            background_validation: None,
+            metrics_history: None,
             connection_customizer: None,
+            replace_broken_eagerly: false,
+            replace_broken_for_waiters: false,
+            fail_waiters_on_connect_exhaustion: false,
+            return_to_front: false,
+            hot_idle: None,
+            max_idle: None,
+            selection_strategy: SelectionStrategy::default(),
+            lazy_only: false,
+            connect_inline: false,
+            deadline_scheduling: false,
+            waiter_capacity_hint: 0,
+            shards: 1,
+            subsets: Vec::new(),
+            on_broken: None,
+            on_release: None,
+            on_slow_acquire: None,
+            on_waiter_delivery: None,
+            on_reap: None,
+            on_min_idle_underflow: None,
+            on_first_connection: None,
+            #[cfg(feature = "test-util")]
+            connect_hook: None,
+            map_connect_error: None,
+            replica: None,
             _p: PhantomData,
         }
     }
@@ -143,6 +892,10 @@ impl<M: ManageConnection> Builder<M> {
     /// If set, the pool will try to maintain at least this many idle
     /// connections at all times, while respecting the value of `max_size`.
     ///
+    /// The reaper will not close idle connections for exceeding `idle_timeout` if that would
+    /// leave fewer than `min_idle` idle connections. Connections past their `max_lifetime` are
+    /// always closed, and replaced as needed to restore `min_idle`.
+    ///
     /// Defaults to None.
     #[must_use]
     pub fn min_idle(mut self, min_idle: Option<u32>) -> Self {
@@ -153,6 +906,9 @@ impl<M: ManageConnection> Builder<M> {
     /// If true, the health of a connection will be verified through a call to
     /// `ManageConnection::is_valid` before it is provided to a pool user.
     ///
+    /// This applies both to idle connections and to connections handed to a waiting caller
+    /// as they are returned; newly established connections are not validated.
+    ///
     /// Defaults to true.
     #[must_use]
     pub fn test_on_check_out(mut self, test_on_check_out: bool) -> Self {
@@ -160,6 +916,98 @@ impl<M: ManageConnection> Builder<M> {
         self
     }
 
+    /// If true, connections are handed out without being validated, and are instead checked
+    /// through `ManageConnection::is_valid` when they are returned after an error was reported
+    /// with `PooledConnection::report_error`. Connections that fail validation are dropped.
+    ///
+    /// When enabled, this takes precedence over `test_on_check_out`.
+    ///
+    /// Defaults to false.
+    #[must_use]
+    pub fn validate_on_error(mut self, validate_on_error: bool) -> Self {
+        self.validate_on_error = validate_on_error;
+        self
+    }
+
+    /// If false, connections returned to the pool are queued again without a call to
+    /// `ManageConnection::has_broken`.
+    ///
+    /// This saves the cost of the check on every return for connection types where it is a
+    /// no-op or expensive, leaving broken connections to be caught by checkout-time or
+    /// background validation.
+    ///
+    /// Defaults to true.
+    #[must_use]
+    pub fn check_broken_on_return(mut self, check_broken_on_return: bool) -> Self {
+        self.check_broken_on_return = check_broken_on_return;
+        self
+    }
+
+    /// If true, connections returned to the pool are passed to `ManageConnection::reset`
+    /// before they become idle again, and dropped if that fails.
+    ///
+    /// Since connections are returned synchronously as the `PooledConnection` is dropped, the
+    /// reset runs on a spawned task, and the connection is only available to other callers
+    /// once it completes. With `validate_on_error`, connections for which an error was
+    /// reported are validated after being reset.
+    ///
+    /// Defaults to false.
+    #[must_use]
+    pub fn reset_on_return(mut self, reset_on_return: bool) -> Self {
+        self.reset_on_return = reset_on_return;
+        self
+    }
+
+    /// If true, dropping a `PooledConnection` hands the connection to a spawned task that
+    /// returns it to the pool, instead of returning it on the dropping task.
+    ///
+    /// `Drop` cannot await, so the work done as a connection is returned, like calling
+    /// `ManageConnection::has_broken` and the `on_release` and `on_broken` callbacks, runs
+    /// synchronously wherever the guard is dropped. This moves it off that task, for
+    /// connection types where it is slow, at the cost of a spawn per return and of the
+    /// connection only becoming available to others once the task runs. Cleanup that needs
+    /// to await belongs in `ManageConnection::reset`, see `reset_on_return`.
+    ///
+    /// If there is no runtime to spawn on, the connection is returned synchronously as usual.
+    /// The same goes for a task the runtime drops without running it while shutting down.
+    ///
+    /// Defaults to false.
+    #[must_use]
+    pub fn async_return(mut self, async_return: bool) -> Self {
+        self.async_return = async_return;
+        self
+    }
+
+    /// Sets how many idle connections that fail validation a checkout will discard before
+    /// waiting for a new connection instead.
+    ///
+    /// Without a bound, a checkout keeps trying idle connections until one is valid, which
+    /// can close every idle connection in a single call while the backend is down.
+    ///
+    /// Defaults to None.
+    #[must_use]
+    pub fn max_checkout_validation_retries(mut self, max_retries: Option<u32>) -> Self {
+        self.max_checkout_validation_retries = max_retries;
+        self
+    }
+
+    /// Sets how many times a checkout that finds no idle connection while the pool is at
+    /// `max_size` looks again, `delay` apart, before it starts waiting for a connection.
+    ///
+    /// When connections are only held briefly, one is likely to be returned any moment, and
+    /// picking it up from the idle queue is cheaper than joining the queue of waiters and
+    /// having it handed over. The spinning costs CPU time, however, and adds up to
+    /// `count * delay` of latency whenever no connection is returned after all, so this is
+    /// best kept to a few retries with a short delay. With a zero `delay`, the checkout
+    /// yields to other tasks between retries instead of sleeping.
+    ///
+    /// Defaults to 0, waiting right away.
+    #[must_use]
+    pub fn spin_retries(mut self, count: u32, delay: Duration) -> Self {
+        self.spin_retries = (count, delay);
+        self
+    }
+
     /// Sets the maximum lifetime of connections in the pool.
     ///
     /// If set, connections will be closed at the next reaping after surviving
@@ -189,6 +1037,10 @@ impl<M: ManageConnection> Builder<M> {
     /// If set, idle connections in excess of `min_idle` will be closed at the
     /// next reaping after remaining idle past this duration.
     ///
+    /// A connection for which `ManageConnection::idle_timeout` returned a value is reaped
+    /// according to that value instead. Connections are only reaped while the reaper runs,
+    /// which requires either this or `max_lifetime` to be set.
+    ///
     /// Defaults to 10 minutes.
     ///
     /// # Panics
@@ -205,6 +1057,95 @@ impl<M: ManageConnection> Builder<M> {
         self
     }
 
+    /// Sets the age a connection must reach before it can be closed for exceeding the idle
+    /// timeout.
+    ///
+    /// This avoids churn with a short idle timeout, where a connection opened for `min_idle`
+    /// right before the reaper runs could otherwise be closed almost as soon as it was
+    /// established. It does not affect `max_lifetime`.
+    ///
+    /// Defaults to 0.
+    #[must_use]
+    pub fn min_connection_age_before_reap(mut self, min_age: Duration) -> Self {
+        self.min_connection_age_before_reap = min_age;
+        self
+    }
+
+    /// Sets how long a connection may stay checked out before the pool reclaims it.
+    ///
+    /// If set, connections checked out for longer than this are considered abandoned at the
+    /// next reaping: the pool stops counting them towards `max_size` and opens replacements,
+    /// so a stuck consumer cannot permanently shrink its capacity. A reclaimed connection
+    /// must be considered invalid by its consumer, and is closed rather than returned to the
+    /// pool once its guard is dropped. Until then, more than `max_size` connections may be
+    /// open. Connections checked out through `Pool::get_shared` count as checked out for as
+    /// long as any caller holds them.
+    ///
+    /// Defaults to None.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `max_checkout_duration` is 0.
+    #[must_use]
+    pub fn max_checkout_duration(mut self, max_checkout_duration: Option<Duration>) -> Self {
+        assert_ne!(
+            max_checkout_duration,
+            Some(Duration::from_secs(0)),
+            "max_checkout_duration must be greater than zero!"
+        );
+        self.max_checkout_duration = max_checkout_duration;
+        self
+    }
+
+    /// Sets the maximum number of connections that may be checked out at once.
+    ///
+    /// Without a limit, as many connections as `max_size` may be checked out, after which
+    /// callers wait for one to be returned. Setting a lower limit makes further checkouts
+    /// fail right away with `RunError::TooManyCheckouts` instead, which applies backpressure
+    /// while keeping the remaining connections as headroom for `Pool::get_priority`. Only
+    /// exclusive checkouts count towards the limit, not connections shared through
+    /// `Pool::get_shared`, for which `max_size` bounds the number of connections rather than
+    /// of callers.
+    ///
+    /// Defaults to None.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `max_checkouts` is 0.
+    #[must_use]
+    pub fn max_checkouts(mut self, max_checkouts: Option<u32>) -> Self {
+        assert_ne!(
+            max_checkouts,
+            Some(0),
+            "max_checkouts must be greater than zero!"
+        );
+        self.max_checkouts = max_checkouts;
+        self
+    }
+
+    /// Sets the largest `ManageConnection::resource_usage` a connection may report when it
+    /// is returned to the pool.
+    ///
+    /// Connections that accumulate state on the server, such as temporary tables or
+    /// session variables, are closed and replaced once they report more than this, which
+    /// bounds their growth independently of `max_lifetime`.
+    ///
+    /// Defaults to None.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `max_connection_resource` is 0.
+    #[must_use]
+    pub fn max_connection_resource(mut self, max_connection_resource: Option<u64>) -> Self {
+        assert_ne!(
+            max_connection_resource,
+            Some(0),
+            "max_connection_resource must be greater than zero!"
+        );
+        self.max_connection_resource = max_connection_resource;
+        self
+    }
+
     /// Sets the connection timeout used by the pool.
     ///
     /// Futures returned by `Pool::get` will wait this long before giving up and
@@ -225,31 +1166,652 @@ impl<M: ManageConnection> Builder<M> {
         self
     }
 
-    /// Set the sink for errors that are not associated with any particular operation
-    /// on the pool. This can be used to log and monitor failures.
+    /// Sets the floor for the backoff between failed attempts to establish a connection.
+    ///
+    /// The backoff starts at twice this value and doubles on every subsequent failure, up to
+    /// half of `connection_timeout`.
+    ///
+    /// Defaults to 200 milliseconds.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `min_connect_backoff` is 0.
+    #[must_use]
+    pub fn min_connect_backoff(mut self, min_connect_backoff: Duration) -> Self {
+        assert!(
+            min_connect_backoff > Duration::from_secs(0),
+            "min_connect_backoff must be non-zero"
+        );
+        self.min_connect_backoff = min_connect_backoff;
+        self
+    }
+
+    /// Sets how many times a demand-driven connection attempt is retried before failing.
+    ///
+    /// The pool establishes connections in one of two modes. A connection is demand-driven
+    /// when a caller is waiting for it, either because the pool had no idle connection to
+    /// hand out or because it was built with `connect_inline`. Otherwise it is maintenance,
+    /// opened in the background to keep `min_idle` idle connections or to replace a broken
+    /// one. Both modes retry failed attempts with backoff until `connection_timeout` passes.
+    ///
+    /// Limiting the retries of demand-driven attempts fails them fast instead: an inline
+    /// attempt returns the error to its caller, and together with
+    /// `fail_waiters_on_connect_exhaustion` waiting callers are failed without waiting out
+    /// their timeout. Maintenance attempts are not affected. `Some(0)` disables retries.
+    ///
+    /// Defaults to None, retrying until the connection timeout.
+    #[must_use]
+    pub fn demand_connect_retries(mut self, demand_connect_retries: Option<u32>) -> Self {
+        self.demand_connect_retries = demand_connect_retries;
+        self
+    }
+
+    /// Sets the floor for the backoff between failed maintenance connection attempts.
+    ///
+    /// Maintenance attempts, described at `demand_connect_retries`, have nobody waiting on
+    /// them, so they can retry less aggressively than demand-driven ones to go easy on a
+    /// struggling server. The backoff grows the same way as for `min_connect_backoff`.
+    ///
+    /// Defaults to `min_connect_backoff`.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `maintenance_connect_backoff` is 0.
+    #[must_use]
+    pub fn maintenance_connect_backoff(mut self, maintenance_connect_backoff: Duration) -> Self {
+        assert!(
+            maintenance_connect_backoff > Duration::from_secs(0),
+            "maintenance_connect_backoff must be non-zero"
+        );
+        self.maintenance_connect_backoff = Some(maintenance_connect_backoff);
+        self
+    }
+
+    /// Sets how many connections the pool establishes in the background at once.
+    ///
+    /// Connections approved beyond this number, to replace closed connections or maintain
+    /// `min_idle`, wait for an attempt in progress to finish before they start connecting;
+    /// their connection timeout starts once they do. This bounds the work the pool does
+    /// after, for instance, a burst of broken connections. Connections established inline
+    /// for a caller with `connect_inline` are not counted. `Pool::replenishing` returns the
+    /// number of attempts in progress.
+    ///
+    /// Defaults to None.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `max_replenish_inflight` is 0.
+    #[must_use]
+    pub fn max_replenish_inflight(mut self, max_replenish_inflight: Option<u32>) -> Self {
+        assert_ne!(
+            max_replenish_inflight,
+            Some(0),
+            "max_replenish_inflight must be greater than zero!"
+        );
+        self.max_replenish_inflight = max_replenish_inflight;
+        self
+    }
+
+    /// Limit the number of connection attempts made across the whole pool to `attempts` in
+    /// every period of `per`.
+    ///
+    /// During a prolonged outage, every waiter and every connection being replenished keeps
+    /// retrying, which together can put a lot of pressure on the backend. This bounds that
+    /// pressure in aggregate, on top of the retry limits of each connection. Attempts are
+    /// taken from a bucket that holds up to `attempts` and is refilled evenly over `per`, and
+    /// while it is empty connections wait for the next attempt to become available, giving
+    /// up if that would take them past the connection timeout. Retries count against the
+    /// budget like first attempts.
+    ///
+    /// Defaults to no limit.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `attempts` is 0 or `per` is zero.
+    #[must_use]
+    pub fn global_connect_budget(mut self, attempts: u32, per: Duration) -> Self {
+        assert!(attempts > 0, "attempts must be greater than zero!");
+        assert!(
+            per > Duration::from_secs(0),
+            "per must be greater than zero!"
+        );
+        self.global_connect_budget = Some((attempts, per));
+        self
+    }
+
+    /// Set the sink for errors that are not associated with any particular operation
+    /// on the pool. This can be used to log and monitor failures.
+    ///
+    /// Defaults to `NopErrorSink`.
+    #[must_use]
+    pub fn error_sink(mut self, error_sink: Box<dyn ErrorSink<M::Error>>) -> Self {
+        self.error_sink = error_sink;
+        self
+    }
+
+    /// Set a function to rewrite the error of a connection that could not be established,
+    /// for instance to add the endpoint and the number of attempts to a low-level error.
+    ///
+    /// It is applied once the pool gives up on the connection, before the error is passed
+    /// to the error sink or, with `Builder::connect_inline`, returned to the caller, so
+    /// errors are enriched in one place rather than in every manager.
+    #[must_use]
+    pub fn map_connect_error<F>(mut self, map_connect_error: F) -> Self
+    where
+        F: Fn(M::Error, ConnectAttemptInfo) -> M::Error + Send + Sync + 'static,
+    {
+        self.map_connect_error = Some(Callback(Box::new(map_connect_error)));
+        self
+    }
+
+    /// Sets a pool of connections to a read replica, which `Pool::get_role` serves
+    /// `Role::ReadOnly` callers from when this pool cannot.
+    ///
+    /// The replica pool is configured and managed on its own, typically with a manager that
+    /// connects to the replica instead of the primary.
+    ///
+    /// Defaults to None.
+    #[must_use]
+    pub fn replica(mut self, replica: Pool<M>) -> Self {
+        self.replica = Some(replica);
+        self
+    }
+
+    /// Set the controller consulted before every attempt to open a connection.
+    ///
+    /// This gives a central place to stop the pool from hammering a backend during an
+    /// outage, for instance by admitting fewer attempts as the failure rate rises. Attempts
+    /// that are denied, or delayed past the connection timeout, are given up like attempts
+    /// that timed out, or with the error of the previous attempt if there was one. This
+    /// applies to connections opened in the background as well as for waiting callers.
+    ///
+    /// Defaults to `AdmitAll`.
+    #[must_use]
+    pub fn admission_controller(
+        mut self,
+        admission_controller: Box<dyn AdmissionController>,
+    ) -> Self {
+        self.admission_controller = admission_controller;
+        self
+    }
+
+    /// Let the pool adjust its connection cap between `min_size` and `max_size` based on
+    /// contention, instead of always allowing up to `max_size` connections.
+    ///
+    /// The cap starts at `min_size` and is reconsidered every time the reaper runs. It is
+    /// raised by one after two consecutive reaper runs during which callers had to wait for a
+    /// connection, and lowered by one after ten consecutive runs during which nobody waited
+    /// and connections sat idle, closing an idle connection if the pool is over the new cap.
+    /// Requiring a streak in either direction, with shrinking deliberately slower than
+    /// growing, keeps the cap from oscillating under bursty load.
+    ///
+    /// This overrides `max_size`.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `min_size` is 0 or larger than `max_size`.
+    #[must_use]
+    pub fn autoscale(mut self, min_size: u32, max_size: u32) -> Self {
+        assert!(min_size > 0, "min_size must be greater than zero!");
+        assert!(
+            min_size <= max_size,
+            "min_size must be no larger than max_size"
+        );
+        self.max_size = max_size;
+        self.autoscale = Some((min_size, max_size));
+        self
+    }
+
+    /// Used by tests
+    #[allow(dead_code)]
+    #[must_use]
+    pub fn reaper_rate(mut self, reaper_rate: Duration) -> Self {
+        self.reaper_rate = reaper_rate;
+        self
+    }
+
+    /// If true, the reaper that enforces `max_lifetime` and `idle_timeout` runs on a thread of
+    /// its own instead of as a task on the runtime.
+    ///
+    /// This keeps the reaper's periodic work, which holds the pool's lock while it scans the
+    /// idle connections, off the runtime's worker threads, at the cost of one thread per pool.
+    /// Replacement connections are still established on the runtime the pool was built on.
+    /// The thread exits as soon as the pool is closed or dropped.
+    ///
+    /// Defaults to false.
+    #[must_use]
+    pub fn reaper_on_dedicated_thread(mut self, dedicated: bool) -> Self {
+        self.reaper_on_dedicated_thread = dedicated;
+        self
+    }
+
+    /// Sets the duration after which the pool closes all of its idle connections if no
+    /// connection has been requested in the meantime.
+    ///
+    /// Once this happens the pool stops replenishing connections for `min_idle` and pauses
+    /// reaping, until the next call to `Pool::get` (or one of its variants), which opens new
+    /// connections as needed. Connections that are checked out at the time are closed by the
+    /// next check if they are returned before the pool is used again. This is useful to avoid
+    /// paying for idle connections to serverless databases.
+    ///
+    /// Defaults to None.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `pool_idle_shutdown` is 0.
+    #[must_use]
+    pub fn pool_idle_shutdown(mut self, pool_idle_shutdown: Option<Duration>) -> Self {
+        assert_ne!(
+            pool_idle_shutdown,
+            Some(Duration::from_secs(0)),
+            "pool_idle_shutdown must be greater than zero!"
+        );
+        self.pool_idle_shutdown = pool_idle_shutdown;
+        self
+    }
+
+    /// Sets the interval at which a background task validates idle connections.
+    ///
+    /// On every tick, the idle connection that has been idle the longest is taken out of the
+    /// pool, validated through `ManageConnection::is_valid` and returned, so that over time
+    /// every idle connection is visited in turn. Connections that fail validation are closed
+    /// and replaced as needed. Only one connection is taken out at a time, and none while
+    /// callers are waiting for a connection. Validation is not interrupted for callers that
+    /// start waiting while it is in progress: the connection is unavailable to them until
+    /// it is returned, at which point it is handed to a waiting caller before it is queued
+    /// again. Validation that takes longer than `connection_timeout` counts as failed, so a
+    /// checkout is held up by at most one connection being validated, for at most that
+    /// long. The validation task does not keep the pool alive while a connection is being
+    /// validated. Connections in labeled subsets are not validated.
+    ///
+    /// Defaults to None.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `background_validation` is 0.
+    #[must_use]
+    pub fn background_validation(mut self, background_validation: Option<Duration>) -> Self {
+        assert_ne!(
+            background_validation,
+            Some(Duration::from_secs(0)),
+            "background_validation must be greater than zero!"
+        );
+        self.background_validation = background_validation;
+        self
+    }
+
+    /// Records the pool's `State` and `Statistics` every `interval`, keeping the last
+    /// `capacity` samples for `Pool::history`.
+    ///
+    /// This gives a view of the recent past of the pool, for instance for an admin page,
+    /// without an external metrics system. Samples are taken by a background task, and the
+    /// oldest one is dropped once `capacity` is reached.
+    ///
+    /// Defaults to no sampling.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `interval` is zero or `capacity` is 0.
+    #[must_use]
+    pub fn metrics_history(mut self, interval: Duration, capacity: usize) -> Self {
+        assert!(
+            interval > Duration::from_secs(0),
+            "interval must be greater than zero!"
+        );
+        assert!(capacity > 0, "capacity must be greater than zero!");
+        self.metrics_history = Some((interval, capacity));
+        self
+    }
+
+    /// Set the connection customizer to customize newly checked out connections
+    #[must_use]
+    pub fn connection_customizer(
+        mut self,
+        connection_customizer: Box<dyn CustomizeConnection<M::Connection, M::Error>>,
+    ) -> Self {
+        self.connection_customizer = Some(connection_customizer);
+        self
+    }
+
+    /// If true, a connection that is dropped for being broken or failing validation is
+    /// immediately replaced, even if the pool holds at least `min_idle` idle connections.
+    ///
+    /// Connections closed by the reaper are still only replaced as needed for `min_idle`.
+    ///
+    /// Defaults to false.
+    #[must_use]
+    pub fn replace_broken_eagerly(mut self, replace_broken_eagerly: bool) -> Self {
+        self.replace_broken_eagerly = replace_broken_eagerly;
+        self
+    }
+
+    /// If true, a connection that is dropped for being broken or failing validation is
+    /// immediately replaced if callers are waiting for a connection.
+    ///
+    /// Otherwise, a caller waiting while the pool is at `max_size` has to wait for another
+    /// connection to be returned, unless the pool holds fewer than `min_idle` idle
+    /// connections. The replacement takes the slot of the dropped connection, so the pool
+    /// still never holds more than `max_size` connections. This is a narrower version of
+    /// `replace_broken_eagerly`, which replaces broken connections whether or not anyone is
+    /// waiting.
+    ///
+    /// Defaults to false.
+    #[must_use]
+    pub fn replace_broken_for_waiters(mut self, replace_broken_for_waiters: bool) -> Self {
+        self.replace_broken_for_waiters = replace_broken_for_waiters;
+        self
+    }
+
+    /// If true, callers waiting for a connection fail with `RunError::ConnectFailed` as soon
+    /// as the last pending connection attempt fails while the pool has no idle connections,
+    /// rather than waiting out their own timeout.
+    ///
+    /// Defaults to false.
+    #[must_use]
+    pub fn fail_waiters_on_connect_exhaustion(mut self, fail: bool) -> Self {
+        self.fail_waiters_on_connect_exhaustion = fail;
+        self
+    }
+
+    /// If true, a connection returned to the pool while no callers are waiting goes to the
+    /// front of the idle queue, so the most recently used connection is handed out next.
+    ///
+    /// Otherwise, idle connections are handed out in the order they were returned.
+    ///
+    /// Defaults to false.
+    #[must_use]
+    pub fn return_to_front(mut self, return_to_front: bool) -> Self {
+        self.return_to_front = return_to_front;
+        self
+    }
+
+    /// Keep at most `hot_idle` idle connections ready to be handed out, and the rest in a
+    /// cold tier that is validated before use.
+    ///
+    /// Connections returned to the pool join the hot tier, and once it holds more than
+    /// `hot_idle` connections its longest idle one is demoted to the cold tier. Checkouts are
+    /// served from the hot tier, and only once it is empty is a cold connection promoted,
+    /// which is always validated first, like with `test_on_check_out`. Connections in the
+    /// hot tier follow `test_on_check_out` as usual.
+    ///
+    /// The tiers are reaped differently: both are subject to `max_lifetime`, but only cold
+    /// connections are closed for exceeding `idle_timeout`, so the hot tier stays ready no
+    /// matter how long it sits idle. `min_idle` counts connections in both tiers. Connections
+    /// of subsets registered with `subset` are not tiered.
+    ///
+    /// Defaults to None, keeping all idle connections in a single queue.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `hot_idle` is 0.
+    #[must_use]
+    pub fn hot_idle(mut self, hot_idle: Option<u32>) -> Self {
+        assert_ne!(hot_idle, Some(0), "hot_idle must be greater than zero!");
+        self.hot_idle = hot_idle;
+        self
+    }
+
+    /// Sets the maximum number of idle connections the pool keeps.
+    ///
+    /// Whenever a returned connection would leave more idle connections than this, the one
+    /// that has been idle the longest is closed, so a pool that opened many connections in a
+    /// burst sheds them as soon as demand drops rather than once they reach `idle_timeout`.
+    /// The reaper also closes any excess at every run. At least `min_idle` connections are
+    /// always kept, even if it is higher. Connections of subsets registered with `subset` do
+    /// not count towards the limit.
+    ///
+    /// Defaults to None.
+    #[must_use]
+    pub fn max_idle(mut self, max_idle: Option<u32>) -> Self {
+        self.max_idle = max_idle;
+        self
+    }
+
+    /// Sets how the pool picks among idle connections for a checkout.
+    ///
+    /// Defaults to `SelectionStrategy::Queue`.
+    #[must_use]
+    pub fn selection_strategy(mut self, selection_strategy: SelectionStrategy) -> Self {
+        self.selection_strategy = selection_strategy;
+        self
+    }
+
+    /// If true and `min_idle` is zero, checking out an idle connection does not consider
+    /// replenishing the pool at all, keeping the checkout path as short as possible.
+    ///
+    /// Has no effect if `min_idle` is above zero.
+    ///
+    /// Defaults to false.
+    #[must_use]
+    pub fn lazy_only(mut self, lazy_only: bool) -> Self {
+        self.lazy_only = lazy_only;
+        self
+    }
+
+    /// If true, a checkout that finds no idle connection while the pool is below `max_size`
+    /// establishes a new connection itself and returns it, instead of queueing up to receive
+    /// one from a background task.
+    ///
+    /// This saves a task hop on cold acquisitions at the cost of fairness: an inline
+    /// checkout does not queue behind callers that are already waiting, and the connection
+    /// it creates is never offered to them. If the connection cannot be established within
+    /// the connection timeout, the last error from `connect` is returned to the caller
+    /// rather than sent to the error sink.
+    ///
+    /// Defaults to false.
+    #[must_use]
+    pub fn connect_inline(mut self, connect_inline: bool) -> Self {
+        self.connect_inline = connect_inline;
+        self
+    }
+
+    /// If true, callers waiting for a connection are served in order of their deadline,
+    /// earliest first, rather than in the order they started waiting.
+    ///
+    /// A caller's deadline is the one given to `Pool::get_deadline`, or else the time it
+    /// started waiting plus the connection timeout, so callers without an explicit deadline
+    /// are still served in FIFO order among themselves. Tenants given to `Pool::get_fair`
+    /// are not taken into account. Waiters are kept in a binary heap, which makes queueing
+    /// and serving a waiter logarithmic rather than constant in the number of waiters.
+    ///
+    /// Defaults to false.
+    #[must_use]
+    pub fn deadline_scheduling(mut self, deadline_scheduling: bool) -> Self {
+        self.deadline_scheduling = deadline_scheduling;
+        self
+    }
+
+    /// Reserves room for `capacity` callers waiting for a connection when the pool is built.
+    ///
+    /// The queue of waiters otherwise grows as needed. Setting this to the expected peak
+    /// number of waiters avoids allocating while bursts of callers queue up.
+    ///
+    /// Defaults to 0.
+    #[must_use]
+    pub fn waiter_capacity_hint(mut self, capacity: usize) -> Self {
+        self.waiter_capacity_hint = capacity;
+        self
+    }
+
+    /// Splits the pool into `shards` parts, each with its own lock, idle connections and
+    /// waiters, to reduce contention on the lock under very high throughput.
+    ///
+    /// `max_size` and `min_idle`, as well as later changes to them through
+    /// `Pool::apply_config` and `Pool::boost_min_idle`, are apportioned across the shards,
+    /// each of which opens and reaps its own connections. Checkouts are spread across the
+    /// shards in turn; a caller whose shard has no idle connection takes one from another
+    /// shard if it can, and otherwise waits on its own shard. `Pool::state`,
+    /// `Pool::statistics` and the other methods reporting on the pool cover all of its
+    /// shards, while `Pool::insert_test_connection` and `Pool::next_reap_in` only use the
+    /// first one.
+    ///
+    /// Defaults to 1, giving every caller the same lock.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `shards` is 0. With more than one shard, building the pool will panic
+    /// if `max_size` is smaller than `shards`, or if any of these options, which cap the
+    /// pool as a whole, is set: `Builder::subset`, `Builder::autoscale`,
+    /// `Builder::max_checkouts`, `Builder::max_idle`, `Builder::hot_idle`,
+    /// `Builder::max_replenish_inflight` and `Builder::global_connect_budget`.
+    #[must_use]
+    pub fn shards(mut self, shards: u32) -> Self {
+        assert!(shards > 0, "shards must be greater than zero!");
+        self.shards = shards;
+        self
+    }
+
+    /// Registers a labeled subset of connections, checked out with `Pool::get_labeled`.
+    ///
+    /// Connections in a subset are established with `ManageConnection::connect_labeled`
+    /// and are only handed out to callers asking for their label, which suits for example
+    /// keeping read and write connections in one pool. The pool keeps `min_idle` of them
+    /// idle and never opens more than `max_size`, while all subsets share the pool's own
+    /// `max_size` with its unlabeled connections. The pool's `min_idle` only counts
+    /// unlabeled connections. When the pool is full, an idle connection from elsewhere in
+    /// the pool is closed to make room for a caller waiting on a subset with room to spare.
+    ///
+    /// Registering a label again replaces its limits.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `max_size` is 0 or smaller than `min_idle`.
+    #[must_use]
+    pub fn subset(mut self, label: &'static str, min_idle: u32, max_size: u32) -> Self {
+        assert!(max_size > 0, "max_size must be greater than zero!");
+        assert!(
+            max_size >= min_idle,
+            "min_idle must be no larger than max_size"
+        );
+        self.subsets.retain(|&(existing, _, _)| existing != label);
+        self.subsets.push((label, min_idle, max_size));
+        self
+    }
+
+    /// Set a callback to be invoked whenever a connection returned to the pool is found to be
+    /// broken by `ManageConnection::has_broken`.
+    ///
+    /// The callback runs on the task dropping the `PooledConnection`, before the pool's lock is
+    /// taken to account for the dropped connection.
+    #[must_use]
+    pub fn on_broken<F>(mut self, on_broken: F) -> Self
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.on_broken = Some(Callback(Box::new(on_broken)));
+        self
+    }
+
+    /// Set a callback to be invoked with the time a connection was checked out for whenever
+    /// it is returned to the pool.
+    ///
+    /// The duration is measured from when `get` handed out the connection until the
+    /// `PooledConnection` was dropped, and is reported for broken connections as well. Like
+    /// `on_broken`, the callback runs on the task dropping the `PooledConnection`.
+    #[must_use]
+    pub fn on_release<F>(mut self, on_release: F) -> Self
+    where
+        F: Fn(Duration) + Send + Sync + 'static,
+    {
+        self.on_release = Some(Callback(Box::new(on_release)));
+        self
+    }
+
+    /// Set a callback to be invoked with the soft timeout whenever a call to
+    /// `Pool::get_tiered` is still waiting for a connection once it has passed.
+    ///
+    /// The callback runs on the task waiting for the connection, which keeps waiting until
+    /// the hard timeout. This makes it possible to alert on slow acquisitions before they
+    /// turn into failures.
+    #[must_use]
+    pub fn on_slow_acquire<F>(mut self, on_slow_acquire: F) -> Self
+    where
+        F: Fn(Duration) + Send + Sync + 'static,
+    {
+        self.on_slow_acquire = Some(Callback(Box::new(on_slow_acquire)));
+        self
+    }
+
+    /// Set a callback to be invoked whenever a connection that became available, because it
+    /// was returned or newly established, is handed to a waiter or queued as idle.
+    ///
+    /// A waiter that gave up is only noticed when a connection is sent to it, after which the
+    /// connection is offered to the next waiter. Every such attempt is reported, so this
+    /// makes it possible to see connections bouncing between waiters that are gone.
+    ///
+    /// The callback runs while the pool is locked, so it must be quick and must not call
+    /// back into the pool.
+    #[must_use]
+    pub fn on_waiter_delivery<F>(mut self, on_waiter_delivery: F) -> Self
+    where
+        F: Fn(WaiterDeliveryOutcome) + Send + Sync + 'static,
+    {
+        self.on_waiter_delivery = Some(Callback(Box::new(on_waiter_delivery)));
+        self
+    }
+
+    /// Set a callback to be invoked after every run of the reaper, with the number of
+    /// connections it closed for exceeding `max_lifetime` and `idle_timeout`.
+    ///
+    /// The callback runs on the reaper task, after the pool's lock has been released.
+    #[must_use]
+    pub fn on_reap<F>(mut self, on_reap: F) -> Self
+    where
+        F: Fn(ReapSummary) + Send + Sync + 'static,
+    {
+        self.on_reap = Some(Callback(Box::new(on_reap)));
+        self
+    }
+
+    /// Set a callback to be invoked when a run of the reaper closes connections and leaves
+    /// fewer than `min_idle` idle, with the number of idle connections left and `min_idle`.
     ///
-    /// Defaults to `NopErrorSink`.
+    /// Replacements for the closed connections are already being established when the
+    /// callback runs, on the reaper task after the pool's lock has been released.
     #[must_use]
-    pub fn error_sink(mut self, error_sink: Box<dyn ErrorSink<M::Error>>) -> Self {
-        self.error_sink = error_sink;
+    pub fn on_min_idle_underflow<F>(mut self, on_min_idle_underflow: F) -> Self
+    where
+        F: Fn(u32, u32) + Send + Sync + 'static,
+    {
+        self.on_min_idle_underflow = Some(Callback(Box::new(on_min_idle_underflow)));
         self
     }
 
-    /// Used by tests
-    #[allow(dead_code)]
+    /// Set a callback to be invoked once, when the pool establishes its first connection.
+    ///
+    /// This marks the moment the pool goes from zero connections to one, for example to
+    /// sequence startup. The callback runs on the task that established the connection,
+    /// after the connection has been added to the pool and its lock has been released.
+    /// Later connections, including ones opened after all connections were closed, do not
+    /// invoke it again.
     #[must_use]
-    pub fn reaper_rate(mut self, reaper_rate: Duration) -> Self {
-        self.reaper_rate = reaper_rate;
+    pub fn on_first_connection<F>(mut self, on_first_connection: F) -> Self
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let on_first_connection = Mutex::new(Some(on_first_connection));
+        self.on_first_connection = Some(Callback(Box::new(move || {
+            if let Some(f) = on_first_connection.lock().take() {
+                f();
+            }
+        })));
         self
     }
 
-    /// Set the connection customizer to customize newly checked out connections
+    /// Set a hook that runs before every attempt to establish a connection for the pool,
+    /// failing the attempt if it returns an error.
+    ///
+    /// This lets tests script slow or failing connects, to exercise backoff and the delivery
+    /// of new connections to waiters, without implementing that in the test manager. The
+    /// hook is not consulted by `Pool::dedicated_connection`.
+    #[cfg(feature = "test-util")]
     #[must_use]
-    pub fn connection_customizer(
-        mut self,
-        connection_customizer: Box<dyn CustomizeConnection<M::Connection, M::Error>>,
-    ) -> Self {
-        self.connection_customizer = Some(connection_customizer);
+    pub fn connect_hook<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), M::Error>> + Send + 'static,
+    {
+        self.connect_hook = Some(Callback(Box::new(move || Box::pin(hook()))));
         self
     }
 
@@ -260,6 +1822,30 @@ impl<M: ManageConnection> Builder<M> {
                 "min_idle must be no larger than max_size"
             );
         }
+        if self.shards > 1 {
+            assert!(
+                self.max_size >= self.shards,
+                "shards must be no larger than max_size"
+            );
+            let capped = [
+                ("subset", !self.subsets.is_empty()),
+                ("autoscale", self.autoscale.is_some()),
+                ("max_checkouts", self.max_checkouts.is_some()),
+                ("max_idle", self.max_idle.is_some()),
+                ("hot_idle", self.hot_idle.is_some()),
+                (
+                    "max_replenish_inflight",
+                    self.max_replenish_inflight.is_some(),
+                ),
+                (
+                    "global_connect_budget",
+                    self.global_connect_budget.is_some(),
+                ),
+            ];
+            for (option, set) in capped {
+                assert!(!set, "shards cannot be combined with {}", option);
+            }
+        }
 
         Pool {
             inner: PoolInner::new(self, manager),
@@ -275,6 +1861,21 @@ impl<M: ManageConnection> Builder<M> {
         pool.inner.start_connections().await.map(|()| pool)
     }
 
+    /// Consumes the builder, returning a new, initialized `Pool` using the manager produced
+    /// by `factory`.
+    ///
+    /// This behaves like `build`, but first awaits `factory`, which allows managers that need
+    /// asynchronous setup (such as fetching credentials) to be created as part of building the
+    /// pool. Errors from either the factory or the initial connections are returned.
+    pub async fn build_with_manager_factory<F, E>(self, factory: F) -> Result<Pool<M>, E>
+    where
+        F: Future<Output = Result<M, E>>,
+        E: From<M::Error>,
+    {
+        let manager = factory.await?;
+        Ok(self.build(manager).await?)
+    }
+
     /// Consumes the builder, returning a new, initialized `Pool`.
     ///
     /// Unlike `build`, this does not wait for any connections to be established
@@ -284,6 +1885,82 @@ impl<M: ManageConnection> Builder<M> {
         p.inner.spawn_start_connections();
         p
     }
+
+    /// Consumes the builder, returning a new, initialized `Pool` once a single connection
+    /// has been established.
+    ///
+    /// This makes exactly one attempt to connect, within the connection timeout, and returns
+    /// its error if it fails, so that a misconfigured pool surfaces at startup instead of on
+    /// the first request. The connection is added to the pool, and any further connections
+    /// needed to reach `min_idle` are established in the background, like with
+    /// `build_unchecked`.
+    pub async fn build_checked(self, manager: M) -> Result<Pool<M>, RunError<M::Error>> {
+        let p = self.build_inner(manager);
+        p.inner.connect_first().await?;
+        p.inner.spawn_start_connections();
+        Ok(p)
+    }
+
+    /// Consumes the builder, returning a new `Pool` seeded with `conns`.
+    ///
+    /// This is meant for connections opened elsewhere, for instance by a previous pool
+    /// handing off its connections during a restart. They become idle connections as if
+    /// the manager had just established them, so their lifetime and idle time count from
+    /// now, and they are not passed to the connection customizer. Connections beyond
+    /// `max_size` are dropped. Like `build_unchecked`, this does not wait for any further
+    /// connections needed to reach `min_idle` to be established before returning.
+    pub fn build_with_connections(self, manager: M, conns: Vec<M::Connection>) -> Pool<M> {
+        let p = self.build_inner(manager);
+        for conn in conns {
+            if p.inner.adopt_connection(conn).is_err() {
+                break;
+            }
+        }
+        p.inner.spawn_start_connections();
+        p
+    }
+}
+
+/// How a pool picks the idle connection to hand out, see `Builder::selection_strategy`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SelectionStrategy {
+    /// Take connections from the idle queue in order, see `Builder::return_to_front`.
+    Queue,
+    /// Prefer a connection to the backend that was least recently checked out from, as
+    /// identified by `ManageConnection::backend`, spreading load across backends.
+    ///
+    /// Finding the connection takes a scan of the idle queue on every checkout.
+    LeastRecentBackend,
+}
+
+/// The kind of access a caller needs, see `Pool::get_role`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Role {
+    /// The caller may write, so it must be served by the primary.
+    ReadWrite,
+    /// The caller only reads, so it may be served by a replica.
+    ReadOnly,
+}
+
+/// What became of a connection offered to waiters, see `Builder::on_waiter_delivery`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum WaiterDeliveryOutcome {
+    /// The connection with this ID was handed to a waiter.
+    Delivered(u64),
+    /// The waiter offered the connection with this ID had given up, so it is offered to the
+    /// next one.
+    WaiterGone(u64),
+    /// No waiter took the connection with this ID, so it was queued as idle.
+    Idle(u64),
+}
+
+impl Default for SelectionStrategy {
+    fn default() -> Self {
+        Self::Queue
+    }
 }
 
 /// A trait which provides connection-specific functionality.
@@ -296,10 +1973,75 @@ pub trait ManageConnection: Sized + Send + Sync + 'static {
 
     /// Attempts to create a new connection.
     async fn connect(&self) -> Result<Self::Connection, Self::Error>;
+    /// Attempts to create a new connection for the subset registered as `label` with
+    /// `Builder::subset`.
+    ///
+    /// The default ignores the label and calls `connect`.
+    async fn connect_labeled(&self, _label: &'static str) -> Result<Self::Connection, Self::Error> {
+        self.connect().await
+    }
     /// Determines if the connection is still connected to the database.
     async fn is_valid(&self, conn: &mut Self::Connection) -> Result<(), Self::Error>;
+    /// Restores a returned connection to a clean state before it becomes idle again, for
+    /// instance by rolling back a transaction left open. Connections for which this fails are
+    /// dropped.
+    ///
+    /// Only called with `Builder::reset_on_return`. The default does nothing.
+    async fn reset(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+        Ok(())
+    }
     /// Synchronously determine if the connection is no longer usable, if possible.
     fn has_broken(&self, conn: &mut Self::Connection) -> bool;
+    /// Determines whether an error returned while using a connection means the connection
+    /// should be discarded and the operation retried on a fresh one.
+    ///
+    /// Used by `Pool::run_with_retry`. The default treats no errors as transient.
+    fn is_transient(&self, _error: &Self::Error) -> bool {
+        false
+    }
+    /// Determines how long this connection may sit idle before it is reaped.
+    ///
+    /// Called once, right after the connection has been established. A returned value takes
+    /// precedence over `Builder::idle_timeout` for this connection only; the default returns
+    /// `None`, leaving the connection subject to the pool-wide idle timeout.
+    fn idle_timeout(&self, _conn: &mut Self::Connection) -> Option<Duration> {
+        None
+    }
+
+    /// The number of callers that may use `conn` at the same time through
+    /// [`Pool::get_shared`].
+    ///
+    /// Defaults to 1, which gives shared checkouts exclusive access to the connection.
+    fn max_concurrent(&self, _conn: &Self::Connection) -> usize {
+        1
+    }
+
+    /// The number of queries that may be in flight on `conn` at the same time, as enforced
+    /// by the permits handed out with [`Pool::get_with_permit`].
+    ///
+    /// Called once per connection, on its first checkout through `Pool::get_with_permit`.
+    /// Defaults to 1; values below 1 are treated as 1.
+    fn query_concurrency(&self, _conn: &Self::Connection) -> usize {
+        1
+    }
+
+    /// Identifies the backend `conn` is connected to, for
+    /// `SelectionStrategy::LeastRecentBackend`.
+    ///
+    /// Called once, right after the connection has been established. Defaults to 0, which
+    /// treats every connection as connected to the same backend.
+    fn backend(&self, _conn: &Self::Connection) -> u64 {
+        0
+    }
+
+    /// Measures the resources `conn` holds on the server, in a unit of the manager's
+    /// choosing, for `Builder::max_connection_resource`.
+    ///
+    /// Called whenever the connection is returned to the pool. Defaults to 0, which never
+    /// exceeds the limit.
+    fn resource_usage(&self, _conn: &Self::Connection) -> u64 {
+        0
+    }
 }
 
 /// A trait which provides functionality to initialize a connection
@@ -324,6 +2066,14 @@ where
 {
     pool: Cow<'a, PoolInner<M>>,
     conn: Option<Conn<M::Connection>>,
+    checked_out_at: Instant,
+    error_reported: bool,
+    /// The end of the budget given to `Pool::get_with_budget`, if any.
+    deadline: Option<Instant>,
+    /// The options the connection was checked out with
+    checkout: CheckoutOptions,
+    #[cfg(feature = "tracing")]
+    span: Box<tracing::Span>,
 }
 
 impl<'a, M> PooledConnection<'a, M>
@@ -331,14 +2081,164 @@ where
     M: ManageConnection,
 {
     pub(crate) fn new(pool: &'a PoolInner<M>, conn: Conn<M::Connection>) -> Self {
+        pool.checkout_started();
         Self {
             pool: Cow::Borrowed(pool),
             conn: Some(conn),
+            checked_out_at: Instant::now(),
+            error_reported: false,
+            deadline: None,
+            checkout: CheckoutOptions::default(),
+            #[cfg(feature = "tracing")]
+            span: Box::new(tracing::Span::current()),
+        }
+    }
+
+    /// Report that `error` occurred while using this connection.
+    ///
+    /// If the pool was built with `Builder::validate_on_error`, the connection will be
+    /// validated before it is accepted back into the pool.
+    pub fn report_error(&mut self, _error: &M::Error) {
+        self.error_reported = true;
+    }
+
+    /// The ID the pool assigned to this connection when it was established.
+    ///
+    /// IDs are unique within a pool and increase in the order connections are established,
+    /// and are also reported by `Pool::events` and `ConnInfo`, so the history of a single
+    /// connection can be followed from its creation to its closing.
+    pub fn connection_id(&self) -> u64 {
+        self.conn.as_ref().unwrap().id
+    }
+
+    /// The shard of the pool the connection belongs to
+    pub(crate) fn pool(&self) -> &PoolInner<M> {
+        &self.pool
+    }
+
+    pub(crate) fn query_permits(
+        &mut self,
+        permits: impl FnOnce(&M::Connection) -> usize,
+    ) -> Arc<Semaphore> {
+        self.conn.as_mut().unwrap().query_permits(permits)
+    }
+
+    /// Discard this connection and check out a fresh one in its place.
+    ///
+    /// Use this when the connection is found to be in a bad state partway through an
+    /// operation. The connection is dropped rather than returned to the pool, as if the
+    /// manager reported it broken, and the replacement is checked out the same way as this
+    /// connection was, for instance from the same subset with `Pool::get_labeled`. The
+    /// replacement counts as the same checkout for `Builder::on_release`.
+    pub async fn replace(self) -> Result<Self, RunError<M::Error>> {
+        let pool = self.pool.clone();
+        let (checked_out_at, deadline, checkout) =
+            (self.checked_out_at, self.deadline, self.checkout);
+        #[cfg(feature = "tracing")]
+        let span = self.span.clone();
+        self.drop_invalid();
+
+        let conn = pool.get_owned_with(checkout.into()).await?.detach();
+        pool.checkout_started();
+        Ok(Self {
+            pool,
+            conn: Some(conn),
+            checked_out_at,
+            error_reported: false,
+            deadline,
+            checkout,
+            #[cfg(feature = "tracing")]
+            span,
+        })
+    }
+
+    /// The time left of the budget this connection was retrieved with by
+    /// `Pool::get_with_budget`, or `None` if it was retrieved without one.
+    ///
+    /// This is zero once the budget is spent.
+    pub fn remaining_budget(&self) -> Option<Duration> {
+        let deadline = self.deadline?;
+        Some(deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// The span that was current when this connection was checked out.
+    ///
+    /// The span is entered while the connection is returned to the pool, so that the events
+    /// emitted then are correlated with the request that acquired it.
+    #[cfg(feature = "tracing")]
+    pub fn span(&self) -> &tracing::Span {
+        &self.span
+    }
+
+    /// Park this connection, keeping it reserved without holding on to the guard.
+    ///
+    /// This is meant for long-running operations that use a connection in bursts. A parked
+    /// connection is neither idle nor checked out: it is not handed to other callers or
+    /// closed by the reaper, and `ParkedConnection::unpark` takes the same connection back.
+    ///
+    /// Parked connections still count towards `max_size`, so when the pool is full they
+    /// give way to others. A caller that finds no idle connection and no room to open one
+    /// reclaims the connection parked the longest instead of waiting, which invalidates its
+    /// token, and a connection parked while callers are waiting for a full pool is returned
+    /// to them straight away. Dropping the token returns the connection to the pool, and
+    /// closing the pool closes parked connections, which invalidates their tokens.
+    pub fn park(mut self) -> ParkedConnection<M> {
+        let conn = self.conn.take().unwrap();
+        self.pool.checkout_ended();
+        let pool = self.pool.clone().into_owned();
+        let id = conn.id;
+        if let Err(conn) = pool.park(conn) {
+            pool.put_back(conn, true, self.checked_out_at, self.error_reported);
+        }
+
+        ParkedConnection {
+            pool,
+            id,
+            checked_out_at: self.checked_out_at,
+            error_reported: self.error_reported,
+            deadline: self.deadline,
+            checkout: self.checkout,
+            #[cfg(feature = "tracing")]
+            span: self.span.clone(),
         }
     }
 
     pub(crate) fn drop_invalid(mut self) {
-        let _ = self.conn.take();
+        let conn = self.conn.take().unwrap();
+        self.release(conn, false);
+    }
+
+    fn release(&self, conn: Conn<M::Connection>, valid: bool) {
+        #[cfg(feature = "tracing")]
+        let _entered = self.span.enter();
+        #[cfg(feature = "tracing")]
+        tracing::trace!(
+            connection_id = conn.id,
+            valid,
+            "returning connection to the pool"
+        );
+
+        self.pool.checkout_ended();
+        self.pool
+            .as_ref()
+            .return_conn(conn, valid, self.checked_out_at, self.error_reported);
+    }
+
+    /// Whether the connection is from the pool's current generation.
+    pub(crate) fn is_current(&self) -> bool {
+        self.pool.is_current(self.conn.as_ref().unwrap())
+    }
+
+    /// Record the options the connection was checked out with, to check out its replacement.
+    pub(crate) fn with_checkout(mut self, checkout: CheckoutOptions) -> Self {
+        self.checkout = checkout;
+        self
+    }
+
+    /// Take the connection out of the guard without returning it to the pool.
+    pub(crate) fn detach(mut self) -> Conn<M::Connection> {
+        self.pool.checkout_ended();
+        self.conn.take().unwrap()
     }
 }
 
@@ -347,9 +2247,16 @@ where
     M: ManageConnection,
 {
     pub(crate) fn new_owned(pool: PoolInner<M>, conn: Conn<M::Connection>) -> Self {
+        pool.checkout_started();
         Self {
             pool: Cow::Owned(pool),
             conn: Some(conn),
+            checked_out_at: Instant::now(),
+            error_reported: false,
+            deadline: None,
+            checkout: CheckoutOptions::default(),
+            #[cfg(feature = "tracing")]
+            span: Box::new(tracing::Span::current()),
         }
     }
 }
@@ -389,17 +2296,279 @@ where
     M: ManageConnection,
 {
     fn drop(&mut self) {
-        self.pool.as_ref().put_back(self.conn.take());
+        if let Some(conn) = self.conn.take() {
+            self.release(conn, true);
+        }
+    }
+}
+
+/// A connection parked with `PooledConnection::park`.
+///
+/// Dropping this returns the connection to the pool if it is still parked.
+pub struct ParkedConnection<M>
+where
+    M: ManageConnection,
+{
+    pool: PoolInner<M>,
+    id: u64,
+    checked_out_at: Instant,
+    error_reported: bool,
+    /// The end of the budget given to `Pool::get_with_budget`, if any.
+    deadline: Option<Instant>,
+    /// The options the connection was checked out with
+    checkout: CheckoutOptions,
+    #[cfg(feature = "tracing")]
+    span: Box<tracing::Span>,
+}
+
+impl<M> ParkedConnection<M>
+where
+    M: ManageConnection,
+{
+    /// Take the parked connection back, or `None` if the pool reclaimed it for another caller.
+    ///
+    /// The connection counts as checked out since it was first handed out.
+    pub fn unpark(self) -> Option<PooledConnection<'static, M>> {
+        let conn = self.pool.unpark(self.id)?;
+        let mut conn = PooledConnection::new_owned(self.pool.clone(), conn);
+        conn.checked_out_at = self.checked_out_at;
+        conn.error_reported = self.error_reported;
+        conn.deadline = self.deadline;
+        conn.checkout = self.checkout;
+        #[cfg(feature = "tracing")]
+        {
+            conn.span = self.span.clone();
+        }
+        Some(conn)
+    }
+
+    /// The ID of the parked connection, see `PooledConnection::connection_id`.
+    pub fn connection_id(&self) -> u64 {
+        self.id
+    }
+}
+
+impl<M> fmt::Debug for ParkedConnection<M>
+where
+    M: ManageConnection,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("ParkedConnection")
+            .field("id", &self.id)
+            .finish()
+    }
+}
+
+impl<M> Drop for ParkedConnection<M>
+where
+    M: ManageConnection,
+{
+    fn drop(&mut self) {
+        if let Some(conn) = self.pool.unpark(self.id) {
+            self.pool
+                .put_back(conn, true, self.checked_out_at, self.error_reported);
+        }
+    }
+}
+
+/// A smart pointer to a connection that may be in use by several callers at once.
+///
+/// Returned by [`Pool::get_shared`]. Only shared access to the connection is provided; once
+/// the last `SharedConnection` for a connection is dropped, it is returned to the pool.
+pub struct SharedConnection<M>
+where
+    M: ManageConnection,
+    M::Connection: Sync,
+{
+    pool: PoolInner<M>,
+    conn: Option<Arc<Conn<M::Connection>>>,
+}
+
+impl<M> SharedConnection<M>
+where
+    M: ManageConnection,
+    M::Connection: Sync,
+{
+    pub(crate) fn new(pool: PoolInner<M>, conn: Arc<Conn<M::Connection>>) -> Self {
+        Self {
+            pool,
+            conn: Some(conn),
+        }
+    }
+
+    /// The ID the pool assigned to this connection, see `PooledConnection::connection_id`.
+    pub fn connection_id(&self) -> u64 {
+        self.conn.as_ref().unwrap().id
+    }
+}
+
+impl<M> Deref for SharedConnection<M>
+where
+    M: ManageConnection,
+    M::Connection: Sync,
+{
+    type Target = M::Connection;
+
+    fn deref(&self) -> &Self::Target {
+        &self.conn.as_ref().unwrap().conn
+    }
+}
+
+impl<M> fmt::Debug for SharedConnection<M>
+where
+    M: ManageConnection,
+    M::Connection: fmt::Debug + Sync,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.conn.as_ref().unwrap().conn, fmt)
+    }
+}
+
+impl<M> Drop for SharedConnection<M>
+where
+    M: ManageConnection,
+    M::Connection: Sync,
+{
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            self.pool.put_back_shared(conn);
+        }
+    }
+}
+
+/// A smart pointer to a connection, bundled with a permit to run a query on it.
+///
+/// Returned by [`Pool::get_with_permit`]. Dropping it releases the permit and returns the
+/// connection to the pool.
+pub struct PermittedConnection<'a, M>
+where
+    M: ManageConnection,
+{
+    // Declared first so the permit is released before the connection is put back
+    permit: OwnedSemaphorePermit,
+    permits: Arc<Semaphore>,
+    conn: PooledConnection<'a, M>,
+}
+
+impl<'a, M> PermittedConnection<'a, M>
+where
+    M: ManageConnection,
+{
+    pub(crate) fn new(
+        conn: PooledConnection<'a, M>,
+        permits: Arc<Semaphore>,
+        permit: OwnedSemaphorePermit,
+    ) -> Self {
+        Self {
+            permit,
+            permits,
+            conn,
+        }
+    }
+
+    /// Waits for another permit to run a query on this connection.
+    ///
+    /// The permit is released when dropped. Permits still held once the guard is dropped
+    /// keep counting against the connection on its next checkout.
+    pub async fn acquire(&self) -> OwnedSemaphorePermit {
+        self.permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("query permits are never closed")
+    }
+
+    /// Takes another permit to run a query on this connection, if one is available.
+    pub fn try_acquire(&self) -> Option<OwnedSemaphorePermit> {
+        self.permits.clone().try_acquire_owned().ok()
+    }
+
+    /// The number of permits currently available on this connection.
+    pub fn available_permits(&self) -> usize {
+        self.permits.available_permits()
+    }
+
+    /// The ID the pool assigned to this connection, see `PooledConnection::connection_id`.
+    pub fn connection_id(&self) -> u64 {
+        self.conn.connection_id()
+    }
+}
+
+impl<'a, M> Deref for PermittedConnection<'a, M>
+where
+    M: ManageConnection,
+{
+    type Target = M::Connection;
+
+    fn deref(&self) -> &Self::Target {
+        &self.conn
+    }
+}
+
+impl<'a, M> DerefMut for PermittedConnection<'a, M>
+where
+    M: ManageConnection,
+{
+    fn deref_mut(&mut self) -> &mut M::Connection {
+        &mut self.conn
+    }
+}
+
+impl<'a, M> fmt::Debug for PermittedConnection<'a, M>
+where
+    M: ManageConnection,
+    M::Connection: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("PermittedConnection")
+            .field("conn", &*self.conn)
+            .field("permit", &self.permit)
+            .finish()
     }
 }
 
 /// bb8's error type.
+///
+/// To return your own error type from code that checks out connections, implement
+/// `From<RunError<E>>` for it: `?` then converts the errors returned by `Pool::get` and the
+/// other checkout methods directly. `RunError::map_user` converts just the errors returned
+/// from user code, for instance into an error type shared by pools with different managers.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum RunError<E> {
     /// An error returned from user code.
     User(E),
     /// bb8 attempted to get a connection but the provided timeout was exceeded.
     TimedOut,
+    /// bb8 waited for a connection until the connection timeout elapsed, in the state
+    /// the pool was in when it gave up.
+    TimedOutWithState(State),
+    /// The caller cancelled the attempt to get a connection.
+    Cancelled,
+    /// bb8 gave up on the attempt to get a connection because establishing new connections
+    /// failed (see `Builder::fail_waiters_on_connect_exhaustion`).
+    ConnectFailed,
+    /// The pool has been closed by `Pool::drain_timeout`.
+    Closed,
+    /// As many connections as `Builder::max_checkouts` allows were already checked out.
+    TooManyCheckouts,
+}
+
+impl<E> RunError<E> {
+    /// Converts the error returned from user code with `f`, keeping the pool's own errors.
+    pub fn map_user<F, T>(self, f: F) -> RunError<T>
+    where
+        F: FnOnce(E) -> T,
+    {
+        match self {
+            RunError::User(err) => RunError::User(f(err)),
+            RunError::TimedOut => RunError::TimedOut,
+            RunError::TimedOutWithState(state) => RunError::TimedOutWithState(state),
+            RunError::Cancelled => RunError::Cancelled,
+            RunError::ConnectFailed => RunError::ConnectFailed,
+            RunError::Closed => RunError::Closed,
+            RunError::TooManyCheckouts => RunError::TooManyCheckouts,
+        }
+    }
 }
 
 impl<E> fmt::Display for RunError<E>
@@ -410,6 +2579,15 @@ where
         match *self {
             RunError::User(ref err) => write!(f, "{err}"),
             RunError::TimedOut => write!(f, "Timed out in bb8"),
+            RunError::TimedOutWithState(ref state) => write!(
+                f,
+                "Timed out in bb8 with {} connections, {} idle, {} establishing and {} waiting",
+                state.connections, state.idle_connections, state.establishing, state.waiters
+            ),
+            RunError::Cancelled => write!(f, "Cancelled in bb8"),
+            RunError::ConnectFailed => write!(f, "Failed to establish a connection in bb8"),
+            RunError::Closed => write!(f, "Pool closed in bb8"),
+            RunError::TooManyCheckouts => write!(f, "Too many connections checked out in bb8"),
         }
     }
 }
@@ -421,7 +2599,12 @@ where
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match *self {
             RunError::User(ref err) => Some(err),
-            RunError::TimedOut => None,
+            RunError::TimedOut
+            | RunError::TimedOutWithState(_)
+            | RunError::Cancelled
+            | RunError::ConnectFailed
+            | RunError::Closed
+            | RunError::TooManyCheckouts => None,
         }
     }
 }
@@ -435,16 +2618,104 @@ where
     }
 }
 
+/// A hook run before each connection attempt, see `Builder::connect_hook`.
+#[cfg(feature = "test-util")]
+pub(crate) type ConnectHook<E> = dyn Fn() -> BoxFuture<'static, Result<(), E>> + Send + Sync;
+
+/// A function rewriting connect errors, see `Builder::map_connect_error`.
+pub(crate) type MapConnectError<E> = dyn Fn(E, ConnectAttemptInfo) -> E + Send + Sync;
+
+/// A user-supplied callback, wrapped so that `Builder` can implement `Debug`.
+pub(crate) struct Callback<F: ?Sized>(pub(crate) Box<F>);
+
+impl<F: ?Sized> fmt::Debug for Callback<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Callback")
+    }
+}
+
 /// A trait to receive errors generated by connection management that aren't
 /// tied to any particular caller.
 pub trait ErrorSink<E>: fmt::Debug + Send + Sync + 'static {
     /// Receive an error
     fn sink(&self, error: E);
 
+    /// Receive the last error from a new connection that could not be established
+    /// within the connection timeout.
+    ///
+    /// `failure` tells apart background maintenance that keeps failing from callers that
+    /// could not be served. Defaults to passing `error` on to `sink`.
+    fn sink_connect(&self, error: E, failure: ConnectFailure) {
+        let _ = failure;
+        self.sink(error);
+    }
+
     /// Clone this sink.
     fn boxed_clone(&self) -> Box<dyn ErrorSink<E>>;
 }
 
+/// Details about a new connection that could not be established.
+#[derive(Clone, Copy, Debug)]
+#[non_exhaustive]
+pub struct ConnectFailure {
+    /// The number of times `connect` was attempted before giving up.
+    pub attempts: u32,
+    /// Whether a caller was waiting on the connection, as opposed to it being opened in the
+    /// background to maintain `min_idle`.
+    pub demand: bool,
+    /// The state of the pool when the pool gave up, with the failed attempt still counted
+    /// as being established.
+    pub state: State,
+}
+
+/// Details about a connection that could not be established, for
+/// `Builder::map_connect_error`.
+#[derive(Clone, Copy, Debug)]
+#[non_exhaustive]
+pub struct ConnectAttemptInfo {
+    /// The number of times `connect` was attempted before giving up.
+    pub attempts: u32,
+    /// The time spent trying to establish the connection, including backoff.
+    pub elapsed: Duration,
+}
+
+/// Decides whether the pool may attempt to open a connection, see
+/// `Builder::admission_controller`.
+pub trait AdmissionController: fmt::Debug + Send + Sync + 'static {
+    /// Called before every attempt to open a connection, including retries.
+    fn admit(&self) -> Admission;
+
+    /// Called with the outcome of every admitted attempt, where attempts cut short by the
+    /// connection timeout count as failed.
+    ///
+    /// Defaults to doing nothing.
+    fn record(&self, succeeded: bool) {
+        let _ = succeeded;
+    }
+}
+
+/// The decision of an `AdmissionController`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Admission {
+    /// Attempt to open the connection now.
+    Admit,
+    /// Ask again after this delay.
+    Delay(Duration),
+    /// Give up on the connection.
+    Deny,
+}
+
+/// An `AdmissionController` that admits every attempt.
+#[derive(Debug, Clone, Copy)]
+pub struct AdmitAll;
+
+impl AdmissionController for AdmitAll {
+    fn admit(&self) -> Admission {
+        Admission::Admit
+    }
+}
+
 /// An `ErrorSink` implementation that does nothing.
 #[derive(Debug, Clone, Copy)]
 pub struct NopErrorSink;
@@ -1,17 +1,34 @@
+use std::any::Any;
 use std::cmp::{max, min};
 use std::fmt;
 use std::future::Future;
+use std::iter;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
 use std::sync::{Arc, Weak};
-use std::time::{Duration, Instant};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
 use futures_channel::oneshot;
-use futures_util::stream::{FuturesUnordered, StreamExt};
-use futures_util::TryFutureExt;
+use futures_util::future::{join_all, pending, select, select_all, BoxFuture, Either};
+use futures_util::pin_mut;
+use futures_util::stream::{self, FuturesUnordered, Stream, StreamExt};
+use tokio::runtime::Handle;
 use tokio::spawn;
-use tokio::time::{interval_at, sleep, timeout, Interval};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::watch;
+use tokio::time::{interval_at, sleep, sleep_until, timeout, Interval};
 
-use crate::api::{Builder, ManageConnection, PooledConnection, RunError};
-use crate::internals::{Approval, ApprovalIter, Conn, SharedPool, State};
+use crate::api::{
+    Admission, Builder, Callback, ConnectAttemptInfo, ConnectFailure, ManageConnection,
+    PermittedConnection, PooledConnection, RunError, SharedConnection,
+};
+#[cfg(feature = "opentelemetry")]
+use crate::internals::AtomicStatistics;
+use crate::internals::{
+    apportion, Approval, ApprovalIter, Conn, ConnInfo, PoolConfig, PoolEvent, PoolHealth, PoolInfo,
+    PoolWide, Popped, SharedPool, State, Statistics, TimestampedState, WarmSummary,
+};
 
 pub(crate) struct PoolInner<M>
 where
@@ -25,32 +42,168 @@ where
     M: ManageConnection + Send,
 {
     pub(crate) fn new(builder: Builder<M>, manager: M) -> Self {
-        let inner = Arc::new(SharedPool::new(builder, manager));
+        let (statics, manager) = (Arc::new(builder), Arc::new(manager));
+        let pool_wide = Arc::new(PoolWide::default());
+        let shard = |index, shards| {
+            let (statics, manager) = (statics.clone(), manager.clone());
+            Arc::new(SharedPool::new(
+                statics,
+                manager,
+                index,
+                pool_wide.clone(),
+                shards,
+            ))
+        };
+
+        let shards = (1..statics.shards)
+            .map(|index| Self::start(shard(index, Vec::new()), false))
+            .collect();
+        Self::start(shard(0, shards), true)
+    }
 
-        if inner.statics.max_lifetime.is_some() || inner.statics.idle_timeout.is_some() {
+    /// Start the background tasks of a shard, sampling `Builder::metrics_history` for the
+    /// whole pool from the first one
+    fn start(inner: Arc<SharedPool<M>>, first: bool) -> Self {
+        if inner.statics.max_lifetime.is_some()
+            || inner.statics.idle_timeout.is_some()
+            || inner.statics.max_checkout_duration.is_some()
+            || inner.statics.max_idle.is_some()
+            || inner.statics.autoscale.is_some()
+        {
             let s = Arc::downgrade(&inner);
             if let Some(shared) = s.upgrade() {
                 let start = Instant::now() + shared.statics.reaper_rate;
-                let interval = interval_at(start.into(), shared.statics.reaper_rate);
-                schedule_reaping(interval, s);
+                *shared.next_reap.lock() = Some(start);
+                match shared.statics.reaper_on_dedicated_thread {
+                    true => schedule_reaping_on_thread(start, shared.statics.reaper_rate, s),
+                    false => {
+                        let interval = interval_at(start.into(), shared.statics.reaper_rate);
+                        schedule_reaping(interval, s);
+                    }
+                }
+            }
+        }
+
+        if let Some(idle) = inner.statics.pool_idle_shutdown {
+            schedule_idle_shutdown(idle, Arc::downgrade(&inner));
+        }
+
+        if let Some(rate) = inner.statics.background_validation {
+            schedule_validation(rate, Arc::downgrade(&inner));
+        }
+
+        match inner.statics.metrics_history {
+            Some((rate, capacity)) if first => {
+                schedule_sampling(rate, capacity, Arc::downgrade(&inner))
             }
+            _ => {}
         }
 
         Self { inner }
     }
 
+    /// This shard followed by the other shards of the pool, if any
+    fn shards(&self) -> impl Iterator<Item = &PoolInner<M>> {
+        iter::once(self).chain(&self.inner.shards)
+    }
+
+    /// The shard the next checkout is handed to
+    fn shard(&self) -> &PoolInner<M> {
+        match self.inner.shards.is_empty() {
+            true => self,
+            false => match self.inner.next_shard() {
+                0 => self,
+                index => &self.inner.shards[index - 1],
+            },
+        }
+    }
+
     pub(crate) async fn start_connections(&self) -> Result<(), M::Error> {
-        let wanted = self.inner.internals.lock().wanted(&self.inner.statics);
-        let mut stream = self.replenish_idle_connections(wanted);
-        while let Some(result) = stream.next().await {
-            result?;
+        for shard in self.shards() {
+            let wanted = shard.inner.internals.lock().wanted();
+            let mut stream = shard.replenish_idle_connections(wanted);
+            while let Some(result) = stream.next().await {
+                result.map_err(|(e, _)| e)?;
+            }
         }
         Ok(())
     }
 
-    pub(crate) fn spawn_start_connections(&self) {
+    pub(crate) async fn warm(&self, num: u32) -> Result<WarmSummary, Vec<M::Error>> {
+        let mut summary = WarmSummary {
+            requested: num,
+            ..WarmSummary::default()
+        };
+
+        let mut errors = Vec::new();
+        let shards = self.inner.statics.shards;
+        for (index, shard) in self.shards().enumerate() {
+            let num = apportion(num, shards, index as u32);
+            let approvals = shard.inner.internals.lock().approvals(num);
+            summary.approved += approvals.len() as u32;
+
+            let mut stream = shard.replenish_idle_connections(approvals);
+            while let Some(result) = stream.next().await {
+                match result {
+                    Ok(true) => summary.succeeded += 1,
+                    Ok(false) => summary.failed += 1,
+                    Err((e, _)) => errors.push(e),
+                }
+            }
+        }
+
+        match errors.is_empty() {
+            true => Ok(summary),
+            false => Err(errors),
+        }
+    }
+
+    /// Add a connection established elsewhere, as if it had been established by the manager
+    pub(crate) fn adopt_connection(&self, mut conn: M::Connection) -> Result<(), M::Connection> {
+        let manager = self.inner.manager();
+        let (idle_timeout, backend) = (manager.idle_timeout(&mut conn), manager.backend(&conn));
         let mut locked = self.inner.internals.lock();
-        self.spawn_replenishing_approvals(locked.wanted(&self.inner.statics));
+        match locked.approvals(1).next() {
+            Some(approval) => {
+                let id = self.inner.next_connection_id();
+                let generation = self.generation();
+                let conn = Conn::new(conn, id, None, idle_timeout, backend, generation);
+                let closed = locked.put(conn, Some(approval), self.inner.clone());
+                drop(locked);
+                drop(closed);
+                Ok(())
+            }
+            None => Err(conn),
+        }
+    }
+
+    /// Establish a single connection without retrying and add it to the pool
+    pub(crate) async fn connect_first(&self) -> Result<(), RunError<M::Error>> {
+        let start = Instant::now();
+        let conn = match timeout(self.connection_timeout(), self.connect()).await {
+            Ok(Ok(conn)) => conn,
+            Ok(Err(e)) => {
+                self.inner.statistics.record_connect_error();
+                return Err(RunError::User(e));
+            }
+            Err(_) => {
+                self.inner.statistics.record_connect_timeout();
+                return Err(RunError::TimedOut);
+            }
+        };
+
+        if self.adopt_connection(conn).is_ok() {
+            self.inner.statistics.record_connection(start.elapsed());
+            self.first_connection();
+        }
+        Ok(())
+    }
+
+    pub(crate) fn spawn_start_connections(&self) {
+        for shard in self.shards() {
+            let approvals = shard.inner.internals.lock().wanted();
+            shard.spawn_replenishing_approvals(approvals);
+        }
     }
 
     fn spawn_replenishing_approvals(&self, approvals: ApprovalIter) {
@@ -58,13 +211,26 @@ where
             return;
         }
 
+        // Outside of a runtime, such as when a connection is dropped on another thread, the
+        // connections are left to the next caller or reaper run to replace
+        let handle = match Handle::try_current() {
+            Ok(handle) => handle,
+            Err(_) => {
+                let mut locked = self.inner.internals.lock();
+                for approval in approvals {
+                    locked.connect_canceled(approval);
+                }
+                return;
+            }
+        };
+
         let this = self.clone();
-        spawn(async move {
+        handle.spawn(async move {
             let mut stream = this.replenish_idle_connections(approvals);
             while let Some(result) = stream.next().await {
                 match result {
-                    Ok(()) => {}
-                    Err(e) => this.inner.statics.error_sink.sink(e),
+                    Ok(_) => {}
+                    Err((e, failure)) => this.inner.statics.error_sink.sink_connect(e, failure),
                 }
             }
         });
@@ -73,7 +239,7 @@ where
     fn replenish_idle_connections(
         &self,
         approvals: ApprovalIter,
-    ) -> FuturesUnordered<impl Future<Output = Result<(), M::Error>>> {
+    ) -> FuturesUnordered<impl Future<Output = Result<bool, (M::Error, ConnectFailure)>>> {
         let stream = FuturesUnordered::new();
         for approval in approvals {
             let this = self.clone();
@@ -83,147 +249,1363 @@ where
     }
 
     pub(crate) async fn get(&self) -> Result<PooledConnection<'_, M>, RunError<M::Error>> {
-        self.make_pooled(|this, conn| PooledConnection::new(this, conn))
-            .await
+        self.get_with(GetOptions::default(), pending()).await
     }
 
     pub(crate) async fn get_owned(
         &self,
     ) -> Result<PooledConnection<'static, M>, RunError<M::Error>> {
-        self.make_pooled(|this, conn| {
-            let pool = PoolInner {
-                inner: Arc::clone(&this.inner),
+        self.get_owned_with(GetOptions::default()).await
+    }
+
+    pub(crate) async fn get_owned_with(
+        &self,
+        options: GetOptions,
+    ) -> Result<PooledConnection<'static, M>, RunError<M::Error>> {
+        self.shard()
+            .make_pooled(
+                self,
+                |this, conn| {
+                    let pool = PoolInner {
+                        inner: Arc::clone(&this.inner),
+                    };
+                    PooledConnection::new_owned(pool, conn)
+                },
+                options,
+                pending(),
+            )
+            .await
+    }
+
+    /// Retrieve a connection, giving up with `RunError::Cancelled` as soon as an attempt to
+    /// open a connection fails
+    pub(crate) async fn get_until_connect_error(
+        &self,
+    ) -> Result<PooledConnection<'_, M>, RunError<M::Error>> {
+        // Created before checking out, so that no error is missed
+        let shard = self.shard();
+        let failed = shard.inner.connect_errors.notified();
+        shard
+            .make_pooled(
+                self,
+                |this, conn| PooledConnection::new(this, conn),
+                GetOptions::default(),
+                failed,
+            )
+            .await
+    }
+
+    pub(crate) async fn get_with<C>(
+        &self,
+        options: GetOptions,
+        cancel: C,
+    ) -> Result<PooledConnection<'_, M>, RunError<M::Error>>
+    where
+        C: Future<Output = ()>,
+    {
+        self.shard()
+            .make_pooled(
+                self,
+                |this, conn| PooledConnection::new(this, conn),
+                options,
+                cancel,
+            )
+            .await
+    }
+
+    pub(crate) async fn get_tiered(
+        &self,
+        soft: Duration,
+        hard: Duration,
+    ) -> Result<PooledConnection<'_, M>, RunError<M::Error>> {
+        let options = GetOptions {
+            deadline: Some(Instant::now() + hard),
+            ..GetOptions::default()
+        };
+        let get = self.get_with(options, pending());
+        pin_mut!(get);
+
+        let on_slow_acquire = match &self.inner.statics.on_slow_acquire {
+            Some(on_slow_acquire) => on_slow_acquire,
+            None => return get.await,
+        };
+        let slow = sleep(soft);
+        pin_mut!(slow);
+        match select(get, slow).await {
+            Either::Left((result, _)) => result,
+            Either::Right((_, get)) => {
+                (on_slow_acquire.0)(soft);
+                get.await
+            }
+        }
+    }
+
+    pub(crate) async fn get_shared(&self) -> Result<SharedConnection<M>, RunError<M::Error>>
+    where
+        M::Connection: Sync,
+    {
+        let shared = self
+            .shards()
+            .find_map(|shard| Some((shard, shard.inner.internals.lock().share()?)));
+        if let Some((shard, conn)) = shared {
+            return Ok(SharedConnection::new(shard.clone(), downcast(conn)));
+        }
+
+        let conn = self.get_owned().await?;
+        let shard = conn.pool().clone();
+        let limit = self.inner.manager().max_concurrent(&conn);
+        let conn = Arc::new(conn.detach());
+        shard.inner.internals.lock().add_shared(conn.clone(), limit);
+        Ok(SharedConnection::new(shard, conn))
+    }
+
+    pub(crate) async fn get_with_permit(
+        &self,
+    ) -> Result<PermittedConnection<'_, M>, RunError<M::Error>> {
+        let start = Instant::now();
+        let mut conn = self.get().await?;
+        let manager = self.inner.manager();
+        let permits = conn.query_permits(|conn| manager.query_concurrency(conn));
+        // Permits acquired through an earlier checkout may still be held, so waiting for one
+        // counts against the connection timeout; the connection goes back if it runs out
+        let wait = self.connection_timeout().saturating_sub(start.elapsed());
+        let permit = match timeout(wait, permits.clone().acquire_owned()).await {
+            Ok(permit) => permit.expect("query permits are never closed"),
+            Err(_) => {
+                self.inner.statistics.record_checkout_timeout();
+                return Err(RunError::TimedOut);
+            }
+        };
+        Ok(PermittedConnection::new(conn, permits, permit))
+    }
+
+    pub(crate) async fn run_with_retry<T, F>(
+        &self,
+        mut retries: u32,
+        mut f: F,
+    ) -> Result<T, RunError<M::Error>>
+    where
+        F: for<'c> FnMut(&'c mut M::Connection) -> BoxFuture<'c, Result<T, M::Error>>,
+    {
+        loop {
+            let mut conn = self.get().await?;
+            match f(&mut conn).await {
+                Ok(value) => return Ok(value),
+                Err(e) if self.inner.manager().is_transient(&e) => {
+                    conn.drop_invalid();
+                    match retries {
+                        0 => return Err(RunError::User(e)),
+                        _ => retries -= 1,
+                    }
+                }
+                Err(e) => return Err(RunError::User(e)),
+            }
+        }
+    }
+
+    pub(crate) async fn get_scoped<T, F>(&self, f: F) -> Result<T, RunError<M::Error>>
+    where
+        F: for<'c> FnOnce(&'c mut M::Connection) -> BoxFuture<'c, Result<T, M::Error>>,
+    {
+        let mut conn = self.get().await?;
+        match f(&mut conn).await {
+            Ok(value) => Ok(value),
+            Err(e) => {
+                conn.drop_invalid();
+                Err(RunError::User(e))
+            }
+        }
+    }
+
+    pub(crate) async fn for_each_idle<F>(&self, mut f: F)
+    where
+        F: for<'c> FnMut(&'c mut M::Connection) -> BoxFuture<'c, Result<(), M::Error>>,
+    {
+        for shard in self.shards() {
+            shard.for_each_idle_in_shard(&mut f).await;
+        }
+    }
+
+    async fn for_each_idle_in_shard<F>(&self, f: &mut F)
+    where
+        F: for<'c> FnMut(&'c mut M::Connection) -> BoxFuture<'c, Result<(), M::Error>>,
+    {
+        // The connections are picked up front, so that each one is visited once even as others
+        // are checked out and returned during the sweep
+        let ids = self.inner.internals.lock().idle_ids();
+        for id in ids {
+            // Skip connections checked out or closed since
+            let conn = match self.inner.internals.lock().take_maintained(id) {
+                Some(conn) => conn,
+                None => continue,
             };
-            PooledConnection::new_owned(pool, conn)
-        })
-        .await
+
+            let mut maintained = Maintained {
+                pool: self,
+                conn: Some(conn),
+            };
+            let conn = maintained.conn.as_mut().unwrap();
+            match f(&mut conn.conn).await {
+                Ok(()) if self.is_current(conn) => {
+                    let conn = maintained.conn.take().unwrap();
+                    let mut locked = self.inner.internals.lock();
+                    let closed = locked.put_maintained(conn, self.inner.clone());
+                    drop(locked);
+                    drop(closed);
+                }
+                Ok(()) => {}
+                Err(e) => self.inner.statics.error_sink.sink(e),
+            }
+        }
+    }
+
+    pub(crate) async fn get_preferred<F>(
+        &self,
+        preferred: F,
+    ) -> Result<PooledConnection<'_, M>, RunError<M::Error>>
+    where
+        F: Fn(&ConnInfo<'_, M::Connection>) -> bool,
+    {
+        let reserved = self.reserve_checkout()?;
+        let popped = self.shards().find_map(|shard| {
+            let mut locked = shard.inner.internals.lock();
+            Some((
+                shard,
+                locked.pop(&shard.inner.statics, Some(&preferred), None)?,
+            ))
+        });
+
+        if let Some((shard, (conn, promoted, approvals))) = popped {
+            shard.spawn_replenishing_approvals(approvals);
+            let conn = PooledConnection::new(shard, conn);
+            if let Some(conn) = shard.check_out(conn, true, promoted).await {
+                return Ok(conn);
+            }
+        }
+        drop(reserved);
+        self.get().await
+    }
+
+    /// Validate a connection taken from the idle queue, if the pool is configured to or it
+    /// was promoted from the cold tier
+    async fn check_out<'b>(
+        &self,
+        mut conn: PooledConnection<'b, M>,
+        validate: bool,
+        promoted: bool,
+    ) -> Option<PooledConnection<'b, M>> {
+        if !conn.is_current() {
+            conn.drop_invalid();
+            return None;
+        }
+
+        let statics = &self.inner.statics;
+        let configured = statics.test_on_check_out && !statics.validate_on_error;
+        if !validate || !(configured || promoted) {
+            return Some(conn);
+        }
+
+        // A validation that does not complete in time counts as failed
+        let manager = self.inner.manager();
+        let valid = manager.is_valid(&mut conn);
+        match timeout(self.connection_timeout(), valid).await {
+            Ok(Ok(())) => Some(conn),
+            Ok(Err(e)) => {
+                self.inner.statics.error_sink.sink(e);
+                conn.drop_invalid();
+                None
+            }
+            Err(_) => {
+                conn.drop_invalid();
+                None
+            }
+        }
     }
 
-    pub(crate) async fn make_pooled<'a, 'b, F>(
+    /// Check out a connection from this shard of `pool`, or failing that, an idle connection
+    /// of one of its other shards before waiting on this one
+    pub(crate) async fn make_pooled<'a, 'b, F, C>(
         &'a self,
+        pool: &'a Self,
         make_pooled_conn: F,
+        mut options: GetOptions,
+        cancel: C,
     ) -> Result<PooledConnection<'b, M>, RunError<M::Error>>
     where
         F: Fn(&'a Self, Conn<M::Connection>) -> PooledConnection<'b, M>,
+        C: Future<Output = ()>,
     {
+        // Kept with the connection, so that a replacement is checked out the same way
+        let checkout = options.checkout();
+        let make_pooled_conn = |this, conn| make_pooled_conn(this, conn).with_checkout(checkout);
+
+        let timeout_at = Instant::now() + self.connection_timeout();
+        let deadline = match options.deadline {
+            Some(deadline) if deadline <= Instant::now() => {
+                self.inner.statistics.record_checkout_timeout();
+                return Err(RunError::TimedOut);
+            }
+            Some(deadline) => min(deadline, timeout_at),
+            None => timeout_at,
+        };
+
+        // Held until the connection is handed out, which then counts as checked out itself
+        let _reserved = match options.priority {
+            true => None,
+            false => Some(self.reserve_checkout()?),
+        };
+
+        pin_mut!(cancel);
+        let mut retries = self.inner.statics.max_checkout_validation_retries;
+        let (mut spins, spin_delay) = self.inner.statics.spin_retries;
+        let mut exhausted = false;
+        // A connection found idle when about to wait for one
+        let mut returned = None;
+        // Connections handed to a waiter may fail validation, in which case we start over
         loop {
-            let mut conn = {
+            loop {
+                // Spawn replenishment only after releasing the lock, to keep it short
+                let popped = match returned.take() {
+                    Some(popped) => Some(popped),
+                    None => {
+                        let mut locked = self.inner.internals.lock();
+                        if locked.is_closed() {
+                            return Err(RunError::Closed);
+                        }
+                        locked.pop(&self.inner.statics, None, options.label)
+                    }
+                };
+                let (shard, (conn, promoted, approvals)) = match popped {
+                    Some(popped) => (self, popped),
+                    None => match self.steal(pool, options.label) {
+                        Some(stolen) => stolen,
+                        None => break,
+                    },
+                };
+                shard.spawn_replenishing_approvals(approvals);
+                let conn = make_pooled_conn(shard, conn);
+
+                let validate = !options.skip_validation;
+                if let Some(conn) = shard.check_out(conn, validate, promoted).await {
+                    return Ok(conn);
+                }
+
+                match retries {
+                    Some(0) => {
+                        exhausted = true;
+                        break;
+                    }
+                    Some(ref mut n) => *n -= 1,
+                    None => {}
+                }
+            }
+
+            // With the pool full, a connection may be returned any moment now
+            if spins > 0
+                && Instant::now() + spin_delay < deadline
+                && self.inner.internals.lock().would_approve(1) == 0
+            {
+                spins -= 1;
+                match spin_delay.is_zero() {
+                    true => tokio::task::yield_now().await,
+                    false => sleep(spin_delay).await,
+                }
+                continue;
+            }
+
+            if self.inner.statics.connect_inline {
+                let approved = self.inner.internals.lock().approve_inline(options.label);
+                if let Some((approval, approvals)) = approved {
+                    self.spawn_replenishing_approvals(approvals);
+                    let connect = self.establish(deadline, approval.demand, approval.label);
+                    pin_mut!(connect);
+                    let err = match select(connect, cancel.as_mut()).await {
+                        Either::Left((Ok(conn), _)) => {
+                            self.inner.statistics.record_connection(approval.age());
+                            self.inner.internals.lock().connected(approval, conn.id);
+                            self.first_connection();
+                            return Ok(make_pooled_conn(self, conn));
+                        }
+                        Either::Left((Err(Some((e, _))), _)) => RunError::User(e),
+                        Either::Left((Err(None), _)) => RunError::TimedOut,
+                        Either::Right(_) => RunError::Cancelled,
+                    };
+
+                    let mut locked = self.inner.internals.lock();
+                    match err {
+                        RunError::Cancelled => locked.connect_canceled(approval),
+                        _ => {
+                            locked.connect_failed(approval, &self.inner.statics);
+                            self.inner.connect_failures.notify_waiters();
+                        }
+                    }
+                    if let RunError::TimedOut = err {
+                        self.inner.statistics.record_checkout_timeout();
+                    }
+                    return Err(err);
+                }
+            }
+
+            let (tx, mut rx) = oneshot::channel();
+            // Created before waiting, so that no failure is missed
+            let failed = self.inner.connect_failures.notified();
+            let (ahead, approvals, establishing) = {
                 let mut locked = self.inner.internals.lock();
-                match locked.pop(&self.inner.statics) {
-                    Some((conn, approvals)) => {
-                        self.spawn_replenishing_approvals(approvals);
-                        make_pooled_conn(self, conn)
+                if locked.is_closed() {
+                    return Err(RunError::Closed);
+                }
+                // A connection returned since we last looked, with no waiter to hand it to,
+                // would otherwise stay idle while we wait
+                if !exhausted {
+                    returned = locked.pop(&self.inner.statics, None, options.label);
+                    if returned.is_some() {
+                        continue;
                     }
-                    None => break,
                 }
+                let (ahead, approvals) = locked.push_waiter(
+                    tx,
+                    options.tenant,
+                    deadline,
+                    options.label,
+                    &self.inner.statics,
+                );
+                (ahead, approvals, locked.state().establishing)
             };
-
-            if !self.inner.statics.test_on_check_out {
-                return Ok(conn);
+            self.spawn_replenishing_approvals(approvals);
+            if let Some(on_queued) = options.on_queued.take() {
+                (on_queued.0)(ahead);
             }
 
-            match self.inner.manager.is_valid(&mut conn).await {
-                Ok(()) => return Ok(conn),
-                Err(e) => {
-                    self.inner.statics.error_sink.sink(e);
-                    conn.drop_invalid();
-                    continue;
+            let waited = {
+                let wait = timeout(deadline.saturating_duration_since(Instant::now()), &mut rx);
+                pin_mut!(wait);
+                let failed = async {
+                    match options.await_pending && establishing > 0 {
+                        true => failed.await,
+                        false => pending().await,
+                    }
+                };
+                pin_mut!(failed);
+                match select(wait, select(cancel.as_mut(), failed)).await {
+                    Either::Left((waited, _)) => Ok(waited),
+                    Either::Right((Either::Left(_), _)) => Err(RunError::Cancelled),
+                    Either::Right((Either::Right(_), _)) => Err(RunError::ConnectFailed),
+                }
+            };
+
+            let received = match waited {
+                Ok(Ok(Ok(guard))) => Ok(guard),
+                Ok(Ok(Err(_))) => match self.inner.internals.lock().is_closed() {
+                    true => Err(RunError::Closed),
+                    false => Err(RunError::ConnectFailed),
+                },
+                Ok(Err(_)) => Err(RunError::TimedOut),
+                Err(err) => Err(err),
+            };
+            // A connection may have been sent just as we gave up, which would otherwise be
+            // offered to the next waiter while we report the error
+            let received = match received {
+                Err(RunError::TimedOut) | Err(RunError::ConnectFailed) => match rx.try_recv() {
+                    Ok(Some(guard)) => Ok(guard),
+                    _ => received,
+                },
+                received => received,
+            };
+
+            let err = match received {
+                Ok(mut guard) => {
+                    // Connections that were just established need no validation
+                    let validate = guard.returned && !options.skip_validation;
+                    let conn = make_pooled_conn(self, guard.extract());
+                    match self.check_out(conn, validate, false).await {
+                        Some(conn) => return Ok(conn),
+                        None => continue,
+                    }
+                }
+                Err(err) => err,
+            };
+
+            // Drop our receiver and stop other connections from being offered to it
+            drop(rx);
+            let mut locked = self.inner.internals.lock();
+            locked.remove_canceled_waiters();
+            return match err {
+                RunError::TimedOut => {
+                    self.inner.statistics.record_checkout_timeout();
+                    Err(RunError::TimedOutWithState(locked.state()))
                 }
+                err => Err(err),
+            };
+        }
+    }
+
+    /// Take an idle connection from a shard of `pool` other than this one
+    fn steal<'a>(
+        &self,
+        pool: &'a Self,
+        label: Option<&'static str>,
+    ) -> Option<(&'a Self, Popped<M::Connection>)> {
+        let others = pool
+            .shards()
+            .filter(|shard| !Arc::ptr_eq(&shard.inner, &self.inner));
+        for shard in others {
+            let popped = shard
+                .inner
+                .internals
+                .lock()
+                .pop(&shard.inner.statics, None, label);
+            if let Some(popped) = popped {
+                return Some((shard, popped));
             }
         }
+        None
+    }
 
-        let (tx, rx) = oneshot::channel();
-        {
+    pub(crate) async fn drain_timeout(&self, wait: Duration) -> bool {
+        let drained = join_all(self.shards().map(|shard| shard.drain_shard(wait))).await;
+        drained.into_iter().all(|drained| drained)
+    }
+
+    async fn drain_shard(&self, wait: Duration) -> bool {
+        let mut state = {
             let mut locked = self.inner.internals.lock();
-            let approvals = locked.push_waiter(tx, &self.inner.statics);
-            self.spawn_replenishing_approvals(approvals);
+            let closed = locked.close();
+            let state = locked.subscribe();
+            drop(locked);
+            drop(closed);
+            state
         };
-
-        match timeout(self.inner.statics.connection_timeout, rx).await {
-            Ok(Ok(mut guard)) => Ok(make_pooled_conn(self, guard.extract())),
-            _ => Err(RunError::TimedOut),
+        if let Some(wakeup) = &*self.inner.reaper_wakeup.lock() {
+            let _ = wakeup.send(());
         }
+
+        let drained = async {
+            loop {
+                let done = {
+                    let current = state.borrow();
+                    current.connections == 0 && current.establishing == 0
+                };
+                if done || state.changed().await.is_err() {
+                    return;
+                }
+            }
+        };
+        timeout(wait, drained).await.is_ok()
     }
 
     pub(crate) async fn connect(&self) -> Result<M::Connection, M::Error> {
-        let mut conn = self.inner.manager.connect().await?;
+        self.connect_with(&self.inner.manager(), None).await
+    }
+
+    async fn connect_with(
+        &self,
+        manager: &M,
+        label: Option<&'static str>,
+    ) -> Result<M::Connection, M::Error> {
+        let mut conn = match label {
+            Some(label) => manager.connect_labeled(label).await?,
+            None => manager.connect().await?,
+        };
         self.on_acquire_connection(&mut conn).await?;
         Ok(conn)
     }
 
+    pub(crate) fn update_manager(&self, manager: M) {
+        let manager = Arc::new(manager);
+        for shard in self.shards() {
+            shard.inner.set_manager(manager.clone());
+        }
+    }
+
+    pub(crate) fn reconfigure(&self, manager: M) {
+        let manager = Arc::new(manager);
+        for shard in self.shards() {
+            shard.inner.reconfigure(manager.clone());
+        }
+    }
+
+    /// Return a connection dropped by its caller, from a spawned task with
+    /// `Builder::async_return`
+    pub(crate) fn return_conn(
+        &self,
+        conn: Conn<M::Connection>,
+        valid: bool,
+        checked_out_at: Instant,
+        error_reported: bool,
+    ) {
+        let handle = match (
+            valid && self.inner.statics.async_return,
+            Handle::try_current(),
+        ) {
+            (true, Ok(handle)) => handle,
+            _ => return self.put_back(conn, valid, checked_out_at, error_reported),
+        };
+
+        // Returned when dropped, so also if the runtime drops the task without running it
+        let returning = Returning {
+            pool: self.clone(),
+            conn: Some(conn),
+            checked_out_at,
+            error_reported,
+        };
+        handle.spawn(async move { drop(returning) });
+    }
+
     /// Return connection back in to the pool
-    pub(crate) fn put_back(&self, conn: Option<Conn<M::Connection>>) {
+    pub(crate) fn put_back(
+        &self,
+        conn: Conn<M::Connection>,
+        valid: bool,
+        checked_out_at: Instant,
+        error_reported: bool,
+    ) {
+        let (id, label) = (conn.id, conn.label);
+        let conn = match valid {
+            true => Some(conn),
+            false => None,
+        };
+        if let (Some(on_release), Some(_)) = (&self.inner.statics.on_release, &conn) {
+            (on_release.0)(checked_out_at.elapsed());
+        }
+
         let conn = conn.and_then(|mut conn| {
-            if !self.inner.manager.has_broken(&mut conn.conn) {
+            if !self.is_current(&conn) {
+                None
+            } else if !self.inner.statics.check_broken_on_return
+                || !self.inner.manager().has_broken(&mut conn.conn)
+            {
                 Some(conn)
             } else {
+                if let Some(on_broken) = &self.inner.statics.on_broken {
+                    (on_broken.0)();
+                }
                 None
             }
         });
+        let conn = conn.filter(|conn| match self.inner.statics.max_connection_resource {
+            Some(max) => self.inner.manager().resource_usage(&conn.conn) <= max,
+            None => true,
+        });
+
+        let reset = self.inner.statics.reset_on_return;
+        let validate = error_reported && self.inner.statics.validate_on_error;
+        let conn = match conn {
+            Some(conn) if reset || validate => {
+                self.spawn_check(conn, reset, validate);
+                return;
+            }
+            conn => conn,
+        };
 
         let mut locked = self.inner.internals.lock();
         match conn {
-            Some(conn) => locked.put(conn, None, self.inner.clone()),
+            Some(conn) => {
+                let closed = locked.put(conn, None, self.inner.clone());
+                drop(locked);
+                drop(closed);
+            }
             None => {
-                let approvals = locked.dropped(1, &self.inner.statics);
+                let approvals = locked.broken(id, label, &self.inner.statics);
+                drop(locked);
                 self.spawn_replenishing_approvals(approvals);
             }
         }
     }
 
-    /// Returns information about the current state of the pool.
-    pub(crate) fn state(&self) -> State {
-        self.inner.internals.lock().state()
+    pub(crate) fn park(&self, conn: Conn<M::Connection>) -> Result<(), Conn<M::Connection>> {
+        self.inner.internals.lock().park(conn)
     }
 
-    fn reap(&self) {
-        let mut internals = self.inner.internals.lock();
-        let approvals = internals.reap(&self.inner.statics);
-        self.spawn_replenishing_approvals(approvals);
+    pub(crate) fn unpark(&self, id: u64) -> Option<Conn<M::Connection>> {
+        self.inner.internals.lock().unpark(id)
     }
 
-    // Outside of Pool to avoid borrow splitting issues on self
-    async fn add_connection(&self, approval: Approval) -> Result<(), M::Error>
+    /// Release a shared connection, returning it to the pool if this was its last user
+    pub(crate) fn put_back_shared(&self, conn: Arc<Conn<M::Connection>>)
     where
-        M: ManageConnection,
+        M::Connection: Sync,
     {
-        let new_shared = Arc::downgrade(&self.inner);
-        let shared = match new_shared.upgrade() {
-            None => return Ok(()),
-            Some(shared) => shared,
-        };
+        let released = self.inner.internals.lock().unshare(conn);
+        if let Some((conn, since)) = released {
+            if let Ok(conn) = Arc::try_unwrap(downcast::<M::Connection>(conn)) {
+                self.put_back(conn, true, since, false);
+            }
+        }
+    }
 
-        let start = Instant::now();
-        let mut delay = Duration::from_secs(0);
-        loop {
-            let conn = shared
-                .manager
-                .connect()
-                .and_then(|mut c| async { self.on_acquire_connection(&mut c).await.map(|_| c) })
-                .await;
+    // Reset a returned connection, and validate one for which an error was reported, before
+    // accepting it back
+    fn spawn_check(&self, mut conn: Conn<M::Connection>, reset: bool, validate: bool) {
+        let (id, label) = (conn.id, conn.label);
+        let handle = match Handle::try_current() {
+            Ok(handle) => handle,
+            // Without a runtime to check it on, the connection is discarded as broken
+            Err(_) => {
+                let approvals = self
+                    .inner
+                    .internals
+                    .lock()
+                    .broken(id, label, &self.inner.statics);
+                return self.spawn_replenishing_approvals(approvals);
+            }
+        };
 
-            match conn {
-                Ok(conn) => {
-                    let conn = Conn::new(conn);
-                    shared
+        let this = self.clone();
+        handle.spawn(async move {
+            let manager = this.inner.manager();
+            let check = async {
+                if reset {
+                    manager.reset(&mut conn.conn).await?;
+                }
+                if validate {
+                    manager.is_valid(&mut conn.conn).await?;
+                }
+                Ok(())
+            };
+            // A check that hangs would hold on to the connection's slot, so it is timed out
+            let valid = match timeout(this.connection_timeout(), check).await {
+                Ok(valid) => valid.map_err(Some),
+                Err(_) => Err(None),
+            };
+            match valid {
+                Ok(()) => {
+                    let closed = this
+                        .inner
                         .internals
                         .lock()
-                        .put(conn, Some(approval), self.inner.clone());
-                    return Ok(());
+                        .put(conn, None, this.inner.clone());
+                    drop(closed);
                 }
                 Err(e) => {
-                    if Instant::now() - start > self.inner.statics.connection_timeout {
-                        let mut locked = shared.internals.lock();
-                        locked.connect_failed(approval);
-                        return Err(e);
-                    } else {
-                        delay = max(Duration::from_millis(200), delay);
-                        delay = min(self.inner.statics.connection_timeout / 2, delay * 2);
-                        sleep(delay).await;
+                    if let Some(e) = e {
+                        this.inner.statics.error_sink.sink(e);
                     }
+                    let approvals =
+                        this.inner
+                            .internals
+                            .lock()
+                            .broken(id, label, &this.inner.statics);
+                    this.spawn_replenishing_approvals(approvals);
                 }
             }
-        }
+        });
+    }
+
+    /// Take the longest idle connection out of the pool for background validation, along
+    /// with the manager to validate it with and the time validation may take.
+    ///
+    /// Connections from an older generation are closed right away instead.
+    fn take_for_validation(&self) -> Option<(Conn<M::Connection>, Arc<M>, Duration)> {
+        let taken = {
+            let mut locked = self.inner.internals.lock();
+            locked.take_for_validation(&self.inner.statics)
+        }?;
+        match self.is_current(&taken) {
+            true => Some((taken, self.inner.manager(), self.connection_timeout())),
+            false => {
+                self.validated(taken, Err(None));
+                None
+            }
+        }
+    }
+
+    /// Return a connection taken for background validation to the pool, or close it if it
+    /// failed validation, with the error if there was one
+    fn validated(&self, conn: Conn<M::Connection>, valid: Result<(), Option<M::Error>>) {
+        let (id, label) = (conn.id, conn.label);
+        match valid {
+            Ok(()) => {
+                let closed = self
+                    .inner
+                    .internals
+                    .lock()
+                    .put(conn, None, self.inner.clone());
+                drop(closed);
+            }
+            Err(e) => {
+                if let Some(e) = e {
+                    self.inner.statics.error_sink.sink(e);
+                }
+                let approvals = self
+                    .inner
+                    .internals
+                    .lock()
+                    .broken(id, label, &self.inner.statics);
+                drop(conn);
+                self.spawn_replenishing_approvals(approvals);
+            }
+        }
+    }
+
+    /// Count a checkout towards `Builder::max_checkouts`, failing if the limit was reached
+    fn reserve_checkout(&self) -> Result<CheckoutReservation<'_, M>, RunError<M::Error>> {
+        if let Some(max) = self.inner.statics.max_checkouts {
+            let counted = self.inner.checkouts.fetch_update(
+                Ordering::AcqRel,
+                Ordering::Acquire,
+                |checkouts| (checkouts < max).then(|| checkouts + 1),
+            );
+            if counted.is_err() {
+                return Err(RunError::TooManyCheckouts);
+            }
+        }
+        Ok(CheckoutReservation(self))
+    }
+
+    /// Count a connection handed out to a caller, with `Builder::max_checkouts`
+    pub(crate) fn checkout_started(&self) {
+        if self.inner.statics.max_checkouts.is_some() {
+            self.inner.checkouts.fetch_add(1, Ordering::AcqRel);
+        }
+    }
+
+    pub(crate) fn checkout_ended(&self) {
+        if self.inner.statics.max_checkouts.is_some() {
+            self.inner.checkouts.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+
+    pub(crate) fn invalidate_generation(&self) {
+        for shard in self.shards() {
+            shard.inner.generation.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn generation(&self) -> u64 {
+        self.inner.generation.load(Ordering::Relaxed)
+    }
+
+    /// Whether `conn` was established after the last call to `invalidate_generation`
+    pub(crate) fn is_current(&self, conn: &Conn<M::Connection>) -> bool {
+        conn.generation >= self.generation()
+    }
+
+    pub(crate) fn next_reap_in(&self) -> Option<Duration> {
+        if self.inner.internals.lock().is_closed() {
+            return None;
+        }
+        let next = (*self.inner.next_reap.lock())?;
+        Some(next.saturating_duration_since(Instant::now()))
+    }
+
+    pub(crate) fn statics(&self) -> &Builder<M> {
+        &self.inner.statics
+    }
+
+    /// Returns information about the current state of the pool.
+    pub(crate) fn state(&self) -> State {
+        let mut state = self.inner.internals.lock().state();
+        for shard in &self.inner.shards {
+            state.merge(&shard.state());
+        }
+        state
+    }
+
+    pub(crate) fn info(&self) -> PoolInfo {
+        let mut info = {
+            let locked = self.inner.internals.lock();
+            let settings = locked.settings();
+            PoolInfo {
+                max_size: settings.max_size,
+                min_idle: settings.min_idle,
+                connection_timeout: settings.connection_timeout,
+                max_lifetime: settings.max_lifetime,
+                idle_timeout: settings.idle_timeout,
+                state: locked.state(),
+            }
+        };
+        for shard in &self.inner.shards {
+            let other = shard.info();
+            info.max_size += other.max_size;
+            info.min_idle = info
+                .min_idle
+                .map(|min_idle| min_idle + other.min_idle.unwrap_or(0));
+            info.state.merge(&other.state);
+        }
+        info
+    }
+
+    pub(crate) fn export_config(&self) -> PoolConfig {
+        let mut config = self.inner.internals.lock().settings().clone();
+        for shard in &self.inner.shards {
+            let other = shard.export_config();
+            config.max_size += other.max_size;
+            config.min_idle = config
+                .min_idle
+                .map(|min_idle| min_idle + other.min_idle.unwrap_or(0));
+        }
+        config
+    }
+
+    pub(crate) fn apply_config(&self, config: PoolConfig) {
+        assert!(config.max_size > 0, "max_size must be greater than zero!");
+        if let Some(min_idle) = config.min_idle {
+            assert!(
+                config.max_size >= min_idle,
+                "min_idle must be no larger than max_size"
+            );
+        }
+        let shards = self.inner.statics.shards;
+        assert!(
+            config.max_size >= shards,
+            "max_size must be no smaller than the number of shards"
+        );
+
+        for (index, shard) in self.shards().enumerate() {
+            let mut config = config.clone();
+            config.max_size = apportion(config.max_size, shards, index as u32);
+            config.min_idle = config
+                .min_idle
+                .map(|min_idle| apportion(min_idle, shards, index as u32));
+            let (approvals, retired) = shard.inner.apply(config);
+            shard.spawn_replenishing_approvals(approvals);
+            drop(retired);
+        }
+    }
+
+    pub(crate) fn boost_min_idle(&self, target: u32, duration: Duration) {
+        let shards = self.inner.statics.shards;
+        for (index, shard) in self.shards().enumerate() {
+            let target = apportion(target, shards, index as u32);
+            let approvals = shard.inner.internals.lock().boost_min_idle(target);
+            shard.spawn_replenishing_approvals(approvals);
+
+            let weak_shared = Arc::downgrade(&shard.inner);
+            spawn(async move {
+                sleep(duration).await;
+                if let Some(inner) = weak_shared.upgrade() {
+                    inner.internals.lock().end_boost(target);
+                }
+            });
+        }
+    }
+
+    pub(crate) fn connection_timeout(&self) -> Duration {
+        self.inner.connection_timeout()
+    }
+
+    pub(crate) fn health(&self) -> PoolHealth {
+        let health = self.inner.internals.lock().health();
+        if self.inner.shards.is_empty() {
+            return health;
+        }
+
+        // The pool is as healthy as its least healthy shard, but still works if any does
+        let mut unavailable = 0;
+        let mut worst = PoolHealth::Healthy;
+        for health in iter::once(health).chain(self.inner.shards.iter().map(Self::health)) {
+            match health {
+                PoolHealth::Unavailable => unavailable += 1,
+                PoolHealth::Healthy => {}
+                degraded => worst = degraded,
+            }
+        }
+        match unavailable {
+            0 => worst,
+            num if num == self.inner.statics.shards => PoolHealth::Unavailable,
+            _ => PoolHealth::Degraded {
+                reason: "some shards of the pool are unavailable".to_owned(),
+            },
+        }
+    }
+
+    pub(crate) fn statistics(&self) -> Statistics {
+        let mut statistics = self.inner.statistics.snapshot();
+        for shard in &self.inner.shards {
+            statistics.merge(&shard.statistics());
+        }
+        statistics
+    }
+
+    #[cfg(feature = "opentelemetry")]
+    pub(crate) fn register_otel(
+        &self,
+        meter: &opentelemetry::metrics::Meter,
+        attributes: &[opentelemetry::KeyValue],
+    ) {
+        type Read = fn(&AtomicStatistics) -> u64;
+        let gauges: [(&'static str, &'static str, Read); 4] = [
+            (
+                "bb8.pool.connections",
+                "The number of connections managed by the pool",
+                |stats| stats.state.load().connections.into(),
+            ),
+            (
+                "bb8.pool.idle_connections",
+                "The number of idle connections",
+                |stats| stats.state.load().idle_connections.into(),
+            ),
+            (
+                "bb8.pool.pending_connections",
+                "The number of connections being established",
+                |stats| stats.state.load().establishing.into(),
+            ),
+            (
+                "bb8.pool.waiters",
+                "The number of callers waiting for a connection",
+                |stats| stats.state.load().waiters.into(),
+            ),
+        ];
+        let counters: [(&'static str, &'static str, Read); 4] = [
+            (
+                "bb8.pool.connections_created",
+                "The number of connections established by the pool",
+                |stats| stats.snapshot().connections_created,
+            ),
+            (
+                "bb8.pool.connections_closed",
+                "The number of connections closed by the pool",
+                |stats| stats.snapshot().connections_closed,
+            ),
+            (
+                "bb8.pool.checkouts",
+                "The number of times a connection was handed out",
+                |stats| stats.snapshot().checkouts,
+            ),
+            (
+                "bb8.pool.checkout_timeouts",
+                "The number of checkouts that timed out",
+                |stats| stats.snapshot().checkout_timeouts,
+            ),
+        ];
+
+        // The callbacks only hold on to the statistics of the shards, which are read without
+        // their locks
+        let statistics = self.shards().map(|shard| shard.inner.statistics.clone());
+        let statistics = statistics.collect::<Vec<_>>();
+        for (name, description, read) in gauges {
+            let (statistics, attributes) = (statistics.clone(), attributes.to_vec());
+            meter
+                .u64_observable_gauge(name)
+                .with_description(description)
+                .with_callback(move |observer| {
+                    let value = statistics.iter().map(|stats| read(stats)).sum();
+                    observer.observe(value, &attributes)
+                })
+                .init();
+        }
+        for (name, description, read) in counters {
+            let (statistics, attributes) = (statistics.clone(), attributes.to_vec());
+            meter
+                .u64_observable_counter(name)
+                .with_description(description)
+                .with_callback(move |observer| {
+                    let value = statistics.iter().map(|stats| read(stats)).sum();
+                    observer.observe(value, &attributes)
+                })
+                .init();
+        }
+    }
+
+    pub(crate) fn history(&self) -> Vec<TimestampedState> {
+        self.inner.history.lock().iter().cloned().collect()
+    }
+
+    /// Record a sample for `Builder::metrics_history`, dropping the oldest one if full
+    fn sample(&self, capacity: usize) {
+        let sample = TimestampedState {
+            at: SystemTime::now(),
+            state: self.state(),
+            statistics: self.statistics(),
+        };
+        let mut history = self.inner.history.lock();
+        if history.len() == capacity {
+            history.pop_front();
+        }
+        history.push_back(sample);
+    }
+
+    pub(crate) fn wanted(&self) -> u32 {
+        let wanted = self.shards();
+        wanted
+            .map(|shard| shard.inner.internals.lock().wanted_count())
+            .sum()
+    }
+
+    pub(crate) fn would_approve(&self, num: u32) -> u32 {
+        let approved = self.shards();
+        let approved = approved.map(|shard| shard.inner.internals.lock().would_approve(num));
+        min(approved.sum(), num)
+    }
+
+    pub(crate) fn replenishing(&self) -> u32 {
+        let replenishing = self.shards();
+        replenishing
+            .map(|shard| shard.inner.replenishing.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    pub(crate) fn record_fallback(&self) {
+        self.inner.statistics.record_fallback();
+    }
+
+    pub(crate) fn downgrade(&self) -> WeakPoolInner<M> {
+        WeakPoolInner {
+            inner: Arc::downgrade(&self.inner),
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> watch::Receiver<State> {
+        let mut shards = self
+            .shards()
+            .map(|shard| shard.inner.internals.lock().subscribe())
+            .collect::<Vec<_>>();
+        if shards.len() == 1 {
+            return shards.remove(0);
+        }
+
+        // Add up the states of the shards whenever one of them changes, until the pool or
+        // the subscriber is gone
+        let (tx, rx) = watch::channel(self.state());
+        spawn(async move {
+            loop {
+                let changed = shards.iter_mut().map(|shard| Box::pin(shard.changed()));
+                if select_all(changed).await.0.is_err() {
+                    return;
+                }
+                let mut state = State::default();
+                for shard in &shards {
+                    state.merge(&shard.borrow());
+                }
+                if tx.send(state).is_err() {
+                    return;
+                }
+            }
+        });
+        rx
+    }
+
+    pub(crate) fn events(&self) -> impl Stream<Item = PoolEvent> {
+        stream::select_all(self.shards().map(|shard| Box::pin(shard.shard_events())))
+    }
+
+    fn shard_events(&self) -> impl Stream<Item = PoolEvent> {
+        let rx = self.inner.internals.lock().subscribe_events();
+        stream::unfold(rx, |mut rx| async move {
+            match rx.recv().await {
+                Ok(event) => Some((event, rx)),
+                Err(RecvError::Lagged(dropped)) => Some((PoolEvent::Lagged(dropped), rx)),
+                Err(RecvError::Closed) => None,
+            }
+        })
+    }
+
+    fn reap(&self) {
+        let (approvals, summary, expired, (idle, min_idle)) = {
+            let mut internals = self.inner.internals.lock();
+            let (approvals, summary, expired) = internals.reap(&self.inner.statics);
+            let idle = internals.state().idle_connections;
+            (approvals, summary, expired, (idle, internals.min_idle()))
+        };
+        self.spawn_replenishing_approvals(approvals);
+        // Closing connections may be slow, so only do so after releasing the lock
+        drop(expired);
+
+        let reaped =
+            summary.max_lifetime + summary.idle_timeout + summary.max_idle + summary.abandoned > 0;
+        if let Some(on_underflow) = &self.inner.statics.on_min_idle_underflow {
+            if reaped && idle < min_idle {
+                (on_underflow.0)(idle, min_idle);
+            }
+        }
+
+        if let Some(on_reap) = &self.inner.statics.on_reap {
+            (on_reap.0)(summary);
+        }
+    }
+
+    /// Run `Builder::on_first_connection` if this is the first connection of the pool
+    fn first_connection(&self) {
+        if !self.inner.first_connection() {
+            return;
+        }
+        if let Some(on_first_connection) = &self.inner.statics.on_first_connection {
+            (on_first_connection.0)();
+        }
+    }
+
+    // Outside of Pool to avoid borrow splitting issues on self
+    //
+    // Returns whether the connection was established, or the error if one was returned.
+    async fn add_connection(&self, approval: Approval) -> Result<bool, (M::Error, ConnectFailure)>
+    where
+        M: ManageConnection,
+    {
+        let new_shared = Arc::downgrade(&self.inner);
+        let shared = match new_shared.upgrade() {
+            None => return Ok(false),
+            Some(shared) => shared,
+        };
+
+        // Held until the attempt is over, however it ends
+        let _slot = match &shared.replenish_slots {
+            Some(slots) => slots.acquire().await.ok(),
+            None => None,
+        };
+        let _inflight = Replenishing::new(&shared.replenishing);
+
+        let deadline = Instant::now() + self.connection_timeout();
+        match self
+            .establish(deadline, approval.demand, approval.label)
+            .await
+        {
+            Ok(conn) => {
+                shared.statistics.record_connection(approval.age());
+                let closed = shared
+                    .internals
+                    .lock()
+                    .put(conn, Some(approval), self.inner.clone());
+                drop(closed);
+                self.first_connection();
+                Ok(true)
+            }
+            Err(e) => {
+                let mut locked = shared.internals.lock();
+                locked.connect_failed(approval, &self.inner.statics);
+                drop(locked);
+                shared.connect_failures.notify_waiters();
+                match e {
+                    Some(e) => Err(e),
+                    None => Ok(false),
+                }
+            }
+        }
+    }
+
+    /// Establish a new connection, retrying until `deadline`.
+    ///
+    /// Demand-driven connections, which a caller is waiting on, and maintenance connections
+    /// are retried as configured for each, and connections for a subset `label` are opened
+    /// with `connect_labeled`. Fails without an error if the deadline passed while an attempt
+    /// was in progress.
+    async fn establish(
+        &self,
+        deadline: Instant,
+        demand: bool,
+        label: Option<&'static str>,
+    ) -> Result<Conn<M::Connection>, Option<(M::Error, ConnectFailure)>> {
+        let shared = &self.inner;
+        let (retries, min_backoff) = match demand {
+            true => (
+                shared.statics.demand_connect_retries,
+                shared.statics.min_connect_backoff,
+            ),
+            false => (
+                None,
+                shared
+                    .statics
+                    .maintenance_connect_backoff
+                    .unwrap_or(shared.statics.min_connect_backoff),
+            ),
+        };
+        let started = Instant::now();
+        let give_up = |e, attempts| {
+            let e = match &shared.statics.map_connect_error {
+                Some(map) => {
+                    let elapsed = started.elapsed();
+                    (map.0)(e, ConnectAttemptInfo { attempts, elapsed })
+                }
+                None => e,
+            };
+            let state = shared.internals.lock().state();
+            let failure = ConnectFailure {
+                attempts,
+                demand,
+                state,
+            };
+            (e, failure)
+        };
+        let mut delay = Duration::from_secs(0);
+        let mut attempts = 0;
+        let mut last_error = None;
+        loop {
+            if !self.admit(deadline).await {
+                return Err(last_error.map(|(e, attempts)| give_up(e, attempts)));
+            }
+
+            attempts += 1;
+            // A manager replaced while this attempt is in progress is used by the next one
+            let (manager, started) = shared.manager_generation();
+            let conn = async {
+                #[cfg(feature = "test-util")]
+                if let Some(hook) = &shared.statics.connect_hook {
+                    (hook.0)().await?;
+                }
+                self.connect_with(&manager, label).await
+            };
+
+            // An attempt still in progress when the deadline passes has no error to report
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let conn = match timeout(remaining, conn).await {
+                Ok(conn) => conn,
+                Err(_) => {
+                    shared.statistics.record_connect_timeout();
+                    shared.statics.admission_controller.record(false);
+                    return Err(None);
+                }
+            };
+            shared.statics.admission_controller.record(conn.is_ok());
+
+            match conn {
+                Ok(mut conn) => {
+                    let idle_timeout = manager.idle_timeout(&mut conn);
+                    let backend = manager.backend(&conn);
+                    let id = shared.next_connection_id();
+                    // A connection from a manager that has since been replaced belongs to the
+                    // generation it was started in, so `reconfigure` retires it
+                    let generation = match Arc::ptr_eq(&manager, &shared.manager()) {
+                        true => self.generation(),
+                        false => started,
+                    };
+                    return Ok(Conn::new(
+                        conn,
+                        id,
+                        label,
+                        idle_timeout,
+                        backend,
+                        generation,
+                    ));
+                }
+                Err(e) => {
+                    shared.internals.lock().connect_attempt_failed();
+                    shared.connect_errors.notify_waiters();
+                    let exhausted = matches!(retries, Some(retries) if attempts > retries);
+                    if exhausted || Instant::now() >= deadline {
+                        shared.statistics.record_connect_error();
+                        return Err(Some(give_up(e, attempts)));
+                    } else {
+                        last_error = Some((e, attempts));
+                        delay = max(min_backoff, delay);
+                        delay = min(self.connection_timeout() / 2, delay * 2);
+                        sleep(delay).await;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Ask the admission controller for permission to attempt a connection, then take the
+    /// attempt from the global connect budget, waiting as long as either says to but no
+    /// later than `deadline`
+    async fn admit(&self, deadline: Instant) -> bool {
+        loop {
+            let delay = match self.inner.statics.admission_controller.admit() {
+                Admission::Admit => match &self.inner.connect_budget {
+                    Some(budget) => match budget.lock().take(Instant::now()) {
+                        Ok(()) => return true,
+                        Err(delay) => delay,
+                    },
+                    None => return true,
+                },
+                Admission::Delay(delay) => delay,
+                Admission::Deny => return false,
+            };
+
+            if Instant::now() + delay >= deadline {
+                return false;
+            }
+            sleep(delay).await;
+        }
     }
 
     async fn on_acquire_connection(&self, conn: &mut M::Connection) -> Result<(), M::Error> {
@@ -234,6 +1616,120 @@ where
     }
 }
 
+/// A checkout counted towards `Builder::max_checkouts` until dropped
+struct CheckoutReservation<'a, M: ManageConnection>(&'a PoolInner<M>);
+
+impl<M: ManageConnection> Drop for CheckoutReservation<'_, M> {
+    fn drop(&mut self) {
+        self.0.checkout_ended();
+    }
+}
+
+/// A connection on its way back to the pool, which is returned once this is dropped
+struct Returning<M: ManageConnection> {
+    pool: PoolInner<M>,
+    conn: Option<Conn<M::Connection>>,
+    checked_out_at: Instant,
+    error_reported: bool,
+}
+
+impl<M: ManageConnection> Drop for Returning<M> {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            self.pool
+                .put_back(conn, true, self.checked_out_at, self.error_reported);
+        }
+    }
+}
+
+/// A connection taken out of the pool for a maintenance sweep, which is closed once this is
+/// dropped unless it was put back
+struct Maintained<'a, M: ManageConnection> {
+    pool: &'a PoolInner<M>,
+    conn: Option<Conn<M::Connection>>,
+}
+
+impl<M: ManageConnection> Drop for Maintained<'_, M> {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            let approvals = self.pool.inner.internals.lock().dropped(&[conn.id]);
+            drop(conn);
+            self.pool.spawn_replenishing_approvals(approvals);
+        }
+    }
+}
+
+/// Counts a background connection attempt while it is in progress
+struct Replenishing<'a>(&'a AtomicU32);
+
+impl<'a> Replenishing<'a> {
+    fn new(count: &'a AtomicU32) -> Self {
+        count.fetch_add(1, Ordering::Relaxed);
+        Self(count)
+    }
+}
+
+impl Drop for Replenishing<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Per-call options for checking out a connection
+#[derive(Debug, Default)]
+pub(crate) struct GetOptions {
+    /// Waiters with a tenant are served round-robin across tenants
+    pub(crate) tenant: Option<u64>,
+    /// Hand out idle connections without validating them, regardless of `test_on_check_out`
+    pub(crate) skip_validation: bool,
+    /// Give up at this time if it comes before the connection timeout
+    pub(crate) deadline: Option<Instant>,
+    /// Only hand out connections of this subset
+    pub(crate) label: Option<&'static str>,
+    /// Ignore `Builder::max_checkouts`
+    pub(crate) priority: bool,
+    /// Called with the number of callers already waiting when this one starts waiting
+    pub(crate) on_queued: Option<Callback<dyn FnOnce(usize) + Send>>,
+    /// Give up once a connection being established when this caller started waiting fails
+    pub(crate) await_pending: bool,
+}
+
+impl GetOptions {
+    /// The options that carry over to a replacement from `PooledConnection::replace`
+    pub(crate) fn checkout(&self) -> CheckoutOptions {
+        CheckoutOptions {
+            tenant: self.tenant,
+            skip_validation: self.skip_validation,
+            deadline: self.deadline,
+            label: self.label,
+            priority: self.priority,
+        }
+    }
+}
+
+/// The options a connection was checked out with, see `GetOptions`
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct CheckoutOptions {
+    tenant: Option<u64>,
+    skip_validation: bool,
+    deadline: Option<Instant>,
+    label: Option<&'static str>,
+    priority: bool,
+}
+
+impl From<CheckoutOptions> for GetOptions {
+    fn from(checkout: CheckoutOptions) -> Self {
+        GetOptions {
+            tenant: checkout.tenant,
+            skip_validation: checkout.skip_validation,
+            deadline: checkout.deadline,
+            label: checkout.label,
+            priority: checkout.priority,
+            ..GetOptions::default()
+        }
+    }
+}
+
 impl<M> Clone for PoolInner<M>
 where
     M: ManageConnection,
@@ -245,6 +1741,34 @@ where
     }
 }
 
+/// A handle to a `PoolInner` that does not keep the pool alive
+pub(crate) struct WeakPoolInner<M>
+where
+    M: ManageConnection + Send,
+{
+    inner: Weak<SharedPool<M>>,
+}
+
+impl<M> WeakPoolInner<M>
+where
+    M: ManageConnection + Send,
+{
+    pub(crate) fn upgrade(&self) -> Option<PoolInner<M>> {
+        self.inner.upgrade().map(|inner| PoolInner { inner })
+    }
+}
+
+impl<M> Clone for WeakPoolInner<M>
+where
+    M: ManageConnection,
+{
+    fn clone(&self) -> Self {
+        WeakPoolInner {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
 impl<M> fmt::Debug for PoolInner<M>
 where
     M: ManageConnection,
@@ -261,11 +1785,148 @@ where
     spawn(async move {
         loop {
             let _ = interval.tick().await;
-            if let Some(inner) = weak_shared.upgrade() {
-                PoolInner { inner }.reap();
-            } else {
-                break;
+            match weak_shared.upgrade() {
+                Some(inner) if !inner.internals.lock().is_closed() => {
+                    publish_next_reap(&inner, inner.statics.reaper_rate);
+                    PoolInner { inner }.reap();
+                }
+                Some(inner) => {
+                    *inner.next_reap.lock() = None;
+                    break;
+                }
+                None => break,
+            }
+        }
+    });
+}
+
+/// Record that the reaper runs again `rate` after the tick that is due now; ticks that
+/// were missed still count from when they were due
+fn publish_next_reap<M>(shared: &SharedPool<M>, rate: Duration)
+where
+    M: ManageConnection,
+{
+    let mut next = shared.next_reap.lock();
+    *next = next.map(|due| due + rate);
+}
+
+/// Run the reaper on its own thread, so that it never occupies a runtime worker.
+///
+/// Replacement connections are still established on the runtime the pool was created on.
+fn schedule_reaping_on_thread<M>(start: Instant, rate: Duration, weak_shared: Weak<SharedPool<M>>)
+where
+    M: ManageConnection,
+{
+    let handle = Handle::current();
+    let (wakeup, woken) = mpsc::channel();
+    if let Some(shared) = weak_shared.upgrade() {
+        *shared.reaper_wakeup.lock() = Some(wakeup);
+    }
+    let reaper = move || {
+        let mut next = start;
+        loop {
+            // Woken early once the pool is closed or dropped, so the thread does not linger
+            let timeout = next.saturating_duration_since(Instant::now());
+            if let Err(RecvTimeoutError::Timeout) = woken.recv_timeout(timeout) {
+                next += rate;
+            }
+            match weak_shared.upgrade() {
+                Some(inner) if !inner.internals.lock().is_closed() => {
+                    *inner.next_reap.lock() = Some(next);
+                    let _runtime = handle.enter();
+                    PoolInner { inner }.reap();
+                }
+                Some(inner) => {
+                    *inner.next_reap.lock() = None;
+                    break;
+                }
+                None => break,
+            }
+        }
+    };
+
+    thread::Builder::new()
+        .name("bb8-reaper".to_owned())
+        .spawn(reaper)
+        .expect("failed to spawn the reaper thread");
+}
+
+fn schedule_idle_shutdown<M>(idle: Duration, weak_shared: Weak<SharedPool<M>>)
+where
+    M: ManageConnection,
+{
+    spawn(async move {
+        let mut deadline = Instant::now() + idle;
+        loop {
+            sleep_until(deadline.into()).await;
+            match weak_shared.upgrade() {
+                Some(inner) => {
+                    let (next, closed) = inner.internals.lock().idle_shutdown(idle);
+                    drop(closed);
+                    match next {
+                        Some(next) => deadline = next,
+                        None => break,
+                    }
+                }
+                None => break,
             }
         }
     });
 }
+
+fn schedule_validation<M>(rate: Duration, weak_shared: Weak<SharedPool<M>>)
+where
+    M: ManageConnection,
+{
+    spawn(async move {
+        let mut interval = interval_at((Instant::now() + rate).into(), rate);
+        loop {
+            let _ = interval.tick().await;
+            // The pool is only held on to while taking the connection and putting it back
+            let (mut conn, manager, limit) = match weak_shared.upgrade() {
+                Some(inner) if !inner.internals.lock().is_closed() => {
+                    match (PoolInner { inner }).take_for_validation() {
+                        Some(taken) => taken,
+                        None => continue,
+                    }
+                }
+                _ => break,
+            };
+
+            let valid = match timeout(limit, manager.is_valid(&mut conn.conn)).await {
+                Ok(valid) => valid.map_err(Some),
+                Err(_) => Err(None),
+            };
+            drop(manager);
+            match weak_shared.upgrade() {
+                Some(inner) => PoolInner { inner }.validated(conn, valid),
+                None => break,
+            }
+        }
+    });
+}
+
+fn schedule_sampling<M>(rate: Duration, capacity: usize, weak_shared: Weak<SharedPool<M>>)
+where
+    M: ManageConnection,
+{
+    spawn(async move {
+        let mut interval = interval_at((Instant::now() + rate).into(), rate);
+        loop {
+            let _ = interval.tick().await;
+            match weak_shared.upgrade() {
+                Some(inner) if !inner.internals.lock().is_closed() => {
+                    PoolInner { inner }.sample(capacity)
+                }
+                _ => break,
+            }
+        }
+    });
+}
+
+fn downcast<C: Send + Sync + 'static>(conn: Arc<dyn Any + Send + Sync>) -> Arc<Conn<C>> {
+    match conn.downcast() {
+        Ok(conn) => conn,
+        Err(_) => unreachable!("pools only share connections of their own type"),
+    }
+}
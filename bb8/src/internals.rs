@@ -1,12 +1,19 @@
-use std::cmp::min;
-use std::sync::Arc;
-use std::time::Instant;
+use std::any::Any;
+use std::cmp::{max, min, Reverse};
+use std::convert::TryFrom;
+use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant, SystemTime};
 
 use futures_channel::oneshot;
-use parking_lot::Mutex;
+use parking_lot::{Mutex, RwLock};
+use tokio::sync::{broadcast, watch, Notify, Semaphore};
 
-use crate::api::{Builder, ManageConnection};
-use std::collections::VecDeque;
+use crate::api::{Builder, ManageConnection, SelectionStrategy, WaiterDeliveryOutcome};
+use crate::inner::PoolInner;
+use std::cmp::Ordering as CmpOrdering;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
 
 /// The guts of a `Pool`.
 #[allow(missing_debug_implementations)]
@@ -14,155 +21,1453 @@ pub(crate) struct SharedPool<M>
 where
     M: ManageConnection + Send,
 {
-    pub(crate) statics: Builder<M>,
-    pub(crate) manager: M,
+    /// Shared by the shards of the pool, see `Builder::shards`
+    pub(crate) statics: Arc<Builder<M>>,
+    /// Replaced by `Pool::update_manager`, so callers work on a snapshot
+    manager: RwLock<Arc<M>>,
+    /// The other shards of the pool, held by the first one
+    pub(crate) shards: Vec<PoolInner<M>>,
+    /// The shard the next checkout is handed to, with `Builder::shards`
+    next_shard: AtomicUsize,
     pub(crate) internals: Mutex<PoolInternals<M>>,
+    /// The connection timeout in effect in nanoseconds, kept in step with the settings of the
+    /// internals so that checkouts and connection attempts read it without the lock
+    connection_timeout: AtomicU64,
+    /// Shared with the internals, which count the connections closed and handed out
+    pub(crate) statistics: Arc<AtomicStatistics>,
+    /// Connections stamped with an older generation are closed instead of being handed out
+    pub(crate) generation: AtomicU64,
+    /// Shared by all shards of the pool
+    pool_wide: Arc<PoolWide>,
+    /// Exclusive checkouts in progress or handed out, with `Builder::max_checkouts`
+    pub(crate) checkouts: AtomicU32,
+    /// One permit for every background connection attempt that may run at once, with
+    /// `Builder::max_replenish_inflight`
+    pub(crate) replenish_slots: Option<Semaphore>,
+    /// Background connection attempts in progress
+    pub(crate) replenishing: AtomicU32,
+    /// Connection attempts left, with `Builder::global_connect_budget`
+    pub(crate) connect_budget: Option<Mutex<ConnectBudget>>,
+    /// Notified whenever the pool gives up on establishing a connection
+    pub(crate) connect_failures: Notify,
+    /// Notified whenever an attempt to open a connection fails, even if it is retried
+    pub(crate) connect_errors: Notify,
+    /// When the reaper runs next, published by the reaper while it is active
+    pub(crate) next_reap: Mutex<Option<Instant>>,
+    /// Wakes the reaper running on its own thread, which is also woken once this is dropped
+    /// along with the pool
+    pub(crate) reaper_wakeup: Mutex<Option<mpsc::Sender<()>>>,
+    /// The most recent samples, with `Builder::metrics_history`
+    pub(crate) history: Mutex<VecDeque<TimestampedState>>,
 }
 
 impl<M> SharedPool<M>
 where
     M: ManageConnection + Send,
 {
-    pub(crate) fn new(statics: Builder<M>, manager: M) -> Self {
+    /// Create the shard numbered `shard` of a pool, the first of which holds the others
+    pub(crate) fn new(
+        statics: Arc<Builder<M>>,
+        manager: Arc<M>,
+        shard: u32,
+        pool_wide: Arc<PoolWide>,
+        shards: Vec<PoolInner<M>>,
+    ) -> Self {
+        let statistics = Arc::new(AtomicStatistics::default());
         Self {
+            internals: Mutex::new(PoolInternals::new(&statics, shard, statistics.clone())),
+            connection_timeout: AtomicU64::new(nanos(statics.connection_timeout)),
+            statistics,
+            generation: AtomicU64::new(0),
+            pool_wide,
+            checkouts: AtomicU32::new(0),
+            replenish_slots: statics
+                .max_replenish_inflight
+                .map(|max| Semaphore::new(max as usize)),
+            replenishing: AtomicU32::new(0),
+            connect_budget: statics
+                .global_connect_budget
+                .map(|(attempts, per)| Mutex::new(ConnectBudget::new(attempts, per))),
+            connect_failures: Notify::new(),
+            connect_errors: Notify::new(),
+            next_reap: Mutex::new(None),
+            reaper_wakeup: Mutex::new(None),
+            history: Mutex::new(VecDeque::new()),
             statics,
-            manager,
-            internals: Mutex::new(PoolInternals::default()),
+            manager: RwLock::new(manager),
+            shards,
+            next_shard: AtomicUsize::new(0),
         }
     }
+
+    /// The index of the shard the next checkout goes to, counting this one as 0
+    pub(crate) fn next_shard(&self) -> usize {
+        let shards = self.shards.len() + 1;
+        self.next_shard.fetch_add(1, Ordering::Relaxed) % shards
+    }
+
+    pub(crate) fn connection_timeout(&self) -> Duration {
+        Duration::from_nanos(self.connection_timeout.load(Ordering::Relaxed))
+    }
+
+    /// Replace the configuration in effect, see `PoolInternals::apply`
+    pub(crate) fn apply(&self, settings: PoolConfig) -> (ApprovalIter, Vec<Conn<M::Connection>>) {
+        let mut locked = self.internals.lock();
+        self.connection_timeout
+            .store(nanos(settings.connection_timeout), Ordering::Relaxed);
+        locked.apply(settings)
+    }
+
+    /// The manager currently used to open and check connections
+    pub(crate) fn manager(&self) -> Arc<M> {
+        self.manager.read().clone()
+    }
+
+    pub(crate) fn set_manager(&self, manager: Arc<M>) {
+        *self.manager.write() = manager;
+    }
+
+    /// The current manager, along with the generation its connections are stamped with
+    pub(crate) fn manager_generation(&self) -> (Arc<M>, u64) {
+        let manager = self.manager.read();
+        (manager.clone(), self.generation.load(Ordering::Relaxed))
+    }
+
+    /// Replace the manager and start a new generation, so that no caller sees one without
+    /// the other
+    pub(crate) fn reconfigure(&self, manager: Arc<M>) {
+        let mut current = self.manager.write();
+        *current = manager;
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Assign an ID to a newly established connection
+    pub(crate) fn next_connection_id(&self) -> u64 {
+        self.pool_wide
+            .next_connection_id
+            .fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Returns whether no connection was established by any shard of the pool before
+    pub(crate) fn first_connection(&self) -> bool {
+        !self.pool_wide.connected.swap(true, Ordering::Relaxed)
+    }
+}
+
+/// The state of a `SharedPool` that all of its shards have in common
+#[derive(Default)]
+pub(crate) struct PoolWide {
+    /// The ID given to the next connection established by the pool
+    next_connection_id: AtomicU64,
+    /// Set once the first connection of the pool is established
+    connected: AtomicBool,
 }
 
+/// The number of events buffered for each `Pool::events` subscriber
+const EVENTS_CAPACITY: usize = 1024;
+
+/// Consecutive contended reaper ticks after which an autoscaling pool grows
+const AUTOSCALE_GROW_TICKS: i32 = 2;
+/// Consecutive uncontended reaper ticks with idle connections after which it shrinks
+const AUTOSCALE_SHRINK_TICKS: i32 = 10;
+
 /// The pool data that must be protected by a lock.
 #[allow(missing_debug_implementations)]
 pub(crate) struct PoolInternals<M>
 where
     M: ManageConnection,
 {
-    waiters: VecDeque<oneshot::Sender<InternalsGuard<M>>>,
+    waiters: Waiters<oneshot::Sender<InternalsGuard<M>>>,
     conns: VecDeque<IdleConn<M::Connection>>,
+    /// Idle connections beyond `Builder::hot_idle`, longest demoted first, which are
+    /// validated when promoted back to `conns`
+    cold: VecDeque<IdleConn<M::Connection>>,
     num_conns: u32,
     pending_conns: u32,
+    state_tx: watch::Sender<State>,
+    statistics: Arc<AtomicStatistics>,
+    /// The last time a caller asked for a connection
+    last_checkout: Instant,
+    /// Set after an idle shutdown until a connection is requested again
+    suspended: bool,
+    /// Connections checked out through `Pool::get_shared`
+    shared: Vec<SharedConn>,
+    /// The configuration in effect, initially taken from the builder and replaced by
+    /// `Pool::apply_config`. Its `max_size` is the connection cap, which `Builder::autoscale`
+    /// also adjusts over time.
+    settings: PoolConfig,
+    /// The targets of the boosts started with `Pool::boost_min_idle` that are still running
+    boosts: Vec<u32>,
+    /// Slots still to be retired after `max_size` was lowered below the number of slots in
+    /// use, which are forgotten instead of released as connections close
+    retiring: u32,
+    /// One permit for every connection that may still be opened under `max_size`
+    ///
+    /// Permits are forgotten when handed out as approvals, and added back as connections
+    /// are closed or fail to be established, so the cap is enforced by the semaphore.
+    slots: Semaphore,
+    /// Whether a caller had to wait for a connection since the last reaper tick
+    contended: bool,
+    /// Consecutive reaper ticks with (positive) or without (negative) contention
+    trend: i32,
+    /// Set once the pool is drained, after which no connections are handed out or kept
+    closed: bool,
+    /// Whether the most recent attempt to open a connection failed
+    last_connect_failed: bool,
+    /// Feeds `Pool::events`
+    events: broadcast::Sender<PoolEvent>,
+    /// Set once `Pool::events` has been called, so that events are only sent after that
+    observed: bool,
+    /// When each checked out connection was handed out, and its label, by ID, with
+    /// `max_checkout_duration`
+    checkouts: Option<HashMap<u64, (Instant, Option<&'static str>)>>,
+    /// The labeled subsets registered with `Builder::subset`, which count towards the totals
+    /// above but keep their own idle connections and waiters
+    subsets: HashMap<&'static str, Subset<M>>,
+    /// When a connection to each backend was last taken from the idle queue, with
+    /// `SelectionStrategy::LeastRecentBackend`
+    backend_checkouts: HashMap<u64, Instant>,
+    /// Connections parked with `PooledConnection::park`, longest parked first
+    parked: VecDeque<Conn<M::Connection>>,
 }
 
 impl<M> PoolInternals<M>
 where
     M: ManageConnection,
 {
+    fn new(config: &Builder<M>, shard: u32, statistics: Arc<AtomicStatistics>) -> Self {
+        let max_size = match config.autoscale {
+            Some((min_size, _)) => min_size,
+            None => config.max_size,
+        };
+        let max_size = apportion(max_size, config.shards, shard);
+
+        Self {
+            waiters: Waiters::with_capacity(config.waiter_capacity_hint),
+            conns: VecDeque::new(),
+            cold: VecDeque::new(),
+            num_conns: 0,
+            pending_conns: 0,
+            state_tx: watch::channel(State::default()).0,
+            statistics,
+            last_checkout: Instant::now(),
+            suspended: false,
+            shared: Vec::new(),
+            settings: PoolConfig {
+                max_size,
+                min_idle: config
+                    .min_idle
+                    .map(|min_idle| apportion(min_idle, config.shards, shard)),
+                connection_timeout: config.connection_timeout,
+                max_lifetime: config.max_lifetime,
+                idle_timeout: config.idle_timeout,
+                min_connection_age_before_reap: config.min_connection_age_before_reap,
+            },
+            boosts: Vec::new(),
+            retiring: 0,
+            slots: Semaphore::new(max_size as usize),
+            contended: false,
+            trend: 0,
+            closed: false,
+            last_connect_failed: false,
+            events: broadcast::channel(EVENTS_CAPACITY).0,
+            observed: false,
+            checkouts: config.max_checkout_duration.map(|_| HashMap::new()),
+            subsets: config
+                .subsets
+                .iter()
+                .map(|&(label, min_idle, max_size)| (label, Subset::new(min_idle, max_size)))
+                .collect(),
+            backend_checkouts: HashMap::new(),
+            parked: VecDeque::new(),
+        }
+    }
+
+    /// Hand out another reference to a shared connection with spare capacity, if any.
+    pub(crate) fn share(&mut self) -> Option<Arc<dyn Any + Send + Sync>> {
+        if self.closed {
+            return None;
+        }
+
+        let _ = self.checkout_requested();
+        self.shared
+            .iter()
+            .find(|shared| Arc::strong_count(&shared.conn) <= shared.limit)
+            .map(|shared| shared.conn.clone())
+    }
+
+    pub(crate) fn add_shared(&mut self, conn: Arc<dyn Any + Send + Sync>, limit: usize) {
+        self.shared.push(SharedConn {
+            conn,
+            limit,
+            since: Instant::now(),
+        });
+    }
+
+    /// Release a reference to a shared connection.
+    ///
+    /// Returns the connection and the time it was first shared if this was the last user.
+    pub(crate) fn unshare(
+        &mut self,
+        conn: Arc<dyn Any + Send + Sync>,
+    ) -> Option<(Arc<dyn Any + Send + Sync>, Instant)> {
+        // The list holds one reference, so we are the last user if there are two
+        if Arc::strong_count(&conn) > 2 {
+            return None;
+        }
+
+        let idx = self
+            .shared
+            .iter()
+            .position(|shared| Arc::ptr_eq(&shared.conn, &conn))?;
+        Some((conn, self.shared.swap_remove(idx).since))
+    }
+
+    /// Take an idle connection, preferring the first one to match `preferred` if given.
+    ///
+    /// With a `label`, the connection is taken from that subset instead. Also returns whether
+    /// the connection was promoted from the cold tier, in which case it must be validated.
     pub(crate) fn pop(
         &mut self,
         config: &Builder<M>,
-    ) -> Option<(Conn<M::Connection>, ApprovalIter)> {
+        preferred: Option<&Preference<M::Connection>>,
+        label: Option<&'static str>,
+    ) -> Option<Popped<M::Connection>> {
+        if self.closed {
+            return None;
+        }
+
+        let matched = match (preferred, config.selection_strategy) {
+            (Some(preferred), _) => {
+                let now = Instant::now();
+                self.conns
+                    .iter()
+                    .position(|idle| preferred(&idle.info(now)))
+            }
+            (None, SelectionStrategy::LeastRecentBackend) => self.least_recent_backend(),
+            (None, SelectionStrategy::Queue) => None,
+        };
+
+        let mut promoted = false;
+        let conn = match (label, matched) {
+            (Some(label), _) => match self.subset_mut(label).conns.pop_front() {
+                Some(idle) => idle.conn,
+                None => self.reclaim_parked(Some(label))?,
+            },
+            (None, Some(idx)) => self.conns.remove(idx)?.conn,
+            (None, None) => match self.conns.pop_front() {
+                Some(idle) => idle.conn,
+                // Promote the most recently demoted cold connection, which is the least stale
+                None => match self.cold.pop_back() {
+                    Some(idle) => {
+                        promoted = true;
+                        idle.conn
+                    }
+                    None => self.reclaim_parked(None)?,
+                },
+            },
+        };
+        let _ = self.checkout_requested();
+        self.checked_out(conn.id, conn.label);
+        if config.selection_strategy == SelectionStrategy::LeastRecentBackend {
+            self.backend_checkouts.insert(conn.backend, Instant::now());
+        }
+        // A purely on-demand pool never replenishes after a checkout
+        let approvals = match config.lazy_only && self.min_idle() == 0 {
+            true => ApprovalIter::new(0),
+            false => self.wanted(),
+        };
+        self.publish();
+        Some((conn, promoted, approvals))
+    }
+
+    /// Park a connection coming back from a caller, keeping it out of the idle queue until
+    /// it is unparked.
+    ///
+    /// Returns the connection if a waiter needs it because the pool has no room for another
+    /// connection, in which case it should be returned instead.
+    pub(crate) fn park(&mut self, conn: Conn<M::Connection>) -> Result<(), Conn<M::Connection>> {
+        let waiting = match conn.label {
+            Some(label) => !self.subset_mut(label).waiters.is_empty(),
+            None => !self.waiters.is_empty(),
+        };
+        if waiting && self.slots.available_permits() == 0 {
+            return Err(conn);
+        }
+
+        // Connections reclaimed while checked out are already accounted for
+        if self.checked_in(conn.id) {
+            self.parked.push_back(conn);
+        }
+        Ok(())
+    }
+
+    /// Take a parked connection back for its caller, unless it was reclaimed
+    pub(crate) fn unpark(&mut self, id: u64) -> Option<Conn<M::Connection>> {
+        let idx = self.parked.iter().position(|conn| conn.id == id)?;
+        let conn = self.parked.remove(idx)?;
+        self.checked_out(conn.id, conn.label);
+        Some(conn)
+    }
+
+    /// Reclaim the connection parked the longest for a caller that found no idle connection,
+    /// if the pool has no room to open another one
+    fn reclaim_parked(&mut self, label: Option<&'static str>) -> Option<Conn<M::Connection>> {
+        if self.slots.available_permits() > 0 {
+            return None;
+        }
+        let idx = self.parked.iter().position(|conn| conn.label == label)?;
+        self.parked.remove(idx)
+    }
+
+    /// The position of the first idle connection to the backend least recently checked out
+    /// from, where backends that were never checked out from come first
+    fn least_recent_backend(&self) -> Option<usize> {
+        let checkouts = &self.backend_checkouts;
         self.conns
-            .pop_front()
-            .map(|idle| (idle.conn, self.wanted(config)))
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, idle)| checkouts.get(&idle.conn.backend))
+            .map(|(idx, _)| idx)
+    }
+
+    /// The IDs of the unlabeled idle connections, to visit in a maintenance sweep
+    pub(crate) fn idle_ids(&self) -> Vec<u64> {
+        let idle = self.cold.iter().chain(&self.conns);
+        idle.map(|idle| idle.conn.id).collect()
+    }
+
+    /// Take the idle connection `id` out of the pool for maintenance, without counting it as
+    /// checked out. It goes back through `put_maintained`, or `dropped` if it failed.
+    pub(crate) fn take_maintained(&mut self, id: u64) -> Option<Conn<M::Connection>> {
+        let conns = match self.cold.iter().any(|idle| idle.conn.id == id) {
+            true => &mut self.cold,
+            false => &mut self.conns,
+        };
+        let idle = conns.remove(conns.iter().position(|idle| idle.conn.id == id)?)?;
+        self.publish();
+        Some(idle.conn)
+    }
+
+    /// Put a connection taken by `take_maintained` back into the pool, or hand it to a waiter
+    ///
+    /// Returns the connections to close, which should be dropped after releasing the lock.
+    #[must_use]
+    pub(crate) fn put_maintained(
+        &mut self,
+        conn: Conn<M::Connection>,
+        pool: Arc<SharedPool<M>>,
+    ) -> Vec<Conn<M::Connection>> {
+        self.requeue(conn, true, pool)
     }
 
+    /// Take an idle connection out of the pool for maintenance, without replacing it.
+    ///
+    /// Connections are taken from the opposite end of the queue than they are returned to,
+    /// starting with the cold tier.
+    pub(crate) fn take_idle(&mut self, config: &Builder<M>) -> Option<Conn<M::Connection>> {
+        let conn = match (self.cold.pop_front(), config.return_to_front) {
+            (Some(idle), _) => idle.conn,
+            (None, true) => self.conns.pop_back()?.conn,
+            (None, false) => self.conns.pop_front()?.conn,
+        };
+        self.checked_out(conn.id, None);
+        self.publish();
+        Some(conn)
+    }
+
+    /// Take the longest idle connection out of the pool for background validation.
+    ///
+    /// Returns `None` while callers are waiting, so that validation never competes with them.
+    pub(crate) fn take_for_validation(
+        &mut self,
+        config: &Builder<M>,
+    ) -> Option<Conn<M::Connection>> {
+        if self.closed || self.suspended || !self.waiters.is_empty() {
+            return None;
+        }
+        self.take_idle(config)
+    }
+
+    /// Put a connection back into the pool, or hand it to a waiter
+    ///
+    /// Returns the connections to close, which should be dropped after releasing the lock.
+    #[must_use]
     pub(crate) fn put(
         &mut self,
         conn: Conn<M::Connection>,
         approval: Option<Approval>,
         pool: Arc<SharedPool<M>>,
-    ) {
-        if approval.is_some() {
-            self.pending_conns -= 1;
-            self.num_conns += 1;
+    ) -> Vec<Conn<M::Connection>> {
+        let returned = approval.is_none();
+        if returned && !self.checked_in(conn.id) {
+            // Reclaimed while checked out, so already accounted for
+            return vec![conn];
+        }
+
+        match returned {
+            true => self.emit(PoolEvent::Returned(conn.id)),
+            false => {
+                self.pending_conns -= 1;
+                self.num_conns += 1;
+                if let Some(label) = conn.label {
+                    let subset = self.subset_mut(label);
+                    subset.pending_conns -= 1;
+                    subset.num_conns += 1;
+                }
+                self.last_connect_failed = false;
+                self.emit(PoolEvent::Created(conn.id));
+            }
+        }
+
+        self.requeue(conn, returned, pool)
+    }
+
+    /// Queue a connection already accounted for, or hand it to a waiter. Whether it was
+    /// `returned`, rather than just established, decides where in the queue it goes.
+    #[must_use]
+    fn requeue(
+        &mut self,
+        conn: Conn<M::Connection>,
+        returned: bool,
+        pool: Arc<SharedPool<M>>,
+    ) -> Vec<Conn<M::Connection>> {
+        // Close connections while the pool is closed or has more than `max_size` of them
+        if self.closed || self.retiring > 0 {
+            self.num_conns -= 1;
+            if let Some(label) = conn.label {
+                self.subset_mut(label).num_conns -= 1;
+            }
+            self.release(1);
+            self.emit(PoolEvent::Closed(conn.id));
+            self.publish();
+            return vec![conn];
         }
 
-        let mut guard = InternalsGuard::new(conn, pool);
-        while let Some(waiter) = self.waiters.pop_front() {
+        let to_front = returned && pool.statics.return_to_front;
+        let (id, label) = (conn.id, conn.label);
+        let waiters = match label {
+            Some(label) => &mut self.subset_mut(label).waiters,
+            None => &mut self.waiters,
+        };
+        let on_delivery = pool.statics.on_waiter_delivery.as_ref().map(|f| &f.0);
+        let notify = |outcome| {
+            if let Some(on_delivery) = on_delivery {
+                on_delivery(outcome);
+            }
+        };
+        let mut guard = Some(InternalsGuard::new(conn, pool.clone(), returned));
+        while let Some(waiter) = waiters.pop() {
             // This connection is no longer idle, send it back out
-            match waiter.send(guard) {
-                Ok(()) => return,
+            match waiter.send(guard.take().unwrap()) {
+                Ok(()) => {
+                    notify(WaiterDeliveryOutcome::Delivered(id));
+                    break;
+                }
                 Err(g) => {
-                    guard = g;
+                    notify(WaiterDeliveryOutcome::WaiterGone(id));
+                    guard = Some(g);
                 }
             }
         }
 
+        let mut guard = match guard {
+            Some(guard) => guard,
+            None => {
+                self.checked_out(id, label);
+                self.publish();
+                return Vec::new();
+            }
+        };
+
         // Queue it in the idle queue
-        self.conns
-            .push_back(IdleConn::from(guard.conn.take().unwrap()));
+        notify(WaiterDeliveryOutcome::Idle(id));
+        let conn = IdleConn::from(guard.conn.take().unwrap());
+        let conns = match label {
+            Some(label) => &mut self.subset_mut(label).conns,
+            None => &mut self.conns,
+        };
+        match to_front {
+            true => conns.push_front(conn),
+            false => conns.push_back(conn),
+        }
+
+        // Demote the longest idle connection of the hot tier once it is over its size
+        if let (None, Some(hot_idle)) = (label, pool.statics.hot_idle) {
+            if self.conns.len() > hot_idle as usize {
+                let demoted = match to_front {
+                    true => self.conns.pop_back(),
+                    false => self.conns.pop_front(),
+                };
+                self.cold.extend(demoted);
+            }
+        }
+
+        // Close the longest idle connections beyond `max_idle` right away
+        let mut excess = Vec::new();
+        if let (None, Some(max_idle)) = (label, pool.statics.max_idle) {
+            excess = self.excess_idle(max_idle);
+            let num = excess.len() as u32;
+            self.num_conns -= num;
+            self.release(num);
+            self.emit_closed(excess.iter().map(|conn| conn.id));
+        }
+        self.publish();
+        excess
     }
 
-    pub(crate) fn connect_failed(&mut self, _: Approval) {
+    /// Take the unlabeled idle connections beyond `max_idle`, or `min_idle` if higher, longest
+    /// idle first
+    fn excess_idle(&mut self, max_idle: u32) -> Vec<Conn<M::Connection>> {
+        let keep = max(max_idle, self.min_idle()) as usize;
+        let mut excess = Vec::new();
+        while self.idle_count() > keep {
+            excess.extend(pop_idle_to_close(&mut self.cold, &mut self.conns).map(|idle| idle.conn));
+        }
+        excess
+    }
+
+    /// Record that an attempt to open a connection failed, which may still be retried
+    pub(crate) fn connect_attempt_failed(&mut self) {
+        self.last_connect_failed = true;
+    }
+
+    pub(crate) fn connect_failed(&mut self, approval: Approval, config: &Builder<M>) {
         self.pending_conns -= 1;
+        self.release(1);
+        self.last_connect_failed = true;
+        if let Some(label) = approval.label {
+            let subset = self.subset_mut(label);
+            subset.pending_conns -= 1;
+            if config.fail_waiters_on_connect_exhaustion
+                && subset.pending_conns == 0
+                && subset.conns.is_empty()
+            {
+                subset.waiters.clear();
+            }
+        } else if config.fail_waiters_on_connect_exhaustion
+            && self.pending_conns == 0
+            && self.idle_count() == 0
+        {
+            // Dropping the senders wakes the waiters with an error
+            self.waiters.clear();
+        }
+        self.publish();
     }
 
-    pub(crate) fn dropped(&mut self, num: u32, config: &Builder<M>) -> ApprovalIter {
+    pub(crate) fn dropped(&mut self, ids: &[u64]) -> ApprovalIter {
+        let num = ids.len() as u32;
         self.num_conns -= num;
-        self.wanted(config)
+        self.release(num);
+        self.emit_closed(ids.iter().copied());
+        // Replacing the connections of a closing pool would reopen them as fast as they go
+        let approvals = match self.closed {
+            true => ApprovalIter::new(0),
+            false => self.wanted(),
+        };
+        self.publish();
+        approvals
     }
 
-    pub(crate) fn wanted(&mut self, config: &Builder<M>) -> ApprovalIter {
-        let available = self.conns.len() as u32 + self.pending_conns;
-        let min_idle = config.min_idle.unwrap_or(0);
-        let wanted = if available < min_idle {
-            min_idle - available
-        } else {
-            0
+    /// Account for a connection that was discarded for being broken or invalid
+    pub(crate) fn broken(
+        &mut self,
+        id: u64,
+        label: Option<&'static str>,
+        config: &Builder<M>,
+    ) -> ApprovalIter {
+        if !self.checked_in(id) {
+            return ApprovalIter::new(0);
+        }
+
+        self.num_conns -= 1;
+        if let Some(label) = label {
+            self.subset_mut(label).num_conns -= 1;
+        }
+        self.release(1);
+        self.emit(PoolEvent::Closed(id));
+
+        let waiting = match label {
+            Some(label) => self
+                .subsets
+                .get(label)
+                .map_or(false, |s| !s.waiters.is_empty()),
+            None => !self.waiters.is_empty(),
         };
+        let mut approvals = self.wanted();
+        if config.replace_broken_eagerly || (config.replace_broken_for_waiters && waiting) {
+            match label {
+                None if approvals.num == 0 => {
+                    let replacement = self.approvals(1).for_demand(waiting as u32);
+                    approvals.merge(replacement);
+                }
+                Some(label) if !approvals.labeled.iter().any(|&(l, _)| l == label) => {
+                    let replacement = self.approvals_labeled(label, 1, waiting);
+                    approvals.merge(replacement);
+                }
+                _ => {}
+            }
+        }
+        self.publish();
+        approvals
+    }
+
+    /// Approve the connections needed to restore `min_idle`, and that of every subset
+    pub(crate) fn wanted(&mut self) -> ApprovalIter {
+        let wanted = self.wanted_count();
+        let mut approvals = self.approvals(wanted);
+        if self.suspended || self.closed {
+            return approvals;
+        }
+
+        let labels = self
+            .subsets
+            .iter()
+            .filter_map(|(&label, subset)| {
+                let available = subset.conns.len() as u32 + subset.pending_conns;
+                let wanted = subset.min_idle.saturating_sub(available);
+                (wanted > 0).then(|| (label, wanted))
+            })
+            .collect::<Vec<_>>();
+        for (label, wanted) in labels {
+            let labeled = self.approvals_labeled(label, wanted, false);
+            approvals.merge(labeled);
+        }
+        approvals
+    }
+
+    /// The number of connections needed to get back to `min_idle`
+    pub(crate) fn wanted_count(&self) -> u32 {
+        if self.suspended || self.closed {
+            return 0;
+        }
 
-        self.approvals(config, wanted)
+        let labeled: u32 = self.subsets.values().map(|s| s.pending_conns).sum();
+        let available = self.idle_count() as u32 + self.pending_conns - labeled;
+        self.min_idle().saturating_sub(available)
     }
 
     pub(crate) fn push_waiter(
         &mut self,
         waiter: oneshot::Sender<InternalsGuard<M>>,
+        tenant: Option<u64>,
+        deadline: Instant,
+        label: Option<&'static str>,
         config: &Builder<M>,
-    ) -> ApprovalIter {
-        self.waiters.push_back(waiter);
-        self.approvals(config, 1)
+    ) -> (usize, ApprovalIter) {
+        let resumed = self.checkout_requested();
+        self.contended = true;
+        let waiters = match label {
+            Some(label) => &mut self.subset_mut(label).waiters,
+            None => &mut self.waiters,
+        };
+        let ahead = waiters.len();
+        match config.deadline_scheduling {
+            true => waiters.push_deadline(deadline, waiter),
+            false => waiters.push(tenant, waiter),
+        }
+        self.publish();
+
+        if let Some(label) = label {
+            let mut approvals = self.approvals_labeled(label, 1, true);
+            if resumed {
+                let wanted = self.wanted();
+                approvals.merge(wanted);
+            }
+            return (ahead, approvals);
+        }
+
+        if self.slots.available_permits() == 0 {
+            self.evict_idle(None);
+        }
+
+        // After an idle shutdown, also start restoring `min_idle`
+        let wanted = match resumed {
+            true => max(self.wanted_count(), 1),
+            false => 1,
+        };
+        (ahead, self.approvals(wanted).for_demand(1))
     }
 
-    fn approvals(&mut self, config: &Builder<M>, num: u32) -> ApprovalIter {
-        let current = self.num_conns + self.pending_conns;
-        let allowed = if current < config.max_size {
-            config.max_size - current
-        } else {
-            0
+    /// Approve a connection to be established by the caller that wants to check it out.
+    ///
+    /// Also returns approvals to restore `min_idle` if this ended an idle shutdown.
+    pub(crate) fn approve_inline(
+        &mut self,
+        label: Option<&'static str>,
+    ) -> Option<(Approval, ApprovalIter)> {
+        let approval = match label {
+            Some(label) => self.approvals_labeled(label, 1, true).next()?,
+            None => self.approvals(1).for_demand(1).next()?,
+        };
+        let replenish = match self.checkout_requested() {
+            true => self.wanted(),
+            false => ApprovalIter::new(0),
         };
+        Some((approval, replenish))
+    }
+
+    /// Account for a connection established inline, which goes straight to its caller
+    pub(crate) fn connected(&mut self, approval: Approval, id: u64) {
+        self.pending_conns -= 1;
+        self.num_conns += 1;
+        if let Some(label) = approval.label {
+            let subset = self.subset_mut(label);
+            subset.pending_conns -= 1;
+            subset.num_conns += 1;
+        }
+        self.last_connect_failed = false;
+        self.emit(PoolEvent::Created(id));
+        self.checked_out(id, approval.label);
+        self.publish();
+    }
+
+    /// Account for an inline connection attempt abandoned by its caller
+    pub(crate) fn connect_canceled(&mut self, approval: Approval) {
+        self.pending_conns -= 1;
+        if let Some(label) = approval.label {
+            self.subset_mut(label).pending_conns -= 1;
+        }
+        self.release(1);
+        self.publish();
+    }
+
+    pub(crate) fn remove_canceled_waiters(&mut self) {
+        self.waiters.retain(|waiter| !waiter.is_canceled());
+        for subset in self.subsets.values_mut() {
+            subset.waiters.retain(|waiter| !waiter.is_canceled());
+        }
+        self.publish();
+    }
+
+    pub(crate) fn approvals(&mut self, num: u32) -> ApprovalIter {
+        let num = self.would_approve(num);
+        if num == 0 {
+            return ApprovalIter::new(0);
+        }
+
+        match self.slots.try_acquire_many(num) {
+            Ok(permits) => permits.forget(),
+            Err(_) => return ApprovalIter::new(0),
+        }
 
-        let num = min(num, allowed);
         self.pending_conns += num;
-        ApprovalIter { num: num as usize }
+        self.publish();
+        ApprovalIter::new(num)
+    }
+
+    /// The number of connections `approvals` would approve, without reserving them
+    pub(crate) fn would_approve(&self, num: u32) -> u32 {
+        match self.closed {
+            true => 0,
+            false => min(num, self.slots.available_permits() as u32),
+        }
+    }
+
+    /// Approve up to `num` connections for the subset `label`, within its own `max_size`
+    fn approvals_labeled(&mut self, label: &'static str, num: u32, demand: bool) -> ApprovalIter {
+        let subset = self.subset_mut(label);
+        let room = subset
+            .max_size
+            .saturating_sub(subset.num_conns + subset.pending_conns);
+        if demand && room > 0 && self.slots.available_permits() == 0 {
+            self.evict_idle(Some(label));
+        }
+        let num = self.approvals(min(num, room)).num;
+        self.subset_mut(label).pending_conns += num as u32;
+
+        let mut approvals = ApprovalIter::new(0);
+        approvals.labeled = vec![(label, demand); num];
+        approvals
+    }
+
+    /// Close an idle connection that is not part of subset `keep`, or not unlabeled if `keep`
+    /// is `None`, to make room under `max_size` for a waiting caller.
+    fn evict_idle(&mut self, keep: Option<&'static str>) {
+        let evicted = match keep {
+            Some(_) => pop_idle_to_close(&mut self.cold, &mut self.conns),
+            None => None,
+        };
+        let evicted = evicted.or_else(|| {
+            self.subsets
+                .iter_mut()
+                .filter(|(&label, _)| Some(label) != keep)
+                .find_map(|(_, subset)| {
+                    let idle = subset.conns.pop_front()?;
+                    subset.num_conns -= 1;
+                    Some(idle)
+                })
+        });
+
+        if let Some(idle) = evicted {
+            self.num_conns -= 1;
+            self.release(1);
+            self.emit(PoolEvent::Closed(idle.conn.id));
+        }
+    }
+
+    /// The subset registered as `label`
+    fn subset_mut(&mut self, label: &'static str) -> &mut Subset<M> {
+        match self.subsets.get_mut(label) {
+            Some(subset) => subset,
+            None => panic!("no subset registered as {:?}", label),
+        }
+    }
+
+    /// Make room for `num` more connections after closing connections or failing to open them
+    fn release(&mut self, num: u32) {
+        let retired = min(num, self.retiring);
+        self.retiring -= retired;
+        self.slots.add_permits((num - retired) as usize);
+    }
+
+    /// The configuration in effect
+    pub(crate) fn settings(&self) -> &PoolConfig {
+        &self.settings
+    }
+
+    /// The minimum number of idle connections in effect, raised by any running boost but
+    /// never above `max_size`
+    pub(crate) fn min_idle(&self) -> u32 {
+        let boosted = self.boosts.iter().copied().max().unwrap_or(0);
+        let min_idle = max(self.settings.min_idle.unwrap_or(0), boosted);
+        min(min_idle, self.settings.max_size)
     }
 
-    pub(crate) fn reap(&mut self, config: &Builder<M>) -> ApprovalIter {
+    /// Start a boost of `min_idle` to `target`, returning the approvals needed to reach it
+    pub(crate) fn boost_min_idle(&mut self, target: u32) -> ApprovalIter {
+        self.boosts.push(target);
+        let approvals = self.wanted();
+        self.publish();
+        approvals
+    }
+
+    /// End a boost started with `boost_min_idle`
+    pub(crate) fn end_boost(&mut self, target: u32) {
+        if let Some(idx) = self.boosts.iter().position(|&boost| boost == target) {
+            self.boosts.swap_remove(idx);
+        }
+    }
+
+    /// Replace the configuration in effect.
+    ///
+    /// Returns the approvals needed to satisfy the new configuration, and the idle connections
+    /// closed to make it fit a lower `max_size`, to be dropped once the lock is released.
+    pub(crate) fn apply(
+        &mut self,
+        settings: PoolConfig,
+    ) -> (ApprovalIter, Vec<Conn<M::Connection>>) {
+        let (old, new) = (self.settings.max_size, settings.max_size);
+        self.settings = settings;
+
+        let mut retired = Vec::new();
+        if new > old {
+            self.release(new - old);
+        }
+        for _ in new..old {
+            // Retire an unused slot, or else the slot of an idle connection, or else the slot of
+            // the next connection to be closed
+            if let Ok(permit) = self.slots.try_acquire() {
+                permit.forget();
+            } else if let Some(idle) = pop_idle_to_close(&mut self.cold, &mut self.conns) {
+                self.num_conns -= 1;
+                self.emit(PoolEvent::Closed(idle.conn.id));
+                retired.push(idle.conn);
+            } else {
+                self.retiring += 1;
+            }
+        }
+
+        let approvals = self.wanted();
+        self.publish();
+        (approvals, retired)
+    }
+
+    /// Close expired idle connections and adjust the pool to recent demand.
+    ///
+    /// The closed connections are returned rather than dropped, so that the caller can drop
+    /// them after releasing the lock: closing a connection may be slow, and a large pool may
+    /// close many at once.
+    pub(crate) fn reap(
+        &mut self,
+        config: &Builder<M>,
+    ) -> (ApprovalIter, ReapSummary, Vec<Conn<M::Connection>>) {
+        let mut summary = ReapSummary::default();
+        let mut expired = Vec::new();
         let now = Instant::now();
-        let before = self.conns.len();
+        let reclaimed = self.reclaim_abandoned(config, now);
+        summary.abandoned = reclaimed.len() as u32;
+        if self.suspended {
+            return (self.dropped(&reclaimed), summary, expired);
+        }
 
-        self.conns.retain(|conn| {
-            let mut keep = true;
-            if let Some(timeout) = config.idle_timeout {
-                keep &= now - conn.idle_start < timeout;
+        let grown = self.autoscale(config, &mut expired);
+        let mut closed = Vec::new();
+        let min_idle = self.min_idle();
+        match config.hot_idle {
+            // The hot tier is kept ready, so only the cold tier is closed for being idle
+            Some(_) => {
+                let hot = PoolConfig {
+                    idle_timeout: None,
+                    ..self.settings.clone()
+                };
+                expire(
+                    &mut self.conns,
+                    min_idle,
+                    &hot,
+                    now,
+                    &mut summary,
+                    &mut closed,
+                    &mut expired,
+                );
+                expire(
+                    &mut self.cold,
+                    min_idle.saturating_sub(self.conns.len() as u32),
+                    &self.settings,
+                    now,
+                    &mut summary,
+                    &mut closed,
+                    &mut expired,
+                );
             }
-            if let Some(lifetime) = config.max_lifetime {
-                keep &= now - conn.conn.birth < lifetime;
+            None => expire(
+                &mut self.conns,
+                min_idle,
+                &self.settings,
+                now,
+                &mut summary,
+                &mut closed,
+                &mut expired,
+            ),
+        }
+        if let Some(max_idle) = config.max_idle {
+            for conn in self.excess_idle(max_idle) {
+                summary.max_idle += 1;
+                closed.push(conn.id);
+                expired.push(conn);
             }
-            keep
+        }
+        for subset in self.subsets.values_mut() {
+            let before = closed.len();
+            expire(
+                &mut subset.conns,
+                subset.min_idle,
+                &self.settings,
+                now,
+                &mut summary,
+                &mut closed,
+                &mut expired,
+            );
+            subset.num_conns -= (closed.len() - before) as u32;
+        }
+
+        closed.extend(reclaimed);
+        let mut approvals = self.dropped(&closed);
+        // Replace abandoned connections that callers are waiting for
+        let waiting = min(summary.abandoned, self.waiters.len() as u32);
+        let wanted = waiting.saturating_sub(approvals.num as u32);
+        let demand = self.approvals(wanted).num;
+        approvals.num += demand;
+        approvals.demand += demand;
+        if grown && !self.waiters.is_empty() {
+            let grown = self.approvals(1).num;
+            approvals.num += grown;
+            approvals.demand += grown;
+        }
+        self.emit(PoolEvent::Reaped(summary));
+        (approvals, summary, expired)
+    }
+
+    /// Stop accounting for connections checked out for longer than `max_checkout_duration`.
+    ///
+    /// Returns the IDs of the reclaimed connections, for which `dropped` must be called.
+    fn reclaim_abandoned(&mut self, config: &Builder<M>, now: Instant) -> Vec<u64> {
+        let (checkouts, max) = match (&mut self.checkouts, config.max_checkout_duration) {
+            (Some(checkouts), Some(max)) => (checkouts, max),
+            _ => return Vec::new(),
+        };
+
+        let mut reclaimed = Vec::new();
+        let subsets = &mut self.subsets;
+        checkouts.retain(|&id, &mut (since, label)| {
+            let abandoned = now - since >= max;
+            if abandoned {
+                reclaimed.push(id);
+                if let Some(subset) = label.and_then(|label| subsets.get_mut(label)) {
+                    subset.num_conns -= 1;
+                }
+            }
+            !abandoned
         });
+        reclaimed
+    }
+
+    /// Adjust the connection cap to the contention seen since the last reaper tick.
+    ///
+    /// Returns whether the cap was raised. A connection closed to lower it is added to `retired`.
+    fn autoscale(&mut self, config: &Builder<M>, retired: &mut Vec<Conn<M::Connection>>) -> bool {
+        let (min_size, max_size) = match config.autoscale {
+            Some(bounds) => bounds,
+            None => return false,
+        };
+
+        let contended = mem::take(&mut self.contended) || !self.waiters.is_empty();
+        self.trend = match (contended, self.idle_count() == 0) {
+            (true, _) => max(self.trend, 0) + 1,
+            (false, false) => min(self.trend, 0) - 1,
+            (false, true) => 0,
+        };
+
+        if self.trend >= AUTOSCALE_GROW_TICKS && self.settings.max_size < max_size {
+            self.trend = 0;
+            self.settings.max_size += 1;
+            self.release(1);
+            return true;
+        }
+
+        if self.trend <= -AUTOSCALE_SHRINK_TICKS && self.settings.max_size > min_size {
+            // Retire an unused slot, or else the slot of an idle connection
+            let retired = match self.slots.try_acquire() {
+                Ok(permit) => {
+                    permit.forget();
+                    true
+                }
+                Err(_) => match pop_idle_to_close(&mut self.cold, &mut self.conns) {
+                    Some(idle) => {
+                        self.num_conns -= 1;
+                        self.emit(PoolEvent::Closed(idle.conn.id));
+                        retired.push(idle.conn);
+                        true
+                    }
+                    None => false,
+                },
+            };
+
+            if retired {
+                self.trend = 0;
+                self.settings.max_size -= 1;
+                self.publish();
+            }
+        }
+        false
+    }
+
+    /// Close all idle connections if no connection has been requested for `idle`.
+    ///
+    /// Returns when the pool should be checked again, or `None` once the pool is closed.
+    /// Returns the next deadline, along with the idle connections to close after releasing the
+    /// lock if the pool was suspended
+    pub(crate) fn idle_shutdown(
+        &mut self,
+        idle: Duration,
+    ) -> (Option<Instant>, Vec<Conn<M::Connection>>) {
+        if self.closed {
+            return (None, Vec::new());
+        }
+
+        let now = Instant::now();
+        let deadline = self.last_checkout + idle;
+        if now < deadline {
+            return (Some(deadline), Vec::new());
+        }
+
+        self.suspended = true;
+        (Some(now + idle), self.close_idle())
+    }
+
+    /// Stop handing out connections, closing idle ones now and the rest once returned.
+    ///
+    /// Returns the idle connections, which should be dropped after releasing the lock.
+    #[must_use]
+    pub(crate) fn close(&mut self) -> Vec<Conn<M::Connection>> {
+        self.closed = true;
+        let mut closed = self.close_idle();
+        // Parked connections are idle too, their tokens no longer unpark them
+        for conn in mem::take(&mut self.parked) {
+            if let Some(label) = conn.label {
+                self.subset_mut(label).num_conns -= 1;
+            }
+            self.num_conns -= 1;
+            self.release(1);
+            self.emit(PoolEvent::Closed(conn.id));
+            closed.push(conn);
+        }
+        // Dropping the senders wakes the waiters with an error
+        self.waiters.clear();
+        for subset in self.subsets.values_mut() {
+            subset.waiters.clear();
+        }
+        self.publish();
+        closed
+    }
+
+    /// Take all idle connections, including those of subsets, to be closed by the caller
+    fn close_idle(&mut self) -> Vec<Conn<M::Connection>> {
+        let mut closed = self
+            .conns
+            .drain(..)
+            .chain(self.cold.drain(..))
+            .map(|idle| idle.conn)
+            .collect::<Vec<_>>();
+        for subset in self.subsets.values_mut() {
+            subset.num_conns -= subset.conns.len() as u32;
+            closed.extend(subset.conns.drain(..).map(|idle| idle.conn));
+        }
+
+        if !closed.is_empty() {
+            let num = closed.len() as u32;
+            self.num_conns -= num;
+            self.release(num);
+            self.emit_closed(closed.iter().map(|conn| conn.id));
+            self.publish();
+        }
+        closed
+    }
+
+    /// The number of unlabeled idle connections, in both tiers
+    fn idle_count(&self) -> usize {
+        self.conns.len() + self.cold.len()
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed
+    }
 
-        self.dropped((before - self.conns.len()) as u32, config)
+    /// Returns whether the pool was suspended after an idle shutdown
+    fn checkout_requested(&mut self) -> bool {
+        self.last_checkout = Instant::now();
+        mem::replace(&mut self.suspended, false)
     }
 
     pub(crate) fn state(&self) -> State {
+        let (idle, waiters) = self.subsets.values().fold(
+            (self.idle_count(), self.waiters.len()),
+            |(idle, waiters), subset| (idle + subset.conns.len(), waiters + subset.waiters.len()),
+        );
         State {
             connections: self.num_conns,
-            idle_connections: self.conns.len() as u32,
+            idle_connections: idle as u32,
+            establishing: self.pending_conns,
+            waiters: waiters as u32,
         }
     }
+
+    pub(crate) fn health(&self) -> PoolHealth {
+        if self.closed || (self.num_conns == 0 && self.last_connect_failed) {
+            return PoolHealth::Unavailable;
+        }
+
+        if self.last_connect_failed {
+            return PoolHealth::Degraded {
+                reason: "the last attempt to open a connection failed".to_owned(),
+            };
+        }
+
+        let waiters = self.waiters.len();
+        let saturated =
+            self.idle_count() == 0 && self.num_conns + self.pending_conns >= self.settings.max_size;
+        if saturated && waiters >= self.settings.max_size as usize {
+            return PoolHealth::Degraded {
+                reason: format!(
+                    "all {} connections are in use with {} callers waiting",
+                    self.num_conns, waiters
+                ),
+            };
+        }
+
+        PoolHealth::Healthy
+    }
+
+    pub(crate) fn subscribe(&self) -> watch::Receiver<State> {
+        self.state_tx.subscribe()
+    }
+
+    pub(crate) fn subscribe_events(&mut self) -> broadcast::Receiver<PoolEvent> {
+        self.observed = true;
+        self.events.subscribe()
+    }
+
+    /// Send `event` to `Pool::events` subscribers, if there ever were any
+    fn emit(&self, event: PoolEvent) {
+        // Every connection closed by the pool is reported here, observed or not
+        if let PoolEvent::Closed(_) = event {
+            self.statistics.record_closed();
+        }
+        if self.observed {
+            let _ = self.events.send(event);
+        }
+    }
+
+    /// Record that connection `id`, from subset `label` if any, was handed out to a caller
+    fn checked_out(&mut self, id: u64, label: Option<&'static str>) {
+        if let Some(checkouts) = &mut self.checkouts {
+            checkouts.insert(id, (Instant::now(), label));
+        }
+        self.statistics.record_checkout();
+        self.emit(PoolEvent::CheckedOut(id));
+    }
+
+    /// Record that connection `id` came back from a caller.
+    ///
+    /// Returns false if the connection was reclaimed while checked out.
+    fn checked_in(&mut self, id: u64) -> bool {
+        match &mut self.checkouts {
+            Some(checkouts) => checkouts.remove(&id).is_some(),
+            None => true,
+        }
+    }
+
+    fn emit_closed(&self, ids: impl Iterator<Item = u64>) {
+        for id in ids {
+            self.emit(PoolEvent::Closed(id));
+        }
+    }
+
+    /// Notify subscribers of the current state
+    fn publish(&self) {
+        #[cfg(feature = "debug-invariants")]
+        self.check_invariants();
+        let state = self.state();
+        #[cfg(feature = "opentelemetry")]
+        self.statistics.state.store(&state);
+        self.state_tx.send_replace(state);
+    }
+
+    /// Check the bookkeeping of connections, which every change to it is followed by a
+    /// `publish` for
+    #[cfg(feature = "debug-invariants")]
+    fn check_invariants(&self) {
+        // Every slot under `max_size` is free, taken by a connection, or still to be retired
+        let slots = self.num_conns + self.pending_conns + self.slots.available_permits() as u32;
+        debug_assert_eq!(
+            slots,
+            self.settings.max_size + self.retiring,
+            "slots in use and free do not add up to max_size"
+        );
+
+        let idle = self.state().idle_connections as usize + self.parked.len();
+        debug_assert!(
+            idle <= self.num_conns as usize,
+            "more idle connections than connections"
+        );
+        if let Some(checkouts) = &self.checkouts {
+            debug_assert!(
+                idle + checkouts.len() <= self.num_conns as usize,
+                "more connections idle and checked out than connections"
+            );
+        }
+
+        let (num, pending) = self
+            .subsets
+            .values()
+            .fold((0, 0), |(num, pending), subset| {
+                debug_assert!(
+                    subset.num_conns + subset.pending_conns <= subset.max_size,
+                    "subset above its max_size"
+                );
+                debug_assert!(
+                    subset.conns.len() <= subset.num_conns as usize,
+                    "more idle connections than connections in subset"
+                );
+                (num + subset.num_conns, pending + subset.pending_conns)
+            });
+        debug_assert!(
+            num <= self.num_conns,
+            "subsets have more connections than the pool"
+        );
+        debug_assert!(
+            pending <= self.pending_conns,
+            "subsets establish more connections than the pool"
+        );
+    }
 }
 
-impl<M> Default for PoolInternals<M>
-where
-    M: ManageConnection,
-{
-    fn default() -> Self {
-        Self {
-            waiters: VecDeque::new(),
-            conns: VecDeque::new(),
-            num_conns: 0,
-            pending_conns: 0,
+/// Close the idle connections in `conns` past their maximum lifetime or idle timeout.
+///
+/// Only idle connections in excess of `min_idle` are reaped for their idle timeout, starting
+/// with the ones that have been idle for the longest wherever they are in the queue, which
+/// with `Builder::return_to_front` is at the back rather than the front; otherwise they
+/// would immediately be replaced. The IDs of the closed connections are added to `closed`, and
+/// the connections themselves to `expired`, to be dropped once the lock is released.
+fn expire<C: Send>(
+    conns: &mut VecDeque<IdleConn<C>>,
+    min_idle: u32,
+    settings: &PoolConfig,
+    now: Instant,
+    summary: &mut ReapSummary,
+    closed: &mut Vec<u64>,
+    expired: &mut Vec<Conn<C>>,
+) {
+    for conn in conns.iter_mut() {
+        conn.clamp(now);
+    }
+
+    if let Some(lifetime) = settings.max_lifetime {
+        take_where(conns, expired, |conn| {
+            let expired = conn.age(now) >= lifetime;
+            if expired {
+                summary.max_lifetime += 1;
+                closed.push(conn.conn.id);
+            }
+            expired
+        });
+    }
+
+    // Pick by how long connections have been idle rather than by their place in the queue,
+    // which depends on `Builder::return_to_front`
+    let grace = settings.min_connection_age_before_reap;
+    let mut reaped = conns
+        .iter()
+        .filter(
+            |conn| match conn.conn.idle_timeout.or(settings.idle_timeout) {
+                Some(timeout) => conn.idle(now) >= timeout && conn.age(now) >= grace,
+                None => false,
+            },
+        )
+        .map(|conn| (conn.idle(now), conn.conn.id))
+        .collect::<Vec<_>>();
+    reaped.sort_unstable_by_key(|&(idle, _)| Reverse(idle));
+    reaped.truncate(conns.len().saturating_sub(min_idle as usize));
+    take_where(conns, expired, |conn| {
+        let reap = reaped.iter().any(|&(_, id)| id == conn.conn.id);
+        if reap {
+            summary.idle_timeout += 1;
+            closed.push(conn.conn.id);
+        }
+        reap
+    });
+}
+
+/// The part of `total` that goes to shard `shard` out of `shards`, the first shards taking
+/// one more each to make up the remainder
+pub(crate) fn apportion(total: u32, shards: u32, shard: u32) -> u32 {
+    total / shards + u32::from(shard < total % shards)
+}
+
+/// A duration in nanoseconds, saturating at about 584 years
+fn nanos(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+}
+
+/// Take the idle connection that is best closed, from the cold tier if possible
+fn pop_idle_to_close<C: Send>(
+    cold: &mut VecDeque<IdleConn<C>>,
+    hot: &mut VecDeque<IdleConn<C>>,
+) -> Option<IdleConn<C>> {
+    cold.pop_front().or_else(|| hot.pop_front())
+}
+
+/// Move the connections matching `f` from `conns` to `taken`, keeping the others in order
+fn take_where<C: Send>(
+    conns: &mut VecDeque<IdleConn<C>>,
+    taken: &mut Vec<Conn<C>>,
+    mut f: impl FnMut(&IdleConn<C>) -> bool,
+) {
+    for _ in 0..conns.len() {
+        let conn = conns.pop_front().unwrap(); // safe: we only visit as many as there are
+        match f(&conn) {
+            true => taken.push(conn.conn),
+            false => conns.push_back(conn),
         }
     }
 }
@@ -170,13 +1475,16 @@ where
 pub(crate) struct InternalsGuard<M: ManageConnection> {
     conn: Option<Conn<M::Connection>>,
     pool: Arc<SharedPool<M>>,
+    /// Whether the connection was used before, rather than just established
+    pub(crate) returned: bool,
 }
 
 impl<M: ManageConnection> InternalsGuard<M> {
-    fn new(conn: Conn<M::Connection>, pool: Arc<SharedPool<M>>) -> Self {
+    fn new(conn: Conn<M::Connection>, pool: Arc<SharedPool<M>>, returned: bool) -> Self {
         Self {
             conn: Some(conn),
             pool,
+            returned,
         }
     }
 
@@ -189,7 +1497,171 @@ impl<M: ManageConnection> Drop for InternalsGuard<M> {
     fn drop(&mut self) {
         if let Some(conn) = self.conn.take() {
             let mut locked = self.pool.internals.lock();
-            locked.put(conn, None, self.pool.clone());
+            let closed = locked.put(conn, None, self.pool.clone());
+            drop(locked);
+            drop(closed);
+        }
+    }
+}
+
+/// The queue of callers waiting for a connection.
+///
+/// Callers may be grouped by tenant: waiters for the same tenant are served in FIFO order, while
+/// connections are handed out round-robin across tenants, so that each tenant with waiters gets
+/// a connection before any tenant gets a second one. Waiters without a tenant share a single
+/// queue, so without tenants this is a plain FIFO queue.
+///
+/// Waiters queued by deadline are kept apart and served first, earliest deadline first.
+pub(crate) struct Waiters<T> {
+    untagged: VecDeque<T>,
+    tenants: HashMap<u64, VecDeque<T>>,
+    /// Queues with at least one waiter, in the order they will be served
+    ready: VecDeque<Option<u64>>,
+    deadlines: BinaryHeap<DeadlineWaiter<T>>,
+    /// Breaks ties between equal deadlines in FIFO order
+    next_seq: u64,
+}
+
+impl<T> Waiters<T> {
+    /// Reserve room for `capacity` waiters that did not ask for fair queueing.
+    ///
+    /// The queues are ring buffers that keep their allocation as they drain, so a pool that
+    /// is sized for its peak queue length does not reallocate under bursts of waiters.
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            untagged: VecDeque::with_capacity(capacity),
+            tenants: HashMap::new(),
+            ready: VecDeque::new(),
+            deadlines: BinaryHeap::new(),
+            next_seq: 0,
+        }
+    }
+
+    fn push_deadline(&mut self, deadline: Instant, waiter: T) {
+        self.deadlines.push(DeadlineWaiter {
+            deadline,
+            seq: self.next_seq,
+            waiter,
+        });
+        self.next_seq += 1;
+    }
+
+    fn push(&mut self, tenant: Option<u64>, waiter: T) {
+        let queue = match tenant {
+            None => &mut self.untagged,
+            Some(key) => self.tenants.entry(key).or_default(),
+        };
+
+        if queue.is_empty() {
+            self.ready.push_back(tenant);
+        }
+        queue.push_back(waiter);
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        if let Some(waiter) = self.deadlines.pop() {
+            return Some(waiter.waiter);
+        }
+
+        let tenant = self.ready.pop_front()?;
+        let queue = match tenant {
+            None => &mut self.untagged,
+            Some(key) => self.tenants.get_mut(&key)?,
+        };
+
+        let waiter = queue.pop_front();
+        match (queue.is_empty(), tenant) {
+            (false, _) => self.ready.push_back(tenant),
+            (true, Some(key)) => {
+                self.tenants.remove(&key);
+            }
+            (true, None) => {}
+        }
+        waiter
+    }
+
+    fn is_empty(&self) -> bool {
+        self.ready.is_empty() && self.deadlines.is_empty()
+    }
+
+    fn len(&self) -> usize {
+        let tenants: usize = self.tenants.values().map(VecDeque::len).sum();
+        self.untagged.len() + tenants + self.deadlines.len()
+    }
+
+    fn clear(&mut self) {
+        self.untagged.clear();
+        self.tenants.clear();
+        self.ready.clear();
+        self.deadlines.clear();
+    }
+
+    fn retain(&mut self, mut f: impl FnMut(&T) -> bool) {
+        let mut deadlines = mem::take(&mut self.deadlines).into_vec();
+        deadlines.retain(|waiter| f(&waiter.waiter));
+        self.deadlines = BinaryHeap::from(deadlines);
+
+        self.untagged.retain(&mut f);
+        self.tenants.retain(|_, queue| {
+            queue.retain(&mut f);
+            !queue.is_empty()
+        });
+
+        let (untagged, tenants) = (&self.untagged, &self.tenants);
+        self.ready.retain(|tenant| match tenant {
+            None => !untagged.is_empty(),
+            Some(key) => tenants.contains_key(key),
+        });
+    }
+}
+
+/// A waiter ordered so that the earliest deadline is the greatest, to pop first from a heap
+struct DeadlineWaiter<T> {
+    deadline: Instant,
+    seq: u64,
+    waiter: T,
+}
+
+impl<T> PartialEq for DeadlineWaiter<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl<T> Eq for DeadlineWaiter<T> {}
+
+impl<T> PartialOrd for DeadlineWaiter<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for DeadlineWaiter<T> {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        (other.deadline, other.seq).cmp(&(self.deadline, self.seq))
+    }
+}
+
+/// A labeled subset of connections, see `Builder::subset`
+struct Subset<M: ManageConnection> {
+    conns: VecDeque<IdleConn<M::Connection>>,
+    waiters: Waiters<oneshot::Sender<InternalsGuard<M>>>,
+    /// Connections open in the subset, idle or checked out
+    num_conns: u32,
+    pending_conns: u32,
+    min_idle: u32,
+    max_size: u32,
+}
+
+impl<M: ManageConnection> Subset<M> {
+    fn new(min_idle: u32, max_size: u32) -> Self {
+        Self {
+            conns: VecDeque::new(),
+            waiters: Waiters::with_capacity(0),
+            num_conns: 0,
+            pending_conns: 0,
+            min_idle,
+            max_size,
         }
     }
 }
@@ -197,17 +1669,60 @@ impl<M: ManageConnection> Drop for InternalsGuard<M> {
 #[must_use]
 pub(crate) struct ApprovalIter {
     num: usize,
+    /// How many of the approvals, handed out first, are for callers waiting on them
+    demand: usize,
+    /// Approvals for labeled subsets, handed out before the others, and whether each is for
+    /// a waiting caller
+    labeled: Vec<(&'static str, bool)>,
+    approved_at: Instant,
+}
+
+impl ApprovalIter {
+    fn new(num: u32) -> Self {
+        Self {
+            num: num as usize,
+            demand: 0,
+            labeled: Vec::new(),
+            approved_at: Instant::now(),
+        }
+    }
+
+    fn merge(&mut self, other: ApprovalIter) {
+        self.num += other.num;
+        self.demand += other.demand;
+        self.labeled.extend(other.labeled);
+    }
+
+    /// Mark up to `num` of the approvals as demand-driven, the rest being for maintenance
+    fn for_demand(mut self, num: u32) -> Self {
+        self.demand = min(num as usize, self.num);
+        self
+    }
 }
 
 impl Iterator for ApprovalIter {
     type Item = Approval;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if let Some((label, demand)) = self.labeled.pop() {
+            return Some(Approval {
+                approved_at: self.approved_at,
+                demand,
+                label: Some(label),
+            });
+        }
+
         match self.num {
             0 => None,
             _ => {
                 self.num -= 1;
-                Some(Approval { _priv: () })
+                let demand = self.demand > 0;
+                self.demand = self.demand.saturating_sub(1);
+                Some(Approval {
+                    approved_at: self.approved_at,
+                    demand,
+                    label: None,
+                })
             }
         }
     }
@@ -215,13 +1730,54 @@ impl Iterator for ApprovalIter {
 
 impl ExactSizeIterator for ApprovalIter {
     fn len(&self) -> usize {
-        self.num
+        self.num + self.labeled.len()
+    }
+}
+
+/// A token bucket holding up to `attempts` connection attempts and refilled at the same
+/// rate every `per`, tracked as the time at which it will be full again
+pub(crate) struct ConnectBudget {
+    per: Duration,
+    interval: Duration,
+    full_at: Instant,
+}
+
+impl ConnectBudget {
+    fn new(attempts: u32, per: Duration) -> Self {
+        Self {
+            per,
+            interval: per / attempts,
+            full_at: Instant::now(),
+        }
+    }
+
+    /// Take an attempt from the bucket, or return how long until one is available
+    pub(crate) fn take(&mut self, now: Instant) -> Result<(), Duration> {
+        let full_at = max(self.full_at, now) + self.interval;
+        match full_at.saturating_duration_since(now + self.per) {
+            wait if wait > Duration::from_secs(0) => Err(wait),
+            _ => {
+                self.full_at = full_at;
+                Ok(())
+            }
+        }
     }
 }
 
 #[must_use]
 pub(crate) struct Approval {
-    _priv: (),
+    approved_at: Instant,
+    /// Whether a caller is waiting on this connection, rather than it maintaining `min_idle`
+    pub(crate) demand: bool,
+    /// The subset the connection is for, if any
+    pub(crate) label: Option<&'static str>,
+}
+
+impl Approval {
+    /// How long ago the connection was approved to be established
+    pub(crate) fn age(&self) -> Duration {
+        self.approved_at.elapsed()
+    }
 }
 
 #[derive(Debug)]
@@ -230,14 +1786,49 @@ where
     C: Send,
 {
     pub(crate) conn: C,
+    /// Assigned by the pool when the connection is established
+    pub(crate) id: u64,
+    /// The subset the connection belongs to, if any
+    pub(crate) label: Option<&'static str>,
     birth: Instant,
+    idle_timeout: Option<Duration>,
+    /// The backend as identified by `ManageConnection::backend`
+    backend: u64,
+    pub(crate) generation: u64,
+    /// Limits the queries in flight on the connection, see `Pool::get_with_permit`
+    query_permits: Option<Arc<Semaphore>>,
 }
 
 impl<C: Send> Conn<C> {
-    pub(crate) fn new(conn: C) -> Self {
+    pub(crate) fn new(
+        conn: C,
+        id: u64,
+        label: Option<&'static str>,
+        idle_timeout: Option<Duration>,
+        backend: u64,
+        generation: u64,
+    ) -> Self {
         Self {
             conn,
+            id,
+            label,
             birth: Instant::now(),
+            idle_timeout,
+            backend,
+            generation,
+            query_permits: None,
+        }
+    }
+
+    /// The query permits of this connection, sized by `permits` on first use.
+    pub(crate) fn query_permits(&mut self, permits: impl FnOnce(&C) -> usize) -> Arc<Semaphore> {
+        match &self.query_permits {
+            Some(semaphore) => semaphore.clone(),
+            None => {
+                let semaphore = Arc::new(Semaphore::new(max(1, permits(&self.conn))));
+                self.query_permits = Some(semaphore.clone());
+                semaphore
+            }
         }
     }
 }
@@ -248,6 +1839,15 @@ impl<C: Send> From<IdleConn<C>> for Conn<C> {
     }
 }
 
+/// A connection in use by `Pool::get_shared` callers.
+///
+/// The connection is type-erased since only pools of `Sync` connections can share them.
+struct SharedConn {
+    conn: Arc<dyn Any + Send + Sync>,
+    limit: usize,
+    since: Instant,
+}
+
 struct IdleConn<C>
 where
     C: Send,
@@ -256,6 +1856,34 @@ where
     idle_start: Instant,
 }
 
+impl<C: Send> IdleConn<C> {
+    fn info(&self, now: Instant) -> ConnInfo<'_, C> {
+        ConnInfo {
+            conn: &self.conn.conn,
+            id: self.conn.id,
+            age: self.age(now),
+            idle: self.idle(now),
+        }
+    }
+
+    fn age(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(self.conn.birth)
+    }
+
+    fn idle(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(self.idle_start)
+    }
+
+    /// Move timestamps that lie after `now` back to `now`.
+    ///
+    /// `Instant` is monotonic, so this only happens to connections adopted with timestamps
+    /// taken elsewhere. Without it, such a connection could not expire before that time.
+    fn clamp(&mut self, now: Instant) {
+        self.conn.birth = min(self.conn.birth, now);
+        self.idle_start = min(self.idle_start, now);
+    }
+}
+
 impl<C: Send> From<Conn<C>> for IdleConn<C> {
     fn from(conn: Conn<C>) -> Self {
         IdleConn {
@@ -265,12 +1893,377 @@ impl<C: Send> From<Conn<C>> for IdleConn<C> {
     }
 }
 
-/// Information about the state of a `Pool`.
+/// An idle connection taken for a caller, whether it was promoted from the cold tier, and the
+/// connections approved to replace it
+pub(crate) type Popped<C> = (Conn<C>, bool, ApprovalIter);
+
+/// A predicate selecting the idle connections a caller would rather be given.
+pub(crate) type Preference<'p, C> = dyn Fn(&ConnInfo<'_, C>) -> bool + 'p;
+
+/// Information about an idle connection, used to pick one with `Pool::get_preferred`.
 #[derive(Debug)]
 #[non_exhaustive]
+pub struct ConnInfo<'a, C> {
+    /// The connection.
+    pub conn: &'a C,
+    /// The ID the pool assigned to the connection, see `PooledConnection::connection_id`.
+    pub id: u64,
+    /// How long ago the connection was established.
+    pub age: Duration,
+    /// How long the connection has been idle.
+    pub idle: Duration,
+}
+
+/// A sample of a `Pool`, see `Builder::metrics_history`.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct TimestampedState {
+    /// When the sample was taken.
+    pub at: SystemTime,
+    /// The state of the pool at the time.
+    pub state: State,
+    /// The counters of the pool at the time.
+    pub statistics: Statistics,
+}
+
+/// Counters describing the activity of a `Pool` since it was created.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Statistics {
+    /// The number of connections established by the pool.
+    pub connections_created: u64,
+    /// The number of new connections abandoned because `connect` kept returning errors
+    /// until the connection timeout elapsed.
+    pub connect_errors: u64,
+    /// The number of new connections abandoned because the connection timeout elapsed
+    /// while an attempt to connect was still in progress.
+    pub connect_timeouts: u64,
+    /// The number of times `Pool::get_or_else` ran its fallback instead of handing out a
+    /// connection.
+    pub fallbacks: u64,
+    /// The number of connections closed by the pool.
+    pub connections_closed: u64,
+    /// The number of times a connection was handed out to a caller.
+    pub checkouts: u64,
+    /// The number of checkouts that gave up because the connection timeout or the deadline
+    /// of the checkout elapsed.
+    pub checkout_timeouts: u64,
+    /// The time from a new connection being approved until it was added to the pool,
+    /// including any retries and backoff.
+    pub connect_latency: Histogram,
+}
+
+impl Statistics {
+    /// Add the statistics of another shard of the same pool
+    pub(crate) fn merge(&mut self, other: &Statistics) {
+        self.connections_created += other.connections_created;
+        self.connect_errors += other.connect_errors;
+        self.connect_timeouts += other.connect_timeouts;
+        self.fallbacks += other.fallbacks;
+        self.connections_closed += other.connections_closed;
+        self.checkouts += other.checkouts;
+        self.checkout_timeouts += other.checkout_timeouts;
+        self.connect_latency.merge(&other.connect_latency);
+    }
+}
+
+/// A distribution of durations.
+///
+/// Durations are counted in buckets with exponentially growing upper bounds, from one
+/// millisecond up to about half a minute, plus a final unbounded bucket.
+#[derive(Clone, Debug, Default)]
+pub struct Histogram {
+    buckets: [u64; HISTOGRAM_BUCKETS],
+    count: u64,
+    sum: Duration,
+}
+
+impl Histogram {
+    fn merge(&mut self, other: &Histogram) {
+        for (bucket, other) in self.buckets.iter_mut().zip(&other.buckets) {
+            *bucket += other;
+        }
+        self.count += other.count;
+        self.sum += other.sum;
+    }
+
+    /// The number of durations recorded.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The sum of all durations recorded.
+    pub fn sum(&self) -> Duration {
+        self.sum
+    }
+
+    /// The upper bound and number of recorded durations for each bucket, in ascending order.
+    ///
+    /// The last bucket has no upper bound.
+    pub fn buckets(&self) -> impl Iterator<Item = (Option<Duration>, u64)> + '_ {
+        self.buckets
+            .iter()
+            .enumerate()
+            .map(|(i, count)| (bucket_bound(i), *count))
+    }
+}
+
+const HISTOGRAM_BUCKETS: usize = 17;
+
+/// The exclusive upper bound of a histogram bucket
+fn bucket_bound(bucket: usize) -> Option<Duration> {
+    match bucket + 1 < HISTOGRAM_BUCKETS {
+        true => Some(Duration::from_millis(1 << bucket)),
+        false => None,
+    }
+}
+
+/// The lock-free counterpart of `Statistics`, updated as the pool is used.
+#[derive(Default)]
+pub(crate) struct AtomicStatistics {
+    connections_created: AtomicU64,
+    connect_errors: AtomicU64,
+    connect_timeouts: AtomicU64,
+    fallbacks: AtomicU64,
+    connections_closed: AtomicU64,
+    checkouts: AtomicU64,
+    checkout_timeouts: AtomicU64,
+    connect_latency: AtomicHistogram,
+    /// The state last published by the internals, for the OpenTelemetry callbacks
+    #[cfg(feature = "opentelemetry")]
+    pub(crate) state: AtomicState,
+}
+
+impl AtomicStatistics {
+    pub(crate) fn record_connection(&self, latency: Duration) {
+        self.connect_latency.record(latency);
+        self.connections_created.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_connect_error(&self) {
+        self.connect_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_connect_timeout(&self) {
+        self.connect_timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_fallback(&self) {
+        self.fallbacks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_closed(&self) {
+        self.connections_closed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_checkout(&self) {
+        self.checkouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_checkout_timeout(&self) {
+        self.checkout_timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> Statistics {
+        Statistics {
+            connections_created: self.connections_created.load(Ordering::Relaxed),
+            connect_errors: self.connect_errors.load(Ordering::Relaxed),
+            connect_timeouts: self.connect_timeouts.load(Ordering::Relaxed),
+            fallbacks: self.fallbacks.load(Ordering::Relaxed),
+            connections_closed: self.connections_closed.load(Ordering::Relaxed),
+            checkouts: self.checkouts.load(Ordering::Relaxed),
+            checkout_timeouts: self.checkout_timeouts.load(Ordering::Relaxed),
+            connect_latency: self.connect_latency.snapshot(),
+        }
+    }
+}
+
+/// The lock-free counterpart of `State`, stored whenever the internals publish their state.
+#[cfg(feature = "opentelemetry")]
+#[derive(Default)]
+pub(crate) struct AtomicState {
+    connections: AtomicU32,
+    idle_connections: AtomicU32,
+    establishing: AtomicU32,
+    waiters: AtomicU32,
+}
+
+#[cfg(feature = "opentelemetry")]
+impl AtomicState {
+    fn store(&self, state: &State) {
+        self.connections.store(state.connections, Ordering::Relaxed);
+        self.idle_connections
+            .store(state.idle_connections, Ordering::Relaxed);
+        self.establishing
+            .store(state.establishing, Ordering::Relaxed);
+        self.waiters.store(state.waiters, Ordering::Relaxed);
+    }
+
+    pub(crate) fn load(&self) -> State {
+        State {
+            connections: self.connections.load(Ordering::Relaxed),
+            idle_connections: self.idle_connections.load(Ordering::Relaxed),
+            establishing: self.establishing.load(Ordering::Relaxed),
+            waiters: self.waiters.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Default)]
+struct AtomicHistogram {
+    buckets: [AtomicU64; HISTOGRAM_BUCKETS],
+    count: AtomicU64,
+    sum_nanos: AtomicU64,
+}
+
+impl AtomicHistogram {
+    fn record(&self, duration: Duration) {
+        let bucket = (0..HISTOGRAM_BUCKETS)
+            .find(|&i| bucket_bound(i).map_or(true, |bound| duration < bound))
+            .unwrap_or(HISTOGRAM_BUCKETS - 1);
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        self.sum_nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> Histogram {
+        let mut buckets = [0; HISTOGRAM_BUCKETS];
+        for (bucket, count) in buckets.iter_mut().zip(&self.buckets) {
+            *bucket = count.load(Ordering::Relaxed);
+        }
+
+        Histogram {
+            buckets,
+            count: self.count.load(Ordering::Relaxed),
+            sum: Duration::from_nanos(self.sum_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// The connections closed by a single run of the reaper, by reason.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ReapSummary {
+    /// The number of connections closed for exceeding the maximum lifetime.
+    pub max_lifetime: u32,
+    /// The number of connections closed for sitting idle past their idle timeout.
+    pub idle_timeout: u32,
+    /// The number of idle connections closed for exceeding `Builder::max_idle`.
+    pub max_idle: u32,
+    /// The number of checked out connections reclaimed for exceeding the maximum checkout
+    /// duration, which are closed once returned.
+    pub abandoned: u32,
+}
+
+/// The outcome of a call to `Pool::warm`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct WarmSummary {
+    /// The number of connections asked for.
+    pub requested: u32,
+    /// The number of connections `max_size` allowed to be opened.
+    pub approved: u32,
+    /// The number of connections established.
+    pub succeeded: u32,
+    /// The number of attempts cut short by the connection timeout, or by the pool being
+    /// dropped.
+    pub failed: u32,
+}
+
+/// Something that happened in a `Pool`, as reported by `Pool::events`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PoolEvent {
+    /// A new connection was established, and given this ID.
+    Created(u64),
+    /// The connection with this ID was closed.
+    Closed(u64),
+    /// The connection with this ID was handed out to a caller.
+    CheckedOut(u64),
+    /// The connection with this ID was returned to the pool.
+    Returned(u64),
+    /// The reaper ran, closing the given connections.
+    Reaped(ReapSummary),
+    /// The subscriber fell behind, and this many events were dropped.
+    Lagged(u64),
+}
+
+/// A readiness signal for a `Pool`, derived from its current state.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PoolHealth {
+    /// The pool is able to hand out connections.
+    Healthy,
+    /// The pool works but is struggling, for instance because every connection is in use
+    /// and at least as many callers as `max_size` are waiting, or because the last attempt
+    /// to open a connection failed.
+    Degraded {
+        /// A description of the problem, meant for humans.
+        reason: String,
+    },
+    /// The pool has been drained, or has no connections and failed to open a new one.
+    Unavailable,
+}
+
+/// The configuration of a `Pool` together with its current `State`.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct PoolInfo {
+    /// The maximum number of connections the pool manages, see `Builder::max_size`.
+    pub max_size: u32,
+    /// The minimum number of idle connections the pool maintains, see `Builder::min_idle`.
+    pub min_idle: Option<u32>,
+    /// The time to wait for a connection, see `Builder::connection_timeout`.
+    pub connection_timeout: Duration,
+    /// The maximum lifetime of a connection, see `Builder::max_lifetime`.
+    pub max_lifetime: Option<Duration>,
+    /// The time after which idle connections are closed, see `Builder::idle_timeout`.
+    pub idle_timeout: Option<Duration>,
+    /// The state of the pool at the time the information was retrieved.
+    pub state: State,
+}
+
+/// The configuration of a `Pool` that can be changed while it is in use, see
+/// `Pool::export_config` and `Pool::apply_config`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PoolConfig {
+    /// The maximum number of connections the pool manages, see `Builder::max_size`.
+    pub max_size: u32,
+    /// The minimum number of idle connections the pool maintains, see `Builder::min_idle`.
+    pub min_idle: Option<u32>,
+    /// The time to wait for a connection, see `Builder::connection_timeout`.
+    pub connection_timeout: Duration,
+    /// The maximum lifetime of a connection, see `Builder::max_lifetime`.
+    pub max_lifetime: Option<Duration>,
+    /// The time after which idle connections are closed, see `Builder::idle_timeout`.
+    pub idle_timeout: Option<Duration>,
+    /// The age below which idle connections are kept, see
+    /// `Builder::min_connection_age_before_reap`.
+    pub min_connection_age_before_reap: Duration,
+}
+
+/// Information about the state of a `Pool`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
 pub struct State {
     /// The number of connections currently being managed by the pool.
     pub connections: u32,
     /// The number of idle connections.
     pub idle_connections: u32,
+    /// The number of connections currently being established.
+    pub establishing: u32,
+    /// The number of callers waiting for a connection.
+    pub waiters: u32,
+}
+
+impl State {
+    /// Add the state of another shard of the same pool
+    pub(crate) fn merge(&mut self, other: &State) {
+        self.connections += other.connections;
+        self.idle_connections += other.idle_connections;
+        self.establishing += other.establishing;
+        self.waiters += other.waiters;
+    }
 }
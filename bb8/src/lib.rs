@@ -35,9 +35,17 @@
 
 mod api;
 pub use api::{
-    Builder, CustomizeConnection, ErrorSink, ManageConnection, NopErrorSink, Pool,
-    PooledConnection, RunError, State,
+    Admission, AdmissionController, AdmitAll, Builder, ConnInfo, ConnectAttemptInfo,
+    ConnectFailure, CustomizeConnection, ErrorSink, Histogram, ManageConnection, NopErrorSink,
+    ParkedConnection, PermittedConnection, Pool, PoolConfig, PoolEvent, PoolHealth, PoolInfo,
+    PooledConnection, ReapSummary, Role, RunError, SelectionStrategy, SharedConnection, State,
+    Statistics, TimestampedState, WaiterDeliveryOutcome, WarmSummary, WeakPool,
 };
 
 mod inner;
 mod internals;
+
+#[cfg(feature = "tower")]
+mod service;
+#[cfg(feature = "tower")]
+pub use service::PoolService;
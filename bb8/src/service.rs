@@ -0,0 +1,110 @@
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_util::future::{self, BoxFuture, FutureExt};
+
+use crate::api::{ManageConnection, Pool, PooledConnection, RunError};
+
+/// A `tower::Service` that hands out connections from a `Pool`.
+///
+/// `poll_ready` only resolves once a connection has been checked out of the pool, so the
+/// service reports itself as not ready while the pool is saturated. The connection reserved
+/// by `poll_ready` is then handed out by the next call to `call`. Calling `call` without
+/// first driving `poll_ready` to readiness falls back to `Pool::get_owned`.
+pub struct PoolService<M>
+where
+    M: ManageConnection,
+{
+    pool: Pool<M>,
+    state: Readiness<M>,
+}
+
+impl<M: ManageConnection> PoolService<M> {
+    /// Create a new `PoolService` that checks out connections from `pool`.
+    pub fn new(pool: Pool<M>) -> Self {
+        Self {
+            pool,
+            state: Readiness::Idle,
+        }
+    }
+
+    /// Returns a reference to the underlying pool.
+    pub fn pool(&self) -> &Pool<M> {
+        &self.pool
+    }
+}
+
+impl<M: ManageConnection> tower_service::Service<()> for PoolService<M> {
+    type Response = PooledConnection<'static, M>;
+    type Error = RunError<M::Error>;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        loop {
+            match &mut self.state {
+                Readiness::Idle => {
+                    let pool = self.pool.clone();
+                    self.state =
+                        Readiness::Acquiring(async move { pool.get_owned().await }.boxed());
+                }
+                Readiness::Acquiring(fut) => {
+                    return match Pin::new(fut).poll(cx) {
+                        Poll::Ready(Ok(conn)) => {
+                            self.state = Readiness::Ready(conn);
+                            Poll::Ready(Ok(()))
+                        }
+                        Poll::Ready(Err(e)) => {
+                            self.state = Readiness::Idle;
+                            Poll::Ready(Err(e))
+                        }
+                        Poll::Pending => Poll::Pending,
+                    }
+                }
+                Readiness::Ready(_) => return Poll::Ready(Ok(())),
+            }
+        }
+    }
+
+    fn call(&mut self, _: ()) -> Self::Future {
+        match std::mem::replace(&mut self.state, Readiness::Idle) {
+            Readiness::Ready(conn) => future::ready(Ok(conn)).boxed(),
+            Readiness::Acquiring(fut) => fut,
+            Readiness::Idle => {
+                let pool = self.pool.clone();
+                async move { pool.get_owned().await }.boxed()
+            }
+        }
+    }
+}
+
+impl<M: ManageConnection> Clone for PoolService<M> {
+    fn clone(&self) -> Self {
+        Self::new(self.pool.clone())
+    }
+}
+
+impl<M: ManageConnection> fmt::Debug for PoolService<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = match self.state {
+            Readiness::Idle => "Idle",
+            Readiness::Acquiring(_) => "Acquiring",
+            Readiness::Ready(_) => "Ready",
+        };
+
+        f.debug_struct("PoolService")
+            .field("pool", &self.pool)
+            .field("state", &state)
+            .finish()
+    }
+}
+
+enum Readiness<M>
+where
+    M: ManageConnection,
+{
+    Idle,
+    Acquiring(BoxFuture<'static, Result<PooledConnection<'static, M>, RunError<M::Error>>>),
+    Ready(PooledConnection<'static, M>),
+}
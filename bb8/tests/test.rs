@@ -5,15 +5,16 @@ use std::iter::FromIterator;
 use std::marker::PhantomData;
 use std::pin::Pin;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 use std::task::Poll;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{error, fmt};
 
 use async_trait::async_trait;
 use futures_channel::oneshot;
 use futures_util::future::{err, lazy, ok, pending, ready, try_join_all, FutureExt};
-use futures_util::stream::{FuturesUnordered, TryStreamExt};
+use futures_util::stream::{FuturesUnordered, StreamExt, TryStreamExt};
+use tokio::sync::Notify;
 use tokio::time::timeout;
 
 #[derive(Debug, PartialEq, Eq)]
@@ -122,7 +123,7 @@ async fn test_max_size_ok() {
             let _ = rx2
                 .map(|r| match r {
                     Ok(v) => Ok((v, conn)),
-                    Err(_) => Err((Error, conn)),
+                    Err(_) => Err((Error, Box::new(conn))),
                 })
                 .await;
         });
@@ -250,6 +251,288 @@ async fn test_drop_on_broken() {
     assert!(DROPPED.load(Ordering::SeqCst));
 }
 
+#[tokio::test]
+async fn test_on_broken() {
+    static BROKEN: AtomicUsize = AtomicUsize::new(0);
+
+    struct Handler;
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            Ok(FakeConnection)
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            true
+        }
+    }
+
+    let pool = Pool::builder()
+        .on_broken(|| {
+            BROKEN.fetch_add(1, Ordering::SeqCst);
+        })
+        .build(Handler)
+        .await
+        .unwrap();
+
+    for _ in 0..3 {
+        let _ = pool.get().await.unwrap();
+    }
+
+    assert_eq!(BROKEN.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn test_on_release() {
+    let held = Arc::new(Mutex::new(Vec::new()));
+
+    let released = held.clone();
+    let pool = Pool::builder()
+        .on_release(move |held| released.lock().unwrap().push(held))
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+
+    let conn = pool.get().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    drop(conn);
+    drop(pool.get().await.unwrap());
+
+    let held = held.lock().unwrap();
+    assert_eq!(held.len(), 2);
+    assert!(held[0] >= Duration::from_millis(50));
+    assert!(held[1] < Duration::from_millis(50));
+}
+
+#[tokio::test]
+async fn test_validate_on_error() {
+    static VALIDATED: AtomicUsize = AtomicUsize::new(0);
+
+    struct Handler;
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            Ok(FakeConnection)
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            match VALIDATED.fetch_add(1, Ordering::SeqCst) {
+                0 => Ok(()),
+                _ => Err(Error),
+            }
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let pool = Pool::builder()
+        .max_size(1)
+        .validate_on_error(true)
+        .build(Handler)
+        .await
+        .unwrap();
+
+    // Healthy connections are handed out and returned without validation
+    drop(pool.get().await.unwrap());
+    assert_eq!(VALIDATED.load(Ordering::SeqCst), 0);
+    assert_eq!(pool.state().idle_connections, 1);
+
+    // The first reported error validates successfully, keeping the connection
+    let mut conn = pool.get().await.unwrap();
+    conn.report_error(&Error);
+    drop(conn);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(VALIDATED.load(Ordering::SeqCst), 1);
+    assert_eq!(pool.state().idle_connections, 1);
+
+    // The second one fails validation and the connection is dropped
+    let mut conn = pool.get().await.unwrap();
+    conn.report_error(&Error);
+    drop(conn);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(VALIDATED.load(Ordering::SeqCst), 2);
+    assert_eq!(pool.state().connections, 0);
+}
+
+#[tokio::test]
+async fn test_get_shared() {
+    #[derive(Default)]
+    struct Handler {
+        created: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = usize;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            Ok(self.created.fetch_add(1, Ordering::SeqCst))
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+
+        fn max_concurrent(&self, _: &Self::Connection) -> usize {
+            2
+        }
+    }
+
+    let pool = Pool::builder()
+        .max_size(2)
+        .build(Handler::default())
+        .await
+        .unwrap();
+
+    let first = pool.get_shared().await.unwrap();
+    let second = pool.get_shared().await.unwrap();
+    let third = pool.get_shared().await.unwrap();
+    assert_eq!(*first, *second);
+    assert_ne!(*first, *third);
+    assert_eq!(pool.state().idle_connections, 0);
+
+    // The connection is only returned once all of its users are done with it
+    drop(first);
+    assert_eq!(pool.state().idle_connections, 0);
+    drop(second);
+    assert_eq!(pool.state().idle_connections, 1);
+
+    // The remaining shared connection still has room for another user
+    let fourth = pool.get_shared().await.unwrap();
+    assert_eq!(*fourth, *third);
+    assert_eq!(pool.state().idle_connections, 1);
+}
+
+#[tokio::test]
+async fn test_get_with_permit() {
+    #[derive(Default)]
+    struct Handler {
+        created: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = usize;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            Ok(self.created.fetch_add(1, Ordering::SeqCst))
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+
+        fn query_concurrency(&self, _: &Self::Connection) -> usize {
+            2
+        }
+    }
+
+    let pool = Pool::builder()
+        .max_size(1)
+        .build(Handler::default())
+        .await
+        .unwrap();
+
+    // The guard holds one of the two permits, leaving room for one more query
+    let conn = pool.get_with_permit().await.unwrap();
+    assert_eq!(conn.available_permits(), 1);
+    let extra = conn.try_acquire().unwrap();
+    assert!(conn.try_acquire().is_none());
+    assert_eq!(conn.available_permits(), 0);
+
+    // Acquiring waits until a permit is released
+    assert!(timeout(Duration::from_millis(50), conn.acquire())
+        .await
+        .is_err());
+    let (acquired, ()) = tokio::join!(conn.acquire(), async {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        drop(extra);
+    });
+    assert_eq!(conn.available_permits(), 0);
+    drop(acquired);
+    assert_eq!(conn.available_permits(), 1);
+
+    // Dropping the guard releases both its permit and the connection
+    let id = conn.connection_id();
+    drop(conn);
+    assert_eq!(pool.state().idle_connections, 1);
+    let conn = pool.get_with_permit().await.unwrap();
+    assert_eq!(conn.connection_id(), id);
+    assert_eq!(*conn, 0);
+    assert_eq!(conn.available_permits(), 1);
+    drop(conn);
+
+    // A permit outliving its guard still counts against the connection
+    let conn = pool.get_with_permit().await.unwrap();
+    let extra = conn.try_acquire().unwrap();
+    drop(conn);
+    let conn = pool.get_with_permit().await.unwrap();
+    assert_eq!(conn.available_permits(), 0);
+    drop(extra);
+    assert_eq!(conn.available_permits(), 1);
+}
+
+#[tokio::test]
+async fn test_replace_broken_eagerly() {
+    struct Handler;
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            Ok(FakeConnection)
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            true
+        }
+    }
+
+    for eager in [false, true] {
+        let pool = Pool::builder()
+            .replace_broken_eagerly(eager)
+            .build(Handler)
+            .await
+            .unwrap();
+        {
+            let _ = pool.get().await.unwrap();
+        }
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(pool.state().idle_connections, eager as u32);
+    }
+}
+
 #[tokio::test]
 async fn test_initialization_failure() {
     let manager = NthConnectionFailManager::<FakeConnection>::new(0);
@@ -262,6 +545,24 @@ async fn test_initialization_failure() {
     assert_eq!(res.unwrap_err(), Error);
 }
 
+#[tokio::test]
+async fn test_build_with_manager_factory() {
+    let pool = Pool::builder()
+        .min_idle(Some(1))
+        .build_with_manager_factory(async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            Ok::<_, Error>(OkManager::<FakeConnection>::new())
+        })
+        .await
+        .unwrap();
+    assert_eq!(pool.state().idle_connections, 1);
+
+    let res = Pool::builder()
+        .build_with_manager_factory(ready(Err::<OkManager<FakeConnection>, _>(Error)))
+        .await;
+    assert_eq!(res.unwrap_err(), Error);
+}
+
 #[tokio::test]
 async fn test_lazy_initialization_failure() {
     let manager = NthConnectionFailManager::<FakeConnection>::new(0);
@@ -270,7 +571,32 @@ async fn test_lazy_initialization_failure() {
         .build_unchecked(manager);
 
     let res = pool.get().await;
-    assert_eq!(res.unwrap_err(), RunError::TimedOut);
+    match res {
+        Err(RunError::TimedOutWithState(state)) => {
+            assert_eq!(state.connections, 0);
+            assert_eq!(state.waiters, 0);
+        }
+        _ => panic!("expected a timeout"),
+    }
+}
+
+#[tokio::test]
+async fn test_fail_waiters_on_connect_exhaustion() {
+    let manager = NthConnectionFailManager::<FakeConnection>::new(0);
+    let pool = Pool::builder()
+        .max_size(1)
+        .min_idle(Some(1))
+        .connection_timeout(Duration::from_secs(1))
+        .min_connect_backoff(Duration::from_millis(10))
+        .fail_waiters_on_connect_exhaustion(true)
+        .build_unchecked(manager);
+
+    // Start waiting while the only connection attempt is still being retried
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    let res = timeout(Duration::from_millis(900), pool.get())
+        .await
+        .unwrap();
+    assert_eq!(res.unwrap_err(), RunError::ConnectFailed);
 }
 
 #[tokio::test]
@@ -291,7 +617,7 @@ async fn test_get_timeout() {
         let _ = rx2
             .map(|r| match r {
                 Ok(v) => Ok((v, conn)),
-                Err(_) => Err((Error, conn)),
+                Err(_) => Err((Error, Box::new(conn))),
             })
             .await;
     });
@@ -305,6 +631,66 @@ async fn test_get_timeout() {
     ready(r).await.unwrap();
 }
 
+#[tokio::test]
+async fn test_get_with_cancel() {
+    let pool = Pool::builder()
+        .max_size(1)
+        .connection_timeout(Duration::from_secs(10))
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+
+    let conn = pool.get().await.unwrap();
+    let (tx, rx) = oneshot::channel::<()>();
+    let clone = pool.clone();
+    let waiting = tokio::spawn(async move {
+        clone
+            .get_with_cancel(rx.map(|_| ()))
+            .await
+            .map(|_| ())
+            .unwrap_err()
+    });
+
+    tx.send(()).unwrap();
+    let res = timeout(Duration::from_secs(1), waiting).await.unwrap();
+    assert_eq!(res.unwrap(), RunError::Cancelled);
+
+    // The cancelled waiter no longer holds on to returned connections
+    drop(conn);
+    timeout(Duration::from_secs(1), pool.get())
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_get_fair() {
+    let pool = Pool::builder()
+        .max_size(1)
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+    let served = Arc::new(Mutex::new(Vec::new()));
+
+    let conn = pool.get().await.unwrap();
+    let mut handles = Vec::new();
+    for (i, tenant) in ["a", "a", "a", "b"].iter().enumerate() {
+        let (pool, served) = (pool.clone(), served.clone());
+        handles.push(tokio::spawn(async move {
+            let _conn = pool.get_fair(tenant).await.unwrap();
+            served.lock().unwrap().push(i);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }));
+        // Make sure the waiters are queued in order
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    drop(conn);
+    try_join_all(handles).await.unwrap();
+    // Tenant "b" is served before tenant "a" gets a second connection
+    assert_eq!(*served.lock().unwrap(), vec![0, 3, 1, 2]);
+}
+
 #[tokio::test]
 async fn test_now_invalid() {
     static INVALID: AtomicBool = AtomicBool::new(false);
@@ -357,7 +743,7 @@ async fn test_now_invalid() {
         let _ = rx2
             .map(|r| match r {
                 Ok(v) => Ok((v, conn)),
-                Err(_) => Err((Error, conn)),
+                Err(_) => Err((Error, Box::new(conn))),
             })
             .await;
     });
@@ -371,7 +757,7 @@ async fn test_now_invalid() {
         let _ = rx4
             .map(|r| match r {
                 Ok(v) => Ok((v, conn)),
-                Err(_) => Err((Error, conn)),
+                Err(_) => Err((Error, Box::new(conn))),
             })
             .await;
     });
@@ -431,7 +817,7 @@ async fn test_max_lifetime() {
         let _ = rx2
             .map(|r| match r {
                 Ok(v) => Ok((v, conn)),
-                Err(_) => Err((Error, conn)),
+                Err(_) => Err((Error, Box::new(conn))),
             })
             .await;
     });
@@ -453,19 +839,94 @@ async fn test_max_lifetime() {
 }
 
 #[tokio::test]
-async fn test_min_idle() {
+async fn test_connection_idle_timeout() {
+    static CREATED: AtomicUsize = AtomicUsize::new(0);
+
+    struct Handler;
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = usize;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            Ok(CREATED.fetch_add(1, Ordering::SeqCst))
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+
+        fn idle_timeout(&self, conn: &mut Self::Connection) -> Option<Duration> {
+            // Only the first connection is short-lived
+            match *conn {
+                0 => Some(Duration::from_secs(1)),
+                _ => None,
+            }
+        }
+    }
+
     let pool = Pool::builder()
-        .max_size(5)
-        .min_idle(Some(2))
-        .build(OkManager::<FakeConnection>::new())
+        .max_size(2)
+        .reaper_rate(Duration::from_secs(1))
+        .build(Handler)
         .await
         .unwrap();
 
+    {
+        let _first = pool.get().await.unwrap();
+        let _second = pool.get().await.unwrap();
+    }
+    assert_eq!(pool.state().idle_connections, 2);
+
+    // The global idle timeout is 10 minutes, so only the first connection is reaped.
+    assert!(timeout(Duration::from_secs(3), pending::<()>())
+        .await
+        .is_err());
     let state = pool.state();
-    assert_eq!(2, state.idle_connections);
-    assert_eq!(2, state.connections);
+    assert_eq!(state.idle_connections, 1);
+    assert_eq!(*pool.get().await.unwrap(), 1);
+}
 
-    let mut rx = Vec::with_capacity(3);
+#[tokio::test]
+async fn test_config_getters() {
+    let pool = Pool::builder()
+        .max_size(7)
+        .min_idle(Some(1))
+        .test_on_check_out(false)
+        .max_lifetime(None)
+        .idle_timeout(Some(Duration::from_secs(5)))
+        .connection_timeout(Duration::from_secs(2))
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+
+    assert_eq!(pool.max_size(), 7);
+    assert_eq!(pool.min_idle(), Some(1));
+    assert!(!pool.test_on_check_out());
+    assert_eq!(pool.max_lifetime(), None);
+    assert_eq!(pool.idle_timeout(), Some(Duration::from_secs(5)));
+    assert_eq!(pool.connection_timeout(), Duration::from_secs(2));
+}
+
+#[tokio::test]
+async fn test_min_idle() {
+    let pool = Pool::builder()
+        .max_size(5)
+        .min_idle(Some(2))
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+
+    let state = pool.state();
+    assert_eq!(2, state.idle_connections);
+    assert_eq!(2, state.connections);
+
+    let mut rx = Vec::with_capacity(3);
     let mut tx = Vec::with_capacity(3);
     for _ in 0..3 {
         let (tx1, rx1) = oneshot::channel();
@@ -477,7 +938,7 @@ async fn test_min_idle() {
             let _ = rx2
                 .map(|r| match r {
                     Ok(v) => Ok((v, conn)),
-                    Err(_) => Err((Error, conn)),
+                    Err(_) => Err((Error, Box::new(conn))),
                 })
                 .await;
         });
@@ -507,6 +968,260 @@ async fn test_min_idle() {
     assert_eq!(5, state.connections);
 }
 
+#[tokio::test]
+async fn test_on_reap() {
+    let cases = [
+        (Some(Duration::from_millis(100)), None, (2, 0)),
+        (None, Some(Duration::from_millis(100)), (0, 2)),
+    ];
+
+    for (max_lifetime, idle_timeout, expected) in cases {
+        let reaped = Arc::new(Mutex::new((0, 0)));
+        let summaries = reaped.clone();
+        let pool = Pool::builder()
+            .max_size(2)
+            .max_lifetime(max_lifetime)
+            .idle_timeout(idle_timeout)
+            .reaper_rate(Duration::from_millis(50))
+            .on_reap(move |summary| {
+                let mut reaped = summaries.lock().unwrap();
+                reaped.0 += summary.max_lifetime;
+                reaped.1 += summary.idle_timeout;
+            })
+            .build(OkManager::<FakeConnection>::new())
+            .await
+            .unwrap();
+        pool.warm(2).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(*reaped.lock().unwrap(), expected);
+        assert_eq!(pool.state().connections, 0);
+    }
+}
+
+#[tokio::test]
+async fn test_idle_timeout_respects_min_idle() {
+    static CREATED: AtomicUsize = AtomicUsize::new(0);
+
+    struct Handler;
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            CREATED.fetch_add(1, Ordering::SeqCst);
+            Ok(FakeConnection)
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let pool = Pool::builder()
+        .max_size(5)
+        .min_idle(Some(2))
+        .idle_timeout(Some(Duration::from_secs(1)))
+        .reaper_rate(Duration::from_secs(1))
+        .build(Handler)
+        .await
+        .unwrap();
+
+    {
+        let mut conns = Vec::new();
+        for _ in 0..5 {
+            conns.push(pool.get().await.unwrap());
+        }
+    }
+    assert_eq!(pool.state().idle_connections, 5);
+
+    assert!(timeout(Duration::from_secs(3), pending::<()>())
+        .await
+        .is_err());
+
+    // Only the excess connections were reaped, none had to be reopened
+    let state = pool.state();
+    assert_eq!(state.idle_connections, 2);
+    assert_eq!(state.connections, 2);
+    assert_eq!(CREATED.load(Ordering::SeqCst), 5);
+}
+
+#[tokio::test]
+async fn test_establishing() {
+    struct SlowManager;
+
+    #[async_trait]
+    impl ManageConnection for SlowManager {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Ok(FakeConnection)
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let pool = Pool::builder()
+        .max_size(5)
+        .build(SlowManager)
+        .await
+        .unwrap();
+
+    let gets = (0..10)
+        .map(|_| {
+            let pool = pool.clone();
+            tokio::spawn(async move {
+                let _ = pool.get().await.unwrap();
+            })
+        })
+        .collect::<Vec<_>>();
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(pool.state().establishing, 5);
+    try_join_all(gets).await.unwrap();
+    let state = pool.state();
+    assert_eq!(state.establishing, 0);
+    assert_eq!(state.connections, 5);
+
+    // Failed connections are no longer counted once they give up
+    let pool = Pool::builder()
+        .max_size(3)
+        .min_idle(Some(3))
+        .connection_timeout(Duration::from_millis(500))
+        .build_unchecked(NthConnectionFailManager::<FakeConnection>::new(0));
+    assert_eq!(pool.state().establishing, 3);
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let state = pool.state();
+    assert_eq!(state.establishing, 0);
+    assert_eq!(state.connections, 0);
+}
+
+#[tokio::test]
+async fn test_warm() {
+    let pool = Pool::builder()
+        .max_size(5)
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+    let summary = pool.warm(3).await.unwrap();
+    assert_eq!((summary.approved, summary.succeeded), (3, 3));
+    assert_eq!(pool.state().idle_connections, 3);
+
+    // Never opens more than max_size
+    let summary = pool.warm(10).await.unwrap();
+    assert_eq!(summary.requested, 10);
+    assert_eq!(
+        (summary.approved, summary.succeeded, summary.failed),
+        (2, 2, 0)
+    );
+    assert_eq!(pool.state().idle_connections, 5);
+
+    let pool = Pool::builder()
+        .max_size(5)
+        .connection_timeout(Duration::from_millis(100))
+        .build(NthConnectionFailManager::<FakeConnection>::new(1))
+        .await
+        .unwrap();
+    let errors = pool.warm(3).await.unwrap_err();
+    assert_eq!(errors, vec![Error, Error]);
+    assert_eq!(pool.state().idle_connections, 1);
+}
+
+#[tokio::test]
+async fn test_subscribe() {
+    let pool = Pool::builder()
+        .max_size(2)
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+
+    let mut rx = pool.subscribe();
+    assert_eq!(rx.borrow().connections, 0);
+
+    let conn = pool.get().await.unwrap();
+    rx.changed().await.unwrap();
+    assert_eq!(rx.borrow_and_update().connections, 1);
+
+    drop(conn);
+    rx.changed().await.unwrap();
+    let state = *rx.borrow_and_update();
+    assert_eq!(state.connections, 1);
+    assert_eq!(state.idle_connections, 1);
+}
+
+#[tokio::test]
+async fn test_pool_idle_shutdown() {
+    let pool = Pool::builder()
+        .max_size(5)
+        .min_idle(Some(2))
+        .pool_idle_shutdown(Some(Duration::from_millis(500)))
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+    assert_eq!(pool.state().idle_connections, 2);
+
+    // All connections are closed and not replenished for min_idle
+    tokio::time::sleep(Duration::from_millis(1200)).await;
+    assert_eq!(pool.state().connections, 0);
+
+    // The next checkout reopens connections
+    drop(pool.get().await.unwrap());
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(pool.state().idle_connections, 2);
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_insert_test_connection() {
+    struct Handler;
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = usize;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            Err(Error)
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, conn: &mut Self::Connection) -> bool {
+            *conn == 0
+        }
+    }
+
+    let pool = Pool::builder().max_size(2).build_unchecked(Handler);
+    pool.insert_test_connection(0).unwrap();
+    pool.insert_test_connection(1).unwrap();
+    assert_eq!(pool.insert_test_connection(2), Err(2));
+
+    let state = pool.state();
+    assert_eq!(state.connections, 2);
+    assert_eq!(state.idle_connections, 2);
+
+    // The "broken" connection is dropped on return
+    drop(pool.get().await.unwrap());
+    assert_eq!(pool.state().idle_connections, 1);
+    assert_eq!(*pool.get().await.unwrap(), 1);
+}
+
 #[tokio::test]
 async fn test_conns_drop_on_pool_drop() {
     static DROPPED: AtomicUsize = AtomicUsize::new(0);
@@ -606,32 +1321,75 @@ async fn test_retry() {
 }
 
 #[tokio::test]
-async fn test_conn_fail_once() {
-    static FAILED_ONCE: AtomicBool = AtomicBool::new(false);
-    static NB_CALL: AtomicUsize = AtomicUsize::new(0);
+async fn test_run_with_retry() {
+    static CREATED: AtomicUsize = AtomicUsize::new(0);
 
-    struct Connection;
     struct Handler;
 
-    impl Connection {
-        fn inc(&mut self) {
-            NB_CALL.fetch_add(1, Ordering::SeqCst);
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = usize;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            Ok(CREATED.fetch_add(1, Ordering::SeqCst))
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+
+        fn is_transient(&self, _: &Error) -> bool {
+            true
         }
     }
 
+    let pool = Pool::builder().max_size(1).build(Handler).await.unwrap();
+
+    // Fails on the first two connections, succeeds on the third
+    let used = pool
+        .run_with_retry(2, |conn| {
+            let id = *conn;
+            async move {
+                match id {
+                    0 | 1 => Err(Error),
+                    _ => Ok(id),
+                }
+            }
+            .boxed()
+        })
+        .await
+        .unwrap();
+    assert_eq!(used, 2);
+    assert_eq!(pool.state().connections, 1);
+
+    // Gives up once the retries are used up
+    let res = pool
+        .run_with_retry(1, |_| async { Err::<(), _>(Error) }.boxed())
+        .await;
+    assert_eq!(res.unwrap_err(), RunError::User(Error));
+    assert_eq!(CREATED.load(Ordering::SeqCst), 4);
+    assert_eq!(pool.state().connections, 0);
+}
+
+#[tokio::test]
+async fn test_for_each_idle() {
+    #[derive(Default)]
+    struct Handler {
+        created: AtomicUsize,
+    }
+
     #[async_trait]
     impl ManageConnection for Handler {
-        type Connection = Connection;
+        type Connection = usize;
         type Error = Error;
 
         async fn connect(&self) -> Result<Self::Connection, Self::Error> {
-            // only fail once so the retry should work
-            if FAILED_ONCE.load(Ordering::SeqCst) {
-                Ok(Connection)
-            } else {
-                FAILED_ONCE.store(true, Ordering::SeqCst);
-                Err(Error)
-            }
+            Ok(self.created.fetch_add(1, Ordering::SeqCst))
         }
 
         async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
@@ -643,131 +1401,3329 @@ async fn test_conn_fail_once() {
         }
     }
 
-    let pool = Pool::builder().max_size(1).build(Handler).await.unwrap();
+    let pool = Pool::builder()
+        .max_size(3)
+        .build(Handler::default())
+        .await
+        .unwrap();
+    pool.warm(3).await.unwrap();
+    let checkouts = pool.statistics().checkouts;
 
-    for _ in 0..2 {
-        pool.get().await.unwrap().inc();
-    }
+    // Every idle connection is visited once; the one that fails is dropped
+    let visited = AtomicUsize::new(0);
+    pool.for_each_idle(|conn| {
+        visited.fetch_add(1, Ordering::SeqCst);
+        let res = match *conn {
+            1 => Err(Error),
+            _ => {
+                *conn += 10;
+                Ok(())
+            }
+        };
+        async move { res }.boxed()
+    })
+    .await;
 
-    assert_eq!(NB_CALL.load(Ordering::SeqCst), 2);
+    assert_eq!(visited.load(Ordering::SeqCst), 3);
+    let state = pool.state();
+    assert_eq!(state.connections, 2);
+    assert_eq!(state.idle_connections, 2);
+    // Visiting a connection does not count as checking it out
+    assert_eq!(pool.statistics().checkouts, checkouts);
+
+    let (first, second) = (pool.get().await.unwrap(), pool.get().await.unwrap());
+    let mut ids = vec![*first, *second];
+    ids.sort_unstable();
+    assert_eq!(ids, vec![10, 12]);
+
+    // Connections moving between the hot and cold tiers are still visited once each
+    let pool = Pool::builder()
+        .max_size(3)
+        .hot_idle(Some(1))
+        .build(Handler::default())
+        .await
+        .unwrap();
+    pool.warm(3).await.unwrap();
+    let visited = Mutex::new(Vec::new());
+    pool.for_each_idle(|conn| {
+        visited.lock().unwrap().push(*conn);
+        async move { Ok(()) }.boxed()
+    })
+    .await;
+    let mut visited = visited.into_inner().unwrap();
+    visited.sort_unstable();
+    assert_eq!(visited, vec![0, 1, 2]);
+    assert_eq!(pool.state().idle_connections, 3);
 }
 
-// This mimics the test_acquire_release test, but using the `get()` API.
 #[tokio::test]
-async fn test_guard() {
+async fn test_autoscale() {
     let pool = Pool::builder()
-        .max_size(2)
+        .autoscale(1, 3)
+        .reaper_rate(Duration::from_millis(50))
+        .connection_timeout(Duration::from_secs(5))
         .build(OkManager::<FakeConnection>::new())
         .await
         .unwrap();
 
-    let (tx1, rx1) = oneshot::channel();
-    let (tx2, rx2) = oneshot::channel();
-    let clone = pool.clone();
-    tokio::spawn(async move {
-        let conn = clone.get().await;
-        tx1.send(()).unwrap();
-        let res = rx2
-            .then(|r| match r {
-                Ok(()) => ok(()),
-                Err(_) => err(Error),
-            })
-            .await
-            .map(|_| ());
-        drop(conn);
-        res
-    });
+    // The cap starts at the lower bound, and is raised while callers are waiting
+    let first = pool.get().await.unwrap();
+    let second = timeout(Duration::from_secs(1), pool.get())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(pool.state().connections, 2);
 
-    let (tx3, rx3) = oneshot::channel();
-    let (tx4, rx4) = oneshot::channel();
-    let clone = pool.clone();
-    tokio::spawn(async move {
-        let conn = clone.get().await;
-        tx3.send(()).unwrap();
-        let res = rx4
-            .then(|r| match r {
-                Ok(()) => ok(()),
-                Err(_) => err(Error),
-            })
+    // Once connections sit idle, it is lowered again
+    drop((first, second));
+    tokio::time::sleep(Duration::from_millis(800)).await;
+    let state = pool.state();
+    assert_eq!(state.connections, 1);
+    assert_eq!(state.idle_connections, 1);
+}
+
+#[tokio::test]
+async fn test_return_to_front() {
+    #[derive(Default)]
+    struct Handler {
+        created: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = usize;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            Ok(self.created.fetch_add(1, Ordering::SeqCst))
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    for (return_to_front, expected) in [(false, 0), (true, 1)] {
+        let pool = Pool::builder()
+            .max_size(2)
+            .return_to_front(return_to_front)
+            .build(Handler::default())
+            .await
+            .unwrap();
+        pool.warm(2).await.unwrap();
+
+        // Return `first` before `second`, then see which one is handed out next
+        let (first, second) = (pool.get().await.unwrap(), pool.get().await.unwrap());
+        let ids = [*first, *second];
+        drop(first);
+        drop(second);
+        assert_eq!(*pool.get().await.unwrap(), ids[expected]);
+    }
+
+    // The reaper still closes the connection idle the longest, now at the back of the queue
+    let pool = Pool::builder()
+        .max_size(2)
+        .min_idle(Some(1))
+        .return_to_front(true)
+        .idle_timeout(Some(Duration::from_millis(50)))
+        .reaper_rate(Duration::from_millis(200))
+        .build(Handler::default())
+        .await
+        .unwrap();
+    let (first, second) = (pool.get().await.unwrap(), pool.get().await.unwrap());
+    let kept = *second;
+    drop(first);
+    tokio::time::sleep(Duration::from_millis(30)).await;
+    drop(second);
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    assert_eq!(pool.state().connections, 1);
+    assert_eq!(*pool.get().await.unwrap(), kept);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_max_size_under_churn() {
+    static LIVE: AtomicUsize = AtomicUsize::new(0);
+    static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+    struct Connection(usize);
+
+    impl Drop for Connection {
+        fn drop(&mut self) {
+            LIVE.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    #[derive(Default)]
+    struct Handler {
+        created: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = Connection;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            let live = LIVE.fetch_add(1, Ordering::SeqCst) + 1;
+            PEAK.fetch_max(live, Ordering::SeqCst);
+            Ok(Connection(self.created.fetch_add(1, Ordering::SeqCst)))
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, conn: &mut Self::Connection) -> bool {
+            conn.0 % 3 == 0
+        }
+    }
+
+    let pool = Pool::builder()
+        .max_size(5)
+        .min_idle(Some(2))
+        .replace_broken_eagerly(true)
+        .connection_timeout(Duration::from_secs(2))
+        .build(Handler::default())
+        .await
+        .unwrap();
+
+    // With a short timeout a checkout left waiting fails fast, naming itself
+    let mut tasks = Vec::new();
+    for i in 0..200 {
+        let pool = pool.clone();
+        tasks.push(tokio::spawn(async move {
+            let conn = pool
+                .get()
+                .await
+                .unwrap_or_else(|e| panic!("checkout {} failed: {}", i, e));
+            tokio::task::yield_now().await;
+            drop(conn);
+        }));
+    }
+    try_join_all(tasks).await.unwrap();
+
+    assert!(PEAK.load(Ordering::SeqCst) <= 5);
+    assert!(pool.state().connections <= 5);
+}
+
+#[tokio::test]
+async fn test_get_preferred() {
+    #[derive(Default)]
+    struct Handler {
+        created: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = usize;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            Ok(self.created.fetch_add(1, Ordering::SeqCst))
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let pool = Pool::builder()
+        .max_size(3)
+        .build(Handler::default())
+        .await
+        .unwrap();
+    pool.warm(3).await.unwrap();
+
+    let preferred = pool.get_preferred(|info| *info.conn == 2).await.unwrap();
+    assert_eq!(*preferred, 2);
+
+    // Without a match, the usual first idle connection is handed out
+    let first = pool.get_preferred(|info| *info.conn == 2).await.unwrap();
+    let second = pool.get().await.unwrap();
+    let mut ids = vec![*first, *second];
+    ids.sort_unstable();
+    assert_eq!(ids, vec![0, 1]);
+}
+
+#[tokio::test]
+async fn test_is_valid_timeout() {
+    static CREATED: AtomicUsize = AtomicUsize::new(0);
+
+    struct Handler;
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            CREATED.fetch_add(1, Ordering::SeqCst);
+            Ok(FakeConnection)
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            pending().await
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let pool = Pool::builder()
+        .max_size(1)
+        .connection_timeout(Duration::from_millis(200))
+        .build(Handler)
+        .await
+        .unwrap();
+    pool.warm(1).await.unwrap();
+
+    // The hanging validation fails the idle connection, so a new one is handed out
+    let conn = timeout(Duration::from_secs(1), pool.get()).await.unwrap();
+    assert!(conn.is_ok());
+    assert_eq!(CREATED.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn test_lazy_only() {
+    let pool = Pool::builder()
+        .max_size(2)
+        .lazy_only(true)
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+    assert_eq!(pool.state().connections, 0);
+
+    // Connections are only established on demand, and then reused
+    drop(pool.get().await.unwrap());
+    drop(pool.get().await.unwrap());
+    let state = pool.state();
+    assert_eq!(state.connections, 1);
+    assert_eq!(state.idle_connections, 1);
+    assert_eq!(state.establishing, 0);
+}
+
+#[tokio::test]
+async fn test_drain_timeout() {
+    let pool = Pool::builder()
+        .max_size(2)
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+    pool.warm(2).await.unwrap();
+
+    // A connection returned in time lets the drain complete cleanly
+    let conn = pool.get().await.unwrap();
+    let clone = pool.clone();
+    let drain = tokio::spawn(async move { clone.drain_timeout(Duration::from_secs(1)).await });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(pool.get().await.unwrap_err(), RunError::Closed);
+    drop(conn);
+    assert!(drain.await.unwrap());
+    assert_eq!(pool.state().connections, 0);
+
+    // A straggler makes it time out, and is closed once returned
+    let pool = Pool::builder()
+        .max_size(2)
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+    let conn = pool.get().await.unwrap();
+    assert!(!pool.drain_timeout(Duration::from_millis(50)).await);
+    assert_eq!(pool.state().connections, 1);
+    drop(conn);
+    let state = pool.state();
+    assert_eq!(state.connections, 0);
+    assert_eq!(state.idle_connections, 0);
+}
+
+#[tokio::test]
+async fn test_no_replenishing_after_close() {
+    static CREATED: AtomicUsize = AtomicUsize::new(0);
+    static BROKEN: AtomicBool = AtomicBool::new(false);
+
+    struct Handler;
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            CREATED.fetch_add(1, Ordering::SeqCst);
+            Ok(FakeConnection)
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            BROKEN.load(Ordering::SeqCst)
+        }
+    }
+
+    let pool = Pool::builder()
+        .max_size(3)
+        .min_idle(Some(2))
+        .build(Handler)
+        .await
+        .unwrap();
+    let conn = pool.get().await.unwrap();
+    while pool.state().idle_connections < 2 {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    assert!(!pool.drain_timeout(Duration::from_millis(10)).await);
+    let created = CREATED.load(Ordering::SeqCst);
+
+    // Dropping a broken connection after the close must not make up for it
+    BROKEN.store(true, Ordering::SeqCst);
+    drop(conn);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(CREATED.load(Ordering::SeqCst), created);
+    let state = pool.state();
+    assert_eq!(state.establishing, 0);
+    assert_eq!(state.connections, 0);
+}
+
+#[tokio::test]
+async fn test_connect_latency() {
+    struct Handler;
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok(FakeConnection)
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let pool = Pool::builder().max_size(2).build(Handler).await.unwrap();
+    pool.warm(2).await.unwrap();
+
+    let statistics = pool.statistics();
+    assert_eq!(statistics.connections_created, 2);
+    let latency = statistics.connect_latency;
+    assert_eq!(latency.count(), 2);
+    assert!(latency.sum() >= Duration::from_millis(100));
+
+    // Both connections land in buckets above the time it took to connect
+    let slow: u64 = latency
+        .buckets()
+        .filter(|(bound, _)| bound.map_or(true, |bound| bound > Duration::from_millis(50)))
+        .map(|(_, count)| count)
+        .sum();
+    assert_eq!(slow, 2);
+}
+
+#[tokio::test]
+async fn test_invalidate_generation() {
+    #[derive(Default)]
+    struct Handler {
+        created: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = usize;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            Ok(self.created.fetch_add(1, Ordering::SeqCst))
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let pool = Pool::builder()
+        .max_size(2)
+        .build(Handler::default())
+        .await
+        .unwrap();
+    pool.warm(2).await.unwrap();
+
+    let checked_out = pool.get().await.unwrap();
+    pool.invalidate_generation();
+
+    // The idle connection is replaced on checkout, the checked out one closed on return
+    let conn = pool.get().await.unwrap();
+    assert_eq!(*conn, 2);
+    drop(checked_out);
+    let state = pool.state();
+    assert_eq!(state.connections, 1);
+    assert_eq!(state.idle_connections, 0);
+}
+
+#[tokio::test]
+async fn test_get_unvalidated() {
+    static VALIDATED: AtomicUsize = AtomicUsize::new(0);
+
+    struct Handler;
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            Ok(FakeConnection)
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            VALIDATED.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let pool = Pool::builder().max_size(1).build(Handler).await.unwrap();
+    pool.warm(1).await.unwrap();
+
+    drop(pool.get_unvalidated().await.unwrap());
+    assert_eq!(VALIDATED.load(Ordering::SeqCst), 0);
+    drop(pool.get().await.unwrap());
+    assert_eq!(VALIDATED.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_conn_fail_once() {
+    static FAILED_ONCE: AtomicBool = AtomicBool::new(false);
+    static NB_CALL: AtomicUsize = AtomicUsize::new(0);
+
+    struct Connection;
+    struct Handler;
+
+    impl Connection {
+        fn inc(&mut self) {
+            NB_CALL.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = Connection;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            // only fail once so the retry should work
+            if FAILED_ONCE.load(Ordering::SeqCst) {
+                Ok(Connection)
+            } else {
+                FAILED_ONCE.store(true, Ordering::SeqCst);
+                Err(Error)
+            }
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let pool = Pool::builder().max_size(1).build(Handler).await.unwrap();
+
+    for _ in 0..2 {
+        pool.get().await.unwrap().inc();
+    }
+
+    assert_eq!(NB_CALL.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn test_min_connect_backoff() {
+    static FAILED_ONCE: AtomicBool = AtomicBool::new(false);
+
+    struct Handler;
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            match FAILED_ONCE.swap(true, Ordering::SeqCst) {
+                true => Ok(FakeConnection),
+                false => Err(Error),
+            }
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let start = std::time::Instant::now();
+    let pool = Pool::builder()
+        .min_idle(Some(1))
+        .min_connect_backoff(Duration::from_millis(10))
+        .build(Handler)
+        .await
+        .unwrap();
+    assert_eq!(pool.state().idle_connections, 1);
+    assert!(start.elapsed() < Duration::from_millis(150));
+}
+
+// This mimics the test_acquire_release test, but using the `get()` API.
+#[tokio::test]
+async fn test_guard() {
+    let pool = Pool::builder()
+        .max_size(2)
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+
+    let (tx1, rx1) = oneshot::channel();
+    let (tx2, rx2) = oneshot::channel();
+    let clone = pool.clone();
+    tokio::spawn(async move {
+        let conn = clone.get().await;
+        tx1.send(()).unwrap();
+        let res = rx2
+            .then(|r| match r {
+                Ok(()) => ok(()),
+                Err(_) => err(Error),
+            })
+            .await
+            .map(|_| ());
+        drop(conn);
+        res
+    });
+
+    let (tx3, rx3) = oneshot::channel();
+    let (tx4, rx4) = oneshot::channel();
+    let clone = pool.clone();
+    tokio::spawn(async move {
+        let conn = clone.get().await;
+        tx3.send(()).unwrap();
+        let res = rx4
+            .then(|r| match r {
+                Ok(()) => ok(()),
+                Err(_) => err(Error),
+            })
+            .await
+            .map(|_| ());
+        drop(conn);
+        res
+    });
+
+    // Get the first connection.
+    rx1.await.unwrap();
+    // Get the second connection.
+    rx3.await.unwrap();
+
+    let (tx5, mut rx5) = oneshot::channel();
+    let (tx6, rx6) = oneshot::channel();
+    let clone = pool.clone();
+    tokio::spawn(async move {
+        let conn = clone.get().await;
+        tx5.send(()).unwrap();
+        let res = rx6
+            .then(|r| match r {
+                Ok(()) => ok(()),
+                Err(_) => err(Error),
+            })
+            .await
+            .map(|_| ());
+        drop(conn);
+        res
+    });
+
+    {
+        let rx5_ref = &mut rx5;
+        // NB: The channel needs to run on a Task, so shove it onto
+        // the tokio event loop with a lazy.
+        assert_eq!(lazy(|cx| Pin::new(rx5_ref).poll(cx)).await, Poll::Pending);
+    }
+
+    // Release the first connection.
+    tx2.send(()).unwrap();
+
+    rx5.await.unwrap();
+    tx4.send(()).unwrap();
+    tx6.send(()).unwrap();
+}
+
+#[tokio::test]
+async fn test_customize_connection_acquire() {
+    #[derive(Debug, Default)]
+    struct Connection {
+        custom_field: usize,
+    }
+
+    #[derive(Debug, Default)]
+    struct CountingCustomizer {
+        count: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl<E: 'static> CustomizeConnection<Connection, E> for CountingCustomizer {
+        async fn on_acquire(&self, connection: &mut Connection) -> Result<(), E> {
+            connection.custom_field = 1 + self.count.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    let pool = Pool::builder()
+        .max_size(2)
+        .connection_customizer(Box::new(CountingCustomizer::default()))
+        .build(OkManager::<Connection>::new())
+        .await
+        .unwrap();
+
+    // Each connection gets customized
+    {
+        let connection_1 = pool.get().await.unwrap();
+        assert_eq!(connection_1.custom_field, 1);
+        let connection_2 = pool.get().await.unwrap();
+        assert_eq!(connection_2.custom_field, 2);
+    }
+
+    // Connections don't get customized again on re-use
+    let connection_1_or_2 = pool.get().await.unwrap();
+    assert!(connection_1_or_2.custom_field == 1 || connection_1_or_2.custom_field == 2);
+}
+
+#[tokio::test]
+async fn test_connect_failure_statistics() {
+    #[derive(Debug, Clone, Default)]
+    struct AttemptsSink(Arc<Mutex<Vec<u32>>>);
+
+    impl ErrorSink<Error> for AttemptsSink {
+        fn sink(&self, _: Error) {}
+
+        fn sink_connect(&self, _: Error, failure: ConnectFailure) {
+            self.0.lock().unwrap().push(failure.attempts);
+        }
+
+        fn boxed_clone(&self) -> Box<dyn ErrorSink<Error>> {
+            Box::new(self.clone())
+        }
+    }
+
+    struct Hanging;
+
+    #[async_trait]
+    impl ManageConnection for Hanging {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            pending().await
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    // Every attempt errors until the timeout elapses
+    let sink = AttemptsSink::default();
+    let pool = Pool::builder()
+        .min_idle(Some(1))
+        .connection_timeout(Duration::from_millis(100))
+        .min_connect_backoff(Duration::from_millis(10))
+        .error_sink(Box::new(sink.clone()))
+        .build_unchecked(NthConnectionFailManager::<FakeConnection>::new(0));
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let attempts = sink.0.lock().unwrap().clone();
+    assert_eq!(attempts.len(), 1);
+    assert!(attempts[0] > 1);
+    let statistics = pool.statistics();
+    assert_eq!(statistics.connect_errors, 1);
+    assert_eq!(statistics.connect_timeouts, 0);
+
+    // The attempt never completes, so the timeout cuts it short
+    let pool = Pool::builder()
+        .connection_timeout(Duration::from_millis(100))
+        .build_unchecked(Hanging);
+    pool.warm(1).await.unwrap();
+
+    let statistics = pool.statistics();
+    assert_eq!(statistics.connect_errors, 0);
+    assert_eq!(statistics.connect_timeouts, 1);
+    assert_eq!(pool.state().connections, 0);
+}
+
+#[tokio::test]
+async fn test_connect_failure_context() {
+    #[derive(Debug, Clone, Default)]
+    struct ContextSink(Arc<Mutex<Vec<(bool, State)>>>);
+
+    impl ErrorSink<Error> for ContextSink {
+        fn sink(&self, _: Error) {}
+
+        fn sink_connect(&self, _: Error, failure: ConnectFailure) {
+            self.0.lock().unwrap().push((failure.demand, failure.state));
+        }
+
+        fn boxed_clone(&self) -> Box<dyn ErrorSink<Error>> {
+            Box::new(self.clone())
+        }
+    }
+
+    // Background maintenance failing, with nobody waiting
+    let sink = ContextSink::default();
+    let _pool = Pool::builder()
+        .min_idle(Some(1))
+        .connection_timeout(Duration::from_millis(50))
+        .error_sink(Box::new(sink.clone()))
+        .build_unchecked(NthConnectionFailManager::<FakeConnection>::new(0));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let (demand, state) = sink.0.lock().unwrap()[0];
+    assert!(!demand);
+    assert_eq!((state.establishing, state.waiters), (1, 0));
+
+    // A waiting caller that could not be served
+    let sink = ContextSink::default();
+    let pool = Pool::builder()
+        .connection_timeout(Duration::from_millis(50))
+        .demand_connect_retries(Some(0))
+        .error_sink(Box::new(sink.clone()))
+        .build_unchecked(NthConnectionFailManager::<FakeConnection>::new(0));
+    assert!(pool.get().await.is_err());
+    let (demand, state) = sink.0.lock().unwrap()[0];
+    assert!(demand);
+    assert_eq!((state.establishing, state.waiters), (1, 1));
+}
+
+#[tokio::test]
+async fn test_map_connect_error() {
+    struct Refused;
+
+    #[async_trait]
+    impl ManageConnection for Refused {
+        type Connection = FakeConnection;
+        type Error = String;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            Err("connection refused".to_owned())
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let pool = Pool::builder()
+        .connect_inline(true)
+        .demand_connect_retries(Some(1))
+        .min_connect_backoff(Duration::from_millis(10))
+        .map_connect_error(|e, info| {
+            assert!(info.elapsed >= Duration::from_millis(10));
+            format!("db.internal: {} after {} attempts", e, info.attempts)
+        })
+        .build_unchecked(Refused);
+
+    let result = pool.get().await;
+    match result {
+        Err(RunError::User(e)) => {
+            assert_eq!(e, "db.internal: connection refused after 2 attempts")
+        }
+        _ => panic!("expected the rewritten connect error"),
+    }
+}
+
+#[tokio::test]
+async fn test_max_checkout_validation_retries() {
+    static VALIDATED: AtomicUsize = AtomicUsize::new(0);
+
+    struct Handler;
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            Ok(FakeConnection)
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            VALIDATED.fetch_add(1, Ordering::SeqCst);
+            Err(Error)
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let pool = Pool::builder()
+        .max_size(5)
+        .max_checkout_validation_retries(Some(1))
+        .build(Handler)
+        .await
+        .unwrap();
+    pool.warm(5).await.unwrap();
+    assert_eq!(pool.state().idle_connections, 5);
+
+    // Two idle connections fail validation before the checkout waits for a fresh one
+    // instead of discarding the rest of the pool
+    let conn = pool.get().await.unwrap();
+    assert_eq!(VALIDATED.load(Ordering::SeqCst), 2);
+    assert_eq!(pool.state().idle_connections, 3);
+    drop(conn);
+}
+
+#[tokio::test]
+async fn test_weak_pool() {
+    let pool = Pool::builder()
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+    let weak = pool.downgrade();
+
+    // Upgraded handles share the same pool
+    let conn = weak.upgrade().unwrap().get_owned().await.unwrap();
+    assert_eq!(pool.state().connections, 1);
+    drop(conn);
+
+    drop(pool);
+    assert!(weak.upgrade().is_none());
+}
+
+#[tokio::test]
+async fn test_connect_inline() {
+    tokio::task_local! {
+        static CALLER: ();
+    }
+
+    struct Handler {
+        inline: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            // Only the checking out task has the task-local set
+            if CALLER.try_with(|_| ()).is_ok() {
+                self.inline.fetch_add(1, Ordering::SeqCst);
+            }
+            Ok(FakeConnection)
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let inline = Arc::new(AtomicUsize::new(0));
+    let pool = Pool::builder()
+        .max_size(1)
+        .connect_inline(true)
+        .build_unchecked(Handler {
+            inline: inline.clone(),
+        });
+
+    let conn = CALLER.scope((), pool.get()).await.unwrap();
+    assert_eq!(inline.load(Ordering::SeqCst), 1);
+    let state = pool.state();
+    assert_eq!(state.connections, 1);
+    assert_eq!(state.establishing, 0);
+    drop(conn);
+
+    // With the pool at `max_size`, checkouts use the idle connection
+    let conn = CALLER.scope((), pool.get()).await.unwrap();
+    assert_eq!(inline.load(Ordering::SeqCst), 1);
+    assert_eq!(pool.state().connections, 1);
+    drop(conn);
+}
+
+#[tokio::test]
+async fn test_get_deadline() {
+    let pool = Pool::builder()
+        .max_size(1)
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+    let conn = pool.get().await.unwrap();
+
+    // The deadline cuts the wait short well before the connection timeout
+    let start = Instant::now();
+    let deadline = start + Duration::from_millis(100);
+    match pool.get_deadline(deadline).await {
+        Err(RunError::TimedOutWithState(_)) => {}
+        _ => panic!("expected a timeout"),
+    }
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    // A deadline that has passed fails even though a connection is available
+    drop(conn);
+    match pool.get_deadline(deadline).await {
+        Err(RunError::TimedOut) => {}
+        _ => panic!("expected a timeout"),
+    }
+    assert!(pool
+        .get_deadline(Instant::now() + Duration::from_secs(1))
+        .await
+        .is_ok());
+}
+
+#[tokio::test]
+async fn test_health() {
+    let pool = Pool::builder()
+        .max_size(1)
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+    assert_eq!(pool.health(), PoolHealth::Healthy);
+
+    // Every connection is in use and a caller is waiting
+    let conn = pool.get().await.unwrap();
+    assert_eq!(pool.health(), PoolHealth::Healthy);
+    let waiter = {
+        let pool = pool.clone();
+        tokio::spawn(async move { drop(pool.get().await.unwrap()) })
+    };
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(matches!(pool.health(), PoolHealth::Degraded { .. }));
+    drop(conn);
+    waiter.await.unwrap();
+    assert_eq!(pool.health(), PoolHealth::Healthy);
+
+    // No connections could be opened
+    let pool = Pool::builder()
+        .connection_timeout(Duration::from_millis(100))
+        .min_connect_backoff(Duration::from_millis(10))
+        .build_unchecked(NthConnectionFailManager::<FakeConnection>::new(0));
+    assert!(pool.warm(1).await.is_err());
+    assert_eq!(pool.health(), PoolHealth::Unavailable);
+}
+
+#[tokio::test]
+async fn test_on_min_idle_underflow() {
+    let underflows = Arc::new(Mutex::new(Vec::new()));
+    let recorded = underflows.clone();
+    let pool = Pool::builder()
+        .max_size(2)
+        .min_idle(Some(2))
+        .max_lifetime(Some(Duration::from_millis(100)))
+        .reaper_rate(Duration::from_millis(50))
+        .on_min_idle_underflow(move |current, target| {
+            recorded.lock().unwrap().push((current, target));
+        })
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+    assert_eq!(pool.state().idle_connections, 2);
+
+    // Both connections expire together, and are replaced after the hook fires
+    tokio::time::sleep(Duration::from_millis(180)).await;
+    let underflows = underflows.lock().unwrap().clone();
+    assert_eq!(underflows.first(), Some(&(0, 2)));
+    assert_eq!(pool.state().idle_connections, 2);
+}
+
+#[tokio::test]
+async fn test_timed_out_with_state() {
+    let pool = Pool::builder()
+        .max_size(1)
+        .connection_timeout(Duration::from_millis(100))
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+
+    let conn = pool.get().await.unwrap();
+    match pool.get().await {
+        Err(RunError::TimedOutWithState(state)) => {
+            assert_eq!(state.connections, 1);
+            assert_eq!(state.idle_connections, 0);
+            assert_eq!(state.establishing, 0);
+            // The timed out caller is no longer waiting
+            assert_eq!(state.waiters, 0);
+        }
+        _ => panic!("expected a timeout"),
+    }
+    drop(conn);
+}
+
+#[tokio::test]
+async fn test_reaper_on_dedicated_thread() {
+    let threads = Arc::new(Mutex::new(Vec::new()));
+    let recorded = threads.clone();
+    let pool = Pool::builder()
+        .max_size(1)
+        .min_idle(Some(1))
+        .max_lifetime(Some(Duration::from_millis(100)))
+        .reaper_rate(Duration::from_millis(50))
+        .reaper_on_dedicated_thread(true)
+        .on_reap(move |_| {
+            let name = std::thread::current().name().map(str::to_owned);
+            recorded.lock().unwrap().push(name);
+        })
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let threads = threads.lock().unwrap().clone();
+    assert!(!threads.is_empty());
+    assert!(threads
+        .iter()
+        .all(|name| name.as_deref() == Some("bb8-reaper")));
+
+    // Expired connections were replaced on the pool's runtime
+    assert!(pool.statistics().connections_created > 1);
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_reaper_thread_exits() {
+    fn reaper_threads() -> Vec<std::path::PathBuf> {
+        std::fs::read_dir("/proc/self/task")
+            .unwrap()
+            .map(|task| task.unwrap().path())
+            .filter(|task| {
+                let comm = std::fs::read_to_string(task.join("comm")).unwrap_or_default();
+                comm.trim_end() == "bb8-reaper"
+            })
+            .collect()
+    }
+
+    for close in [false, true] {
+        let before = reaper_threads();
+        let pool = Pool::builder()
+            .max_lifetime(Some(Duration::from_secs(3600)))
+            .reaper_rate(Duration::from_secs(3600))
+            .reaper_on_dedicated_thread(true)
+            .build(OkManager::<FakeConnection>::new())
+            .await
+            .unwrap();
+        // The thread names itself once it runs
+        let mut started = Vec::new();
+        for _ in 0..100 {
+            started = reaper_threads();
+            started.retain(|task| !before.contains(task));
+            if !started.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(!started.is_empty());
+
+        // The thread exits right away rather than at its next run
+        match close {
+            true => assert!(pool.drain_timeout(Duration::from_millis(10)).await),
+            false => drop(pool),
+        }
+        let exited = async {
+            while started.iter().any(|task| task.exists()) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        timeout(Duration::from_secs(2), exited).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_update_manager() {
+    struct Handler {
+        credentials: &'static str,
+    }
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = &'static str;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            Ok(self.credentials)
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let pool = Pool::builder()
+        .max_size(2)
+        .build(Handler { credentials: "old" })
+        .await
+        .unwrap();
+    let old = pool.get().await.unwrap();
+    assert_eq!(*old, "old");
+
+    // New connections use the new manager, existing ones are kept
+    pool.update_manager(Handler { credentials: "new" });
+    let new = pool.get().await.unwrap();
+    assert_eq!(*new, "new");
+    drop(old);
+    drop(new);
+    assert_eq!(pool.state().idle_connections, 2);
+}
+
+#[tokio::test]
+async fn test_reconfigure() {
+    struct Handler {
+        credentials: &'static str,
+        gate: Option<Arc<Notify>>,
+    }
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = &'static str;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            if let Some(gate) = &self.gate {
+                gate.notified().await;
+            }
+            Ok(self.credentials)
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let gate = Arc::new(Notify::new());
+    let pool = Pool::builder().max_size(3).build_unchecked(Handler {
+        credentials: "old",
+        gate: Some(gate.clone()),
+    });
+    gate.notify_one();
+    let old = pool.get().await.unwrap();
+    assert_eq!(*old, "old");
+
+    // An attempt started with the old manager, still in progress when it is replaced
+    let in_flight = tokio::spawn({
+        let pool = pool.clone();
+        async move { pool.get_owned().await.unwrap() }
+    });
+    while pool.state().establishing == 0 {
+        tokio::task::yield_now().await;
+    }
+
+    pool.reconfigure(Handler {
+        credentials: "new",
+        gate: None,
+    });
+    gate.notify_one();
+
+    // The connection it opened is retired, and its caller gets one from the new manager
+    let in_flight = in_flight.await.unwrap();
+    assert_eq!(*in_flight, "new");
+    let new = pool.get().await.unwrap();
+    assert_eq!(*new, "new");
+
+    // Connections from the old manager are closed as they are returned
+    drop(old);
+    drop(in_flight);
+    drop(new);
+    assert_eq!(pool.state().connections, 2);
+    let (a, b) = (pool.get().await.unwrap(), pool.get().await.unwrap());
+    assert_eq!((*a, *b), ("new", "new"));
+}
+
+#[tokio::test]
+async fn test_max_connection_resource() {
+    struct Handler;
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = u64;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            Ok(0)
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+
+        fn resource_usage(&self, conn: &Self::Connection) -> u64 {
+            *conn
+        }
+    }
+
+    let pool = Pool::builder()
+        .max_size(1)
+        .max_connection_resource(Some(10))
+        .build(Handler)
+        .await
+        .unwrap();
+
+    // Connections within the limit are kept
+    *pool.get().await.unwrap() = 10;
+    assert_eq!(pool.state().idle_connections, 1);
+    assert_eq!(pool.statistics().connections_created, 1);
+
+    // Connections over it are closed and replaced
+    *pool.get().await.unwrap() = 11;
+    assert_eq!(pool.state().idle_connections, 0);
+    assert_eq!(*pool.get().await.unwrap(), 0);
+    assert_eq!(pool.statistics().connections_created, 2);
+}
+
+#[tokio::test]
+async fn test_next_reap_in() {
+    let pool = Pool::builder()
+        .max_lifetime(None)
+        .idle_timeout(None)
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+    assert_eq!(pool.next_reap_in(), None);
+
+    let pool = Pool::builder()
+        .reaper_rate(Duration::from_millis(100))
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+    let first = pool.next_reap_in().unwrap();
+    assert!(first <= Duration::from_millis(100));
+
+    // After a run, the next one is a full period after it was due
+    tokio::time::sleep(first + Duration::from_millis(20)).await;
+    let next = pool.next_reap_in().unwrap();
+    assert!(next > Duration::from_millis(50) && next <= Duration::from_millis(100));
+}
+
+#[tokio::test]
+async fn test_get_role() {
+    struct Handler(&'static str);
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = &'static str;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            Ok(self.0)
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let replica = Pool::builder().build_unchecked(Handler("replica"));
+    let pool = Pool::builder()
+        .max_size(1)
+        .connection_timeout(Duration::from_millis(50))
+        .replica(replica)
+        .build_unchecked(Handler("primary"));
+
+    // The primary is preferred while it has room
+    let (conn, role) = pool.get_role(Role::ReadOnly).await.unwrap();
+    assert_eq!((*conn, role), ("primary", Role::ReadWrite));
+
+    // Once it is saturated, readers are served by the replica and writers wait
+    let (replica, role) = pool.get_role(Role::ReadOnly).await.unwrap();
+    assert_eq!((*replica, role), ("replica", Role::ReadOnly));
+    assert!(pool.get_role(Role::ReadWrite).await.is_err());
+    drop(conn);
+    let (conn, role) = pool.get_role(Role::ReadWrite).await.unwrap();
+    assert_eq!((*conn, role), ("primary", Role::ReadWrite));
+}
+
+#[tokio::test]
+async fn test_get_role_primary_down() {
+    let replica = Pool::builder()
+        .build(NthConnectionFailManager::<FakeConnection>::new(5))
+        .await
+        .unwrap();
+    let pool = Pool::builder()
+        .connection_timeout(Duration::from_secs(5))
+        .replica(replica)
+        .build_unchecked(NthConnectionFailManager::<FakeConnection>::new(0));
+
+    // Readers fail over on the first connect error, and skip the primary after that
+    for _ in 0..2 {
+        let started = Instant::now();
+        let (_conn, role) = pool.get_role(Role::ReadOnly).await.unwrap();
+        assert_eq!(role, Role::ReadOnly);
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+    assert_ne!(pool.health(), PoolHealth::Healthy);
+}
+
+#[tokio::test]
+async fn test_metrics_history() {
+    let pool = Pool::builder()
+        .metrics_history(Duration::from_millis(20), 3)
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+    assert!(pool.history().is_empty());
+
+    let conn = pool.get().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(110)).await;
+
+    // Only the most recent samples are kept, oldest first
+    let history = pool.history();
+    assert_eq!(history.len(), 3);
+    assert!(history.windows(2).all(|pair| pair[0].at <= pair[1].at));
+    let last = history.last().unwrap();
+    assert_eq!(last.state.connections, 1);
+    assert_eq!(last.state.idle_connections, 0);
+    assert_eq!(last.statistics.connections_created, 1);
+    drop(conn);
+}
+
+#[tokio::test]
+async fn test_max_idle() {
+    let pool = Pool::builder()
+        .max_size(4)
+        .max_idle(Some(1))
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+
+    // Returned connections beyond the limit are closed instead of queued
+    let conns = try_join_all((0..3).map(|_| pool.get())).await.unwrap();
+    assert_eq!(pool.state().connections, 3);
+    drop(conns);
+    assert_eq!(pool.state().connections, 1);
+    assert_eq!(pool.state().idle_connections, 1);
+
+    // `min_idle` takes precedence
+    let pool = Pool::builder()
+        .max_size(4)
+        .min_idle(Some(2))
+        .max_idle(Some(1))
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+    let conns = try_join_all((0..3).map(|_| pool.get())).await.unwrap();
+    drop(conns);
+    assert_eq!(pool.state().idle_connections, 2);
+}
+
+#[tokio::test]
+async fn test_closing_idle_outside_lock() {
+    type Slot = Arc<Mutex<Option<Pool<Handler>>>>;
+    static LOCKED: AtomicUsize = AtomicUsize::new(0);
+    static CLOSED: AtomicUsize = AtomicUsize::new(0);
+
+    struct Connection(Slot);
+
+    impl Drop for Connection {
+        fn drop(&mut self) {
+            // Probe the pool lock from another thread, which blocks while it is held
+            if let Some(pool) = self.0.lock().unwrap().clone() {
+                let (tx, rx) = std::sync::mpsc::channel();
+                std::thread::spawn(move || {
+                    let _ = tx.send(pool.state());
+                });
+                if rx.recv_timeout(Duration::from_millis(500)).is_err() {
+                    LOCKED.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+            CLOSED.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    struct Handler(Slot);
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = Connection;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            Ok(Connection(self.0.clone()))
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let slot = Slot::default();
+    let pool = Pool::builder()
+        .max_size(4)
+        .max_idle(Some(1))
+        .build(Handler(slot.clone()))
+        .await
+        .unwrap();
+    *slot.lock().unwrap() = Some(pool.clone());
+
+    // Connections beyond `max_idle` are closed once returned
+    let conns = try_join_all((0..3).map(|_| pool.get())).await.unwrap();
+    drop(conns);
+    assert_eq!(CLOSED.load(Ordering::SeqCst), 2);
+
+    // Idle connections are closed along with the pool
+    let conns = try_join_all((0..2).map(|_| pool.get())).await.unwrap();
+    drop(conns);
+    let _ = pool.drain_timeout(Duration::from_millis(10)).await;
+    assert_eq!(CLOSED.load(Ordering::SeqCst), 4);
+    assert_eq!(LOCKED.load(Ordering::SeqCst), 0);
+    slot.lock().unwrap().take();
+}
+
+#[tokio::test]
+async fn test_get_with_budget() {
+    let pool = Pool::builder()
+        .max_size(1)
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+    assert_eq!(pool.get().await.unwrap().remaining_budget(), None);
+
+    // The budget includes the time spent waiting for the connection
+    let conn = pool.get().await.unwrap();
+    let budgeted = async {
+        let conn = pool
+            .get_with_budget(Duration::from_millis(200))
+            .await
+            .unwrap();
+        conn.remaining_budget().unwrap()
+    };
+    let release = async {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        drop(conn);
+    };
+    let (remaining, ()) = tokio::join!(budgeted, release);
+    assert!(remaining > Duration::from_millis(0));
+    assert!(remaining <= Duration::from_millis(150));
+
+    // Waiting gives up once the budget is spent
+    let _conn = pool.get().await.unwrap();
+    let result = pool.get_with_budget(Duration::from_millis(20)).await;
+    assert!(matches!(
+        result,
+        Err(RunError::TimedOut | RunError::TimedOutWithState(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_info() {
+    let pool = Pool::builder()
+        .max_size(4)
+        .min_idle(Some(2))
+        .connection_timeout(Duration::from_secs(5))
+        .idle_timeout(None)
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+
+    let info = pool.info();
+    assert_eq!(info.max_size, 4);
+    assert_eq!(info.min_idle, Some(2));
+    assert_eq!(info.connection_timeout, Duration::from_secs(5));
+    assert_eq!(info.max_lifetime, Some(Duration::from_secs(30 * 60)));
+    assert_eq!(info.idle_timeout, None);
+    assert_eq!(info.state, pool.state());
+    assert_eq!(info.state.idle_connections, 2);
+}
+
+#[tokio::test]
+async fn test_waiter_validates_delivered_connection() {
+    #[derive(Debug)]
+    struct Tracked {
+        id: usize,
+        stale: bool,
+    }
+
+    struct Handler {
+        created: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = Tracked;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            let id = self.created.fetch_add(1, Ordering::SeqCst);
+            Ok(Tracked { id, stale: false })
+        }
+
+        async fn is_valid(&self, conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            match conn.stale {
+                true => Err(Error),
+                false => Ok(()),
+            }
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let pool = Pool::builder()
+        .max_size(1)
+        .build(Handler {
+            created: AtomicUsize::new(0),
+        })
+        .await
+        .unwrap();
+
+    let mut conn = pool.get().await.unwrap();
+    assert_eq!(conn.id, 0);
+    conn.stale = true;
+
+    let waiter = {
+        let pool = pool.clone();
+        tokio::spawn(async move { pool.get().await.unwrap().id })
+    };
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // The waiter rejects the stale connection it is handed and waits for a new one
+    drop(conn);
+    let id = timeout(Duration::from_secs(1), waiter)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(id, 1);
+    assert_eq!(pool.state().connections, 1);
+}
+
+#[tokio::test]
+async fn test_events() {
+    let pool = Pool::builder()
+        .max_size(1)
+        .build_unchecked(OkManager::<FakeConnection>::new());
+    let events = pool.events();
+    futures_util::pin_mut!(events);
+
+    let conn = pool.get().await.unwrap();
+    let id = conn.connection_id();
+    drop(conn);
+    pool.drain_timeout(Duration::from_secs(1)).await;
+
+    let mut seen = Vec::new();
+    for _ in 0..4 {
+        let event = timeout(Duration::from_secs(1), events.next())
+            .await
+            .unwrap();
+        seen.push(event.unwrap());
+    }
+    assert_eq!(
+        seen,
+        [
+            PoolEvent::Created(id),
+            PoolEvent::CheckedOut(id),
+            PoolEvent::Returned(id),
+            PoolEvent::Closed(id)
+        ]
+    );
+
+    // The stream ends with the pool
+    drop(pool);
+    assert_eq!(events.next().await, None);
+}
+
+#[tokio::test]
+async fn test_deadline_scheduling() {
+    let pool = Pool::builder()
+        .max_size(1)
+        .deadline_scheduling(true)
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+    let served = Arc::new(Mutex::new(Vec::new()));
+
+    let conn = pool.get().await.unwrap();
+    let mut handles = Vec::new();
+    for (name, deadline) in [("relaxed", 5), ("default", 30), ("urgent", 1)] {
+        let pool = pool.clone();
+        let served = served.clone();
+        let deadline = Instant::now() + Duration::from_secs(deadline);
+        handles.push(tokio::spawn(async move {
+            let conn = pool.get_deadline(deadline).await.unwrap();
+            served.lock().unwrap().push(name);
+            drop(conn);
+        }));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    // Waiters are served earliest deadline first, regardless of arrival
+    drop(conn);
+    for handle in handles {
+        handle.await.unwrap();
+    }
+    assert_eq!(*served.lock().unwrap(), ["urgent", "relaxed", "default"]);
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_connect_hook() {
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let hook_attempts = attempts.clone();
+    let pool = Pool::builder()
+        .max_size(1)
+        .min_connect_backoff(Duration::from_millis(10))
+        .connect_hook(move || {
+            let n = hook_attempts.fetch_add(1, Ordering::SeqCst);
+            async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                if n < 2 {
+                    Err(Error)
+                } else {
+                    Ok(())
+                }
+            }
+        })
+        .build_unchecked(OkManager::<FakeConnection>::new());
+
+    // The waiter gets the connection from the third attempt, after two scripted failures
+    let conn = pool.get().await.unwrap();
+    assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    assert_eq!(pool.statistics().connect_errors, 0);
+    drop(conn);
+
+    // The hook is not consulted by a direct connect
+    pool.dedicated_connection().await.unwrap();
+    assert_eq!(attempts.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn test_connection_id() {
+    let pool = Pool::builder()
+        .max_size(2)
+        .build_unchecked(OkManager::<FakeConnection>::new());
+
+    let first = pool.get().await.unwrap();
+    let second = pool.get().await.unwrap();
+    assert!(first.connection_id() < second.connection_id());
+    let id = first.connection_id();
+    drop(first);
+
+    // A connection keeps its ID across checkouts
+    let preferred = pool.get_preferred(|info| info.id == id).await.unwrap();
+    assert_eq!(preferred.connection_id(), id);
+    drop(preferred);
+
+    // Replacement connections get new IDs
+    drop(second);
+    pool.invalidate_generation();
+    let conn = pool.get().await.unwrap();
+    assert!(conn.connection_id() > id + 1);
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_demand_connect_retries() {
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let hook_attempts = attempts.clone();
+    let pool = Pool::builder()
+        .max_size(1)
+        .connection_timeout(Duration::from_secs(10))
+        .min_connect_backoff(Duration::from_millis(10))
+        .demand_connect_retries(Some(1))
+        .fail_waiters_on_connect_exhaustion(true)
+        .connect_hook(move || {
+            hook_attempts.fetch_add(1, Ordering::SeqCst);
+            ready(Err(Error))
+        })
+        .build_unchecked(OkManager::<FakeConnection>::new());
+
+    // A waiting caller fails after one retry instead of waiting out the connection timeout
+    let start = Instant::now();
+    assert!(pool.get().await.is_err());
+    assert!(start.elapsed() < Duration::from_secs(1));
+    assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    assert_eq!(pool.statistics().connect_errors, 1);
+
+    // Maintenance connections keep retrying in the background
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let hook_attempts = attempts.clone();
+    let _pool = Pool::builder()
+        .min_idle(Some(1))
+        .connection_timeout(Duration::from_secs(10))
+        .maintenance_connect_backoff(Duration::from_millis(10))
+        .demand_connect_retries(Some(1))
+        .connect_hook(move || {
+            hook_attempts.fetch_add(1, Ordering::SeqCst);
+            ready(Err(Error))
+        })
+        .build_unchecked(OkManager::<FakeConnection>::new());
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(attempts.load(Ordering::SeqCst) > 2);
+}
+
+#[tokio::test]
+async fn test_replace() {
+    let pool = Pool::builder()
+        .max_size(1)
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+
+    let conn = pool.get().await.unwrap();
+    let id = conn.connection_id();
+    let conn = conn.replace().await.unwrap();
+    assert_ne!(conn.connection_id(), id);
+    assert_eq!(pool.state().connections, 1);
+    drop(conn);
+
+    let conn = pool.get_owned().await.unwrap();
+    let id = conn.connection_id();
+    let conn = conn.replace().await.unwrap();
+    assert_ne!(conn.connection_id(), id);
+    drop(conn);
+    assert_eq!(pool.state().idle_connections, 1);
+}
+
+#[tokio::test]
+async fn test_replace_labeled() {
+    struct Handler;
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = &'static str;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            Ok("default")
+        }
+
+        async fn connect_labeled(&self, label: &'static str) -> Result<Self::Connection, Error> {
+            Ok(label)
+        }
+
+        async fn is_valid(&self, _: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let pool = Pool::builder()
+        .max_size(2)
+        .min_idle(Some(1))
+        .subset("read", 0, 1)
+        .build(Handler)
+        .await
+        .unwrap();
+
+    // The replacement comes from the same subset, not from the idle default connection
+    let conn = pool.get_labeled("read").await.unwrap();
+    let id = conn.connection_id();
+    let conn = conn.replace().await.unwrap();
+    assert_eq!(*conn, "read");
+    assert_ne!(conn.connection_id(), id);
+    assert_eq!(pool.state().idle_connections, 1);
+}
+
+#[tokio::test]
+async fn test_max_checkout_duration() {
+    let pool = Pool::builder()
+        .max_size(1)
+        .max_checkout_duration(Some(Duration::from_millis(100)))
+        .reaper_rate(Duration::from_millis(50))
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+
+    // A caller waiting behind an abandoned connection gets a replacement
+    let abandoned = pool.get().await.unwrap();
+    let id = abandoned.connection_id();
+    let conn = pool.get().await.unwrap();
+    assert_ne!(conn.connection_id(), id);
+    assert_eq!(pool.state().connections, 1);
+
+    // The abandoned connection is closed rather than returned once dropped
+    drop(abandoned);
+    assert_eq!(pool.state().connections, 1);
+    assert_eq!(pool.state().idle_connections, 0);
+    drop(conn);
+    assert_eq!(pool.state().idle_connections, 1);
+}
+
+#[tokio::test]
+async fn test_subsets() {
+    struct Handler;
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = &'static str;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            Ok("default")
+        }
+
+        async fn connect_labeled(&self, label: &'static str) -> Result<Self::Connection, Error> {
+            Ok(label)
+        }
+
+        async fn is_valid(&self, _: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let pool = Pool::builder()
+        .max_size(4)
+        .subset("read", 1, 3)
+        .subset("write", 0, 1)
+        .build(Handler)
+        .await
+        .unwrap();
+    assert_eq!(pool.state().idle_connections, 1);
+
+    // Each subset only hands out its own connections
+    let read = pool.get_labeled("read").await.unwrap();
+    assert_eq!(*read, "read");
+    let write = pool.get_labeled("write").await.unwrap();
+    assert_eq!(*write, "write");
+    let conn = pool.get().await.unwrap();
+    assert_eq!(*conn, "default");
+
+    // A subset at its own limit waits for one of its connections to be returned
+    let res = timeout(Duration::from_millis(100), pool.get_labeled("write")).await;
+    assert!(res.is_err());
+
+    // Once the pool is full, idle connections elsewhere make room for a subset
+    let second = pool.get_labeled("read").await.unwrap();
+    drop(write);
+    assert_eq!(pool.state().idle_connections, 1);
+    let third = pool.get_labeled("read").await.unwrap();
+    assert_eq!(*third, "read");
+    assert_eq!(pool.state().connections, 4);
+    assert_eq!(pool.state().idle_connections, 0);
+    drop((read, second, third, conn));
+}
+
+#[tokio::test]
+async fn test_max_checkouts() {
+    let pool = Pool::builder()
+        .max_size(3)
+        .max_checkouts(Some(2))
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+
+    let first = pool.get().await.unwrap();
+    let second = pool.get_owned().await.unwrap();
+    assert!(matches!(pool.get().await, Err(RunError::TooManyCheckouts)));
+
+    // The remaining connection is kept for priority callers
+    let priority = pool.get_priority().await.unwrap();
+    drop(priority);
+    assert!(matches!(pool.get().await, Err(RunError::TooManyCheckouts)));
+
+    drop(first);
+    let third = pool.get().await.unwrap();
+    drop((second, third));
+}
+
+#[tokio::test]
+async fn test_on_first_connection() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let on_first = calls.clone();
+    let pool = Pool::builder()
+        .max_size(2)
+        .min_idle(Some(2))
+        .on_first_connection(move || {
+            on_first.fetch_add(1, Ordering::SeqCst);
+        })
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+    assert_eq!(pool.state().connections, 2);
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    // Replacement connections do not fire it again
+    pool.invalidate_generation();
+    drop(pool.get().await.unwrap());
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_get_or_else() {
+    let pool = Pool::builder()
+        .max_size(1)
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+
+    let conn = pool.get().await.unwrap();
+    match pool
+        .get_or_else(Duration::from_millis(10), || "cached")
+        .await
+    {
+        Ok(Err(value)) => assert_eq!(value, "cached"),
+        _ => panic!("expected the fallback to run"),
+    }
+    assert_eq!(pool.statistics().fallbacks, 1);
+
+    drop(conn);
+    let res = pool
+        .get_or_else(Duration::from_millis(10), || "cached")
+        .await;
+    assert!(matches!(res, Ok(Ok(_))));
+    assert_eq!(pool.statistics().fallbacks, 1);
+}
+
+#[tokio::test]
+async fn test_background_validation() {
+    static VALIDATED: AtomicUsize = AtomicUsize::new(0);
+    static FAILING: AtomicBool = AtomicBool::new(false);
+
+    struct Handler;
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            Ok(FakeConnection)
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            VALIDATED.fetch_add(1, Ordering::SeqCst);
+            match FAILING.load(Ordering::SeqCst) {
+                true => Err(Error),
+                false => Ok(()),
+            }
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let pool = Pool::builder()
+        .max_size(2)
+        .min_idle(Some(2))
+        .test_on_check_out(false)
+        .background_validation(Some(Duration::from_millis(20)))
+        .build(Handler)
+        .await
+        .unwrap();
+    let events = pool.events();
+
+    // Valid connections are validated in turn and stay in the pool
+    tokio::time::sleep(Duration::from_millis(110)).await;
+    assert!(VALIDATED.load(Ordering::SeqCst) >= 2);
+    assert_eq!(pool.state().idle_connections, 2);
+
+    // Invalid ones are closed and replaced
+    FAILING.store(true, Ordering::SeqCst);
+    let closed = events
+        .filter(|event| ready(matches!(event, PoolEvent::Closed(_))))
+        .take(2)
+        .collect::<Vec<_>>();
+    timeout(Duration::from_secs(1), closed).await.unwrap();
+    FAILING.store(false, Ordering::SeqCst);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(pool.state().connections, 2);
+    drop(pool.get().await.unwrap());
+}
+
+#[tokio::test]
+async fn test_background_validation_releases_pool() {
+    static VALIDATING: AtomicBool = AtomicBool::new(false);
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Default)]
+    struct Connection;
+
+    impl Drop for Connection {
+        fn drop(&mut self) {
+            DROPPED.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    struct Handler;
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = Connection;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            Ok(Connection)
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            VALIDATING.store(true, Ordering::SeqCst);
+            pending().await
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let pool = Pool::builder()
+        .max_size(2)
+        .min_idle(Some(2))
+        .connection_timeout(Duration::from_secs(10))
+        .background_validation(Some(Duration::from_millis(20)))
+        .build(Handler)
+        .await
+        .unwrap();
+    while !VALIDATING.load(Ordering::SeqCst) {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+
+    // The idle connection is closed with the pool while the other one is being validated
+    drop(pool);
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert_eq!(DROPPED.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_check_broken_on_return() {
+    static CHECKED: AtomicUsize = AtomicUsize::new(0);
+
+    struct Handler;
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            Ok(FakeConnection)
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            CHECKED.fetch_add(1, Ordering::SeqCst);
+            true
+        }
+    }
+
+    let pool = Pool::builder()
+        .max_size(1)
+        .check_broken_on_return(false)
+        .build(Handler)
+        .await
+        .unwrap();
+
+    // The connection is kept although the manager would report it as broken
+    drop(pool.get().await.unwrap());
+    assert_eq!(CHECKED.load(Ordering::SeqCst), 0);
+    assert_eq!(pool.state().idle_connections, 1);
+}
+
+#[tokio::test]
+async fn test_get_scoped() {
+    let pool = Pool::builder()
+        .max_size(1)
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+
+    // The connection is returned after a success
+    let value = pool.get_scoped(|_| async { Ok(1) }.boxed()).await;
+    assert_eq!(value.unwrap(), 1);
+    assert_eq!(pool.state().idle_connections, 1);
+
+    // And discarded after a failure
+    let res = pool
+        .get_scoped(|_| async { Err::<(), _>(Error) }.boxed())
+        .await;
+    assert_eq!(res.unwrap_err(), RunError::User(Error));
+    assert_eq!(pool.state().connections, 0);
+}
+
+#[tokio::test]
+async fn test_least_recent_backend() {
+    struct Handler(AtomicUsize);
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = usize;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            Ok(self.0.fetch_add(1, Ordering::SeqCst))
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+
+        fn backend(&self, conn: &Self::Connection) -> u64 {
+            // Connections 0 and 1 are on backend 0, connections 2 and 3 on backend 1
+            (*conn / 2) as u64
+        }
+    }
+
+    let pool = Pool::builder()
+        .max_size(4)
+        .min_idle(Some(4))
+        .selection_strategy(SelectionStrategy::LeastRecentBackend)
+        .build(Handler(AtomicUsize::new(0)))
+        .await
+        .unwrap();
+
+    // Checkouts alternate between backends, rather than following the idle queue
+    let mut backends = Vec::new();
+    for _ in 0..4 {
+        let conn = pool.get().await.unwrap();
+        backends.push(*conn / 2);
+    }
+    assert_eq!(backends, vec![0, 1, 0, 1]);
+}
+
+#[tokio::test]
+async fn test_min_connection_age_before_reap() {
+    let pool = Pool::builder()
+        .max_size(1)
+        .idle_timeout(Some(Duration::from_millis(20)))
+        .reaper_rate(Duration::from_millis(20))
+        .min_connection_age_before_reap(Duration::from_millis(300))
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+    drop(pool.get().await.unwrap());
+
+    // Idle for long enough, but too young to be reaped
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert_eq!(pool.state().idle_connections, 1);
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(pool.state().connections, 0);
+}
+
+#[tokio::test]
+async fn test_apply_config() {
+    let pool = Pool::builder()
+        .max_size(4)
+        .min_idle(Some(2))
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+    assert_eq!(pool.state().connections, 2);
+
+    let mut config = pool.export_config();
+    assert_eq!(config.max_size, 4);
+    assert_eq!(config.min_idle, Some(2));
+
+    // Shrinking closes idle connections, and those checked out once they are returned
+    let conns = (0..3)
+        .map(|_| pool.get())
+        .collect::<FuturesUnordered<_>>()
+        .try_collect::<Vec<_>>()
+        .await
+        .unwrap();
+    config.max_size = 1;
+    config.min_idle = None;
+    config.connection_timeout = Duration::from_millis(50);
+    pool.apply_config(config.clone());
+    assert_eq!(pool.export_config(), config);
+    assert_eq!(pool.connection_timeout(), Duration::from_millis(50));
+    drop(conns);
+    assert_eq!(pool.state().connections, 1);
+    let conn = pool.get().await.unwrap();
+    assert!(pool.get().await.is_err());
+    drop(conn);
+
+    // Growing opens connections for the new min_idle
+    config.max_size = 3;
+    config.min_idle = Some(3);
+    pool.apply_config(config);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(pool.state().idle_connections, 3);
+}
+
+#[tokio::test]
+async fn test_get_tiered() {
+    let slow = Arc::new(Mutex::new(Vec::new()));
+    let reported = slow.clone();
+    let pool = Pool::builder()
+        .max_size(1)
+        .on_slow_acquire(move |soft| reported.lock().unwrap().push(soft))
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+
+    // Fast acquisitions are not reported
+    let conn = pool
+        .get_tiered(Duration::from_millis(10), Duration::from_millis(100))
+        .await
+        .unwrap();
+    assert!(slow.lock().unwrap().is_empty());
+
+    // Slow ones are reported at the soft timeout and fail at the hard one
+    let res = pool
+        .get_tiered(Duration::from_millis(10), Duration::from_millis(50))
+        .await;
+    assert!(matches!(res, Err(RunError::TimedOutWithState(_))));
+    assert_eq!(*slow.lock().unwrap(), vec![Duration::from_millis(10)]);
+
+    // Or succeed if a connection becomes available in between
+    let release = async {
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        drop(conn);
+    };
+    let get = pool.get_tiered(Duration::from_millis(10), Duration::from_millis(100));
+    let (res, ()) = tokio::join!(get, release);
+    assert!(res.is_ok());
+    assert_eq!(slow.lock().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn test_max_replenish_inflight() {
+    static INFLIGHT: AtomicUsize = AtomicUsize::new(0);
+    static MAX_INFLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+    struct Handler;
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            let inflight = INFLIGHT.fetch_add(1, Ordering::SeqCst) + 1;
+            MAX_INFLIGHT.fetch_max(inflight, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            INFLIGHT.fetch_sub(1, Ordering::SeqCst);
+            Ok(FakeConnection)
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let pool = Pool::builder()
+        .max_size(6)
+        .min_idle(Some(6))
+        .max_replenish_inflight(Some(2))
+        .build_unchecked(Handler);
+
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    assert_eq!(pool.replenishing(), 2);
+    assert_eq!(pool.state().establishing, 6);
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(pool.state().idle_connections, 6);
+    assert_eq!(pool.replenishing(), 0);
+    assert_eq!(MAX_INFLIGHT.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn test_reset_on_return() {
+    struct Handler;
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        // Whether the connection has a transaction open
+        type Connection = bool;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            Ok(false)
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+
+        async fn reset(&self, conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            *conn = false;
+            Ok(())
+        }
+    }
+
+    let pool = Pool::builder()
+        .max_size(1)
+        .reset_on_return(true)
+        .build(Handler)
+        .await
+        .unwrap();
+
+    let mut conn = pool.get().await.unwrap();
+    *conn = true;
+    drop(conn);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!*pool.get().await.unwrap());
+}
+
+#[tokio::test]
+async fn test_reset_on_return_bounded() {
+    struct Handler;
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        // Whether resetting the connection hangs
+        type Connection = bool;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            Ok(false)
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+
+        async fn reset(&self, conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            if *conn {
+                pending::<()>().await;
+            }
+            Ok(())
+        }
+    }
+
+    let pool = Pool::builder()
+        .max_size(1)
+        .connection_timeout(Duration::from_millis(100))
+        .reset_on_return(true)
+        .build(Handler)
+        .await
+        .unwrap();
+
+    // A connection dropped outside of a runtime cannot be reset, so it is closed
+    let conn = pool.get_owned().await.unwrap();
+    std::thread::spawn(move || drop(conn)).join().unwrap();
+    assert_eq!(pool.state().connections, 0);
+
+    // A reset that hangs gives the connection's slot back once it times out
+    let mut conn = pool.get().await.unwrap();
+    *conn = true;
+    drop(conn);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(pool.state().connections, 0);
+    assert!(!*pool.get().await.unwrap());
+}
+
+#[tokio::test]
+async fn test_replace_broken_for_waiters() {
+    struct Handler;
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            Ok(FakeConnection)
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            true
+        }
+    }
+
+    for replace in [false, true] {
+        let pool = Pool::builder()
+            .max_size(1)
+            .connection_timeout(Duration::from_millis(200))
+            .replace_broken_for_waiters(replace)
+            .build(Handler)
             .await
-            .map(|_| ());
+            .unwrap();
+
+        let conn = pool.get().await.unwrap();
+        let release = async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            drop(conn);
+        };
+        let (res, ()) = tokio::join!(pool.get(), release);
+        assert_eq!(res.is_ok(), replace);
+    }
+}
+
+#[tokio::test]
+async fn test_boost_min_idle() {
+    let pool = Pool::builder()
+        .max_size(5)
+        .idle_timeout(Some(Duration::from_millis(50)))
+        .reaper_rate(Duration::from_millis(20))
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+    assert_eq!(pool.state().connections, 0);
+
+    // Connections are kept around for the duration of the boost
+    pool.boost_min_idle(3, Duration::from_millis(200));
+    tokio::time::sleep(Duration::from_millis(120)).await;
+    assert_eq!(pool.state().idle_connections, 3);
+
+    // And reaped after it has ended
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    assert_eq!(pool.state().connections, 0);
+}
+
+#[tokio::test]
+async fn test_get_with_position() {
+    let pool = Pool::builder()
+        .max_size(1)
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+    let positions = Arc::new(Mutex::new(Vec::new()));
+    drop(pool.get().await.unwrap());
+
+    // Not called when a connection is available right away
+    let record = positions.clone();
+    let conn = pool
+        .get_with_position(move |ahead| record.lock().unwrap().push(ahead))
+        .await
+        .unwrap();
+    assert!(positions.lock().unwrap().is_empty());
+
+    let (first, second) = (positions.clone(), positions.clone());
+    let waiters = async {
+        let first = pool.get_with_position(move |ahead| first.lock().unwrap().push(ahead));
+        let second = pool.get_with_position(move |ahead| second.lock().unwrap().push(ahead));
+        let first = async { drop(first.await.unwrap()) };
+        let (_, second) = tokio::join!(first, second);
+        second.unwrap();
+    };
+    let release = async {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        drop(conn);
+    };
+    tokio::join!(waiters, release);
+    assert_eq!(*positions.lock().unwrap(), vec![0, 1]);
+}
+
+#[tokio::test]
+async fn test_admission_controller() {
+    #[derive(Debug, Default)]
+    struct GateState {
+        open: AtomicBool,
+        asked: AtomicUsize,
+        succeeded: AtomicUsize,
+    }
+
+    #[derive(Clone, Debug, Default)]
+    struct Gate(Arc<GateState>);
+
+    impl std::ops::Deref for Gate {
+        type Target = GateState;
+
+        fn deref(&self) -> &GateState {
+            &self.0
+        }
+    }
+
+    impl AdmissionController for Gate {
+        fn admit(&self) -> Admission {
+            self.asked.fetch_add(1, Ordering::SeqCst);
+            match self.open.load(Ordering::SeqCst) {
+                true => Admission::Admit,
+                false => Admission::Delay(Duration::from_millis(10)),
+            }
+        }
+
+        fn record(&self, succeeded: bool) {
+            if succeeded {
+                self.succeeded.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    let gate = Gate::default();
+    let pool = Pool::builder()
+        .max_size(1)
+        .connection_timeout(Duration::from_millis(100))
+        .admission_controller(Box::new(gate.clone()))
+        .build_unchecked(OkManager::<FakeConnection>::new());
+
+    // Delayed past the connection timeout, the attempt is given up.
+    assert!(pool.get().await.is_err());
+    assert!(gate.asked.load(Ordering::SeqCst) > 1);
+    assert_eq!(gate.succeeded.load(Ordering::SeqCst), 0);
+    assert_eq!(pool.state().connections, 0);
+
+    gate.open.store(true, Ordering::SeqCst);
+    pool.get().await.unwrap();
+    assert_eq!(gate.succeeded.load(Ordering::SeqCst), 1);
+}
+
+#[cfg(feature = "tracing")]
+#[tokio::test]
+async fn test_span_propagation() {
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Instrument, Metadata};
+    use tracing_core::span::Current;
+
+    // Just enough of a subscriber for `Span::current` to work
+    #[derive(Default)]
+    struct Spans {
+        metadata: Mutex<Vec<&'static Metadata<'static>>>,
+        entered: Mutex<Vec<Id>>,
+    }
+
+    impl tracing::Subscriber for Spans {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, attrs: &Attributes<'_>) -> Id {
+            let mut metadata = self.metadata.lock().unwrap();
+            metadata.push(attrs.metadata());
+            Id::from_u64(metadata.len() as u64)
+        }
+
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, _: &Event<'_>) {}
+
+        fn enter(&self, id: &Id) {
+            self.entered.lock().unwrap().push(id.clone());
+        }
+
+        fn exit(&self, _: &Id) {
+            self.entered.lock().unwrap().pop();
+        }
+
+        fn current_span(&self) -> Current {
+            match self.entered.lock().unwrap().last() {
+                Some(id) => {
+                    let metadata = self.metadata.lock().unwrap()[id.into_u64() as usize - 1];
+                    Current::new(id.clone(), metadata)
+                }
+                None => Current::none(),
+            }
+        }
+    }
+
+    let _guard = tracing::subscriber::set_default(Spans::default());
+    let pool = Pool::builder()
+        .max_size(1)
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+
+    let span = tracing::info_span!("request");
+    let conn = async { pool.get().await.unwrap() }
+        .instrument(span.clone())
+        .await;
+    assert_eq!(conn.span().id(), span.id());
+    drop(conn);
+
+    let conn = pool.get().await.unwrap();
+    assert!(conn.span().is_none());
+}
+
+#[tokio::test]
+async fn test_would_approve() {
+    let pool = Pool::builder()
+        .max_size(4)
+        .min_idle(Some(2))
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+    assert_eq!(pool.wanted(), 0);
+    assert_eq!(pool.would_approve(1), 1);
+    assert_eq!(pool.would_approve(10), 2);
+    assert_eq!(pool.would_approve(10), 2);
+    assert_eq!(pool.state().connections, 2);
+    assert_eq!(pool.state().establishing, 0);
+}
+
+#[tokio::test]
+async fn test_global_connect_budget() {
+    static ATTEMPTS: AtomicUsize = AtomicUsize::new(0);
+
+    struct Handler;
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            ATTEMPTS.fetch_add(1, Ordering::SeqCst);
+            Err(Error)
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let pool = Pool::builder()
+        .max_size(4)
+        .connection_timeout(Duration::from_millis(300))
+        .global_connect_budget(3, Duration::from_secs(1))
+        .build_unchecked(Handler);
+
+    let results = futures_util::future::join_all((0..4).map(|_| pool.get())).await;
+    assert!(results.iter().all(|res| res.is_err()));
+    assert_eq!(ATTEMPTS.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn test_on_waiter_delivery() {
+    let outcomes = Arc::new(Mutex::new(Vec::new()));
+    let recorded = outcomes.clone();
+    let pool = Pool::builder()
+        .max_size(1)
+        .min_idle(Some(1))
+        .on_waiter_delivery(move |outcome| recorded.lock().unwrap().push(outcome))
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+
+    let conn = pool.get().await.unwrap();
+    let id = conn.connection_id();
+    assert!(timeout(Duration::from_millis(10), pool.get())
+        .await
+        .is_err());
+
+    let release = async {
+        tokio::time::sleep(Duration::from_millis(20)).await;
         drop(conn);
-        res
+    };
+    let (res, ()) = tokio::join!(pool.get(), release);
+    drop(res.unwrap());
+
+    assert_eq!(
+        *outcomes.lock().unwrap(),
+        vec![
+            WaiterDeliveryOutcome::Idle(id),
+            WaiterDeliveryOutcome::WaiterGone(id),
+            WaiterDeliveryOutcome::Delivered(id),
+            WaiterDeliveryOutcome::Idle(id),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_build_with_connections() {
+    let pool = Pool::builder()
+        .max_size(2)
+        .min_idle(Some(1))
+        .build_with_connections(
+            OkManager::<FakeConnection>::new(),
+            vec![FakeConnection, FakeConnection, FakeConnection],
+        );
+    assert_eq!(pool.state().connections, 2);
+    assert_eq!(pool.state().idle_connections, 2);
+    assert_eq!(pool.state().establishing, 0);
+
+    let (a, b) = (pool.get().await.unwrap(), pool.get().await.unwrap());
+    assert_ne!(a.connection_id(), b.connection_id());
+    assert_eq!(pool.statistics().connections_created, 0);
+}
+
+#[tokio::test]
+async fn test_park() {
+    let pool = Pool::builder()
+        .max_size(1)
+        .connection_timeout(Duration::from_millis(100))
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+
+    let conn = pool.get().await.unwrap();
+    let id = conn.connection_id();
+    let parked = conn.park();
+    assert_eq!(parked.connection_id(), id);
+    assert_eq!(pool.state().connections, 1);
+    assert_eq!(pool.state().idle_connections, 0);
+
+    // The same connection comes back
+    let conn = parked.unpark().unwrap();
+    assert_eq!(conn.connection_id(), id);
+
+    // With the pool full, another caller reclaims it
+    let parked = conn.park();
+    let conn = pool.get().await.unwrap();
+    assert_eq!(conn.connection_id(), id);
+    assert!(parked.unpark().is_none());
+
+    // Dropping the token returns it to the pool
+    drop(conn.park());
+    assert_eq!(pool.state().idle_connections, 1);
+}
+
+#[tokio::test]
+async fn test_park_then_close() {
+    let pool = Pool::builder()
+        .max_size(1)
+        .connection_timeout(Duration::from_millis(100))
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+
+    // Closing the pool closes parked connections rather than handing them out
+    let parked = pool.get().await.unwrap().park();
+    assert!(pool.drain_timeout(Duration::from_millis(10)).await);
+    assert_eq!(pool.get().await.unwrap_err(), RunError::Closed);
+    assert!(parked.unpark().is_none());
+    assert_eq!(pool.state().connections, 0);
+}
+
+#[tokio::test]
+async fn test_build_checked() {
+    let res = Pool::builder()
+        .build_checked(NthConnectionFailManager::<FakeConnection>::new(0))
+        .await;
+    assert_eq!(res.unwrap_err(), RunError::User(Error));
+
+    let pool = Pool::builder()
+        .max_size(3)
+        .min_idle(Some(2))
+        .build_checked(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+    assert!(pool.state().idle_connections >= 1);
+    assert_eq!(pool.statistics().connect_errors, 0);
+
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert_eq!(pool.state().idle_connections, 2);
+}
+
+#[tokio::test]
+async fn test_hot_idle() {
+    static VALIDATED: AtomicUsize = AtomicUsize::new(0);
+
+    struct Handler;
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            Ok(FakeConnection)
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            VALIDATED.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    let pool = Pool::builder()
+        .max_size(3)
+        .min_idle(Some(3))
+        .test_on_check_out(false)
+        .hot_idle(Some(1))
+        .build(Handler)
+        .await
+        .unwrap();
+    assert_eq!(pool.state().idle_connections, 3);
+
+    // Only the connections promoted from the cold tier are validated
+    let hot = pool.get().await.unwrap();
+    assert_eq!(VALIDATED.load(Ordering::SeqCst), 0);
+    let cold = (pool.get().await.unwrap(), pool.get().await.unwrap());
+    assert_eq!(VALIDATED.load(Ordering::SeqCst), 2);
+    drop((hot, cold));
+
+    // Only the cold tier is closed for being idle
+    let pool = Pool::builder()
+        .max_size(3)
+        .idle_timeout(Some(Duration::from_millis(50)))
+        .reaper_rate(Duration::from_millis(20))
+        .hot_idle(Some(1))
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+    let conns = (
+        pool.get().await.unwrap(),
+        pool.get().await.unwrap(),
+        pool.get().await.unwrap(),
+    );
+    drop(conns);
+    assert_eq!(pool.state().idle_connections, 3);
+
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert_eq!(pool.state().idle_connections, 1);
+}
+
+#[tokio::test]
+async fn test_run_error_map_user() {
+    #[derive(Debug, PartialEq)]
+    enum AppError {
+        Database(String),
+        Pool(RunError<String>),
+    }
+
+    impl From<RunError<Error>> for AppError {
+        fn from(err: RunError<Error>) -> Self {
+            match err.map_user(|err| err.to_string()) {
+                RunError::User(err) => AppError::Database(err),
+                err => AppError::Pool(err),
+            }
+        }
+    }
+
+    async fn query(pool: &Pool<NthConnectionFailManager<FakeConnection>>) -> Result<(), AppError> {
+        let _conn = pool.get().await?;
+        Ok(())
+    }
+
+    let pool = Pool::builder()
+        .connection_timeout(Duration::from_millis(50))
+        .connect_inline(true)
+        .build_unchecked(NthConnectionFailManager::<FakeConnection>::new(0));
+    assert_eq!(
+        query(&pool).await,
+        Err(AppError::Database(Error.to_string()))
+    );
+    assert_eq!(
+        RunError::<Error>::TimedOut.map_user(|err| err.to_string()),
+        RunError::TimedOut
+    );
+}
+
+#[tokio::test]
+async fn test_get_await_pending() {
+    let pool = Pool::builder()
+        .max_size(1)
+        .min_idle(Some(1))
+        .connection_timeout(Duration::from_millis(300))
+        .build_unchecked(NthConnectionFailManager::<FakeConnection>::new(0));
+
+    // Replenishment gives up after the connection timeout, well before ours elapses
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(pool.state().establishing, 1);
+    let start = Instant::now();
+    let res = pool.get_await_pending().await;
+    assert_eq!(res.unwrap_err(), RunError::ConnectFailed);
+    assert!(start.elapsed() < Duration::from_millis(250));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_bookkeeping_under_churn() {
+    static CONNECTS: AtomicUsize = AtomicUsize::new(0);
+    static CHECKS: AtomicUsize = AtomicUsize::new(0);
+
+    struct Handler;
+
+    #[async_trait]
+    impl ManageConnection for Handler {
+        type Connection = FakeConnection;
+        type Error = Error;
+
+        async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+            match CONNECTS.fetch_add(1, Ordering::SeqCst) % 3 {
+                0 => Err(Error),
+                _ => Ok(FakeConnection),
+            }
+        }
+
+        async fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            match CHECKS.fetch_add(1, Ordering::SeqCst) % 5 {
+                0 => Err(Error),
+                _ => Ok(()),
+            }
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            CHECKS.fetch_add(1, Ordering::SeqCst) % 7 == 0
+        }
+    }
+
+    // Every change to the bookkeeping is checked with the `debug-invariants` feature, which
+    // the tests enable
+    let pool = Pool::builder()
+        .max_size(4)
+        .min_idle(Some(2))
+        .connection_timeout(Duration::from_millis(500))
+        .min_connect_backoff(Duration::from_millis(1))
+        .max_checkout_duration(Some(Duration::from_millis(30)))
+        .reaper_rate(Duration::from_millis(5))
+        .build_unchecked(Handler);
+
+    let workers = (0..32).map(|i| {
+        let pool = pool.clone();
+        tokio::spawn(async move {
+            for j in 0..20 {
+                let conn = match pool.get().await {
+                    Ok(conn) => conn,
+                    Err(_) => continue,
+                };
+                match (i + j) % 4 {
+                    0 => drop(conn.park()),
+                    1 => tokio::time::sleep(Duration::from_millis(40)).await,
+                    _ => tokio::time::sleep(Duration::from_millis(1)).await,
+                }
+            }
+        })
     });
+    let resize = async {
+        for max_size in [2, 6, 1, 4] {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            let mut config = pool.export_config();
+            config.max_size = max_size;
+            config.min_idle = Some(1);
+            pool.apply_config(config);
+        }
+    };
+    let (results, ()) = tokio::join!(futures_util::future::join_all(workers), resize);
+    for res in results {
+        res.unwrap();
+    }
 
-    // Get the first connection.
-    rx1.await.unwrap();
-    // Get the second connection.
-    rx3.await.unwrap();
+    // Checked out on this task, so that a broken invariant fails the test
+    drop(pool.get().await);
+    assert!(pool.state().connections <= 4);
+}
 
-    let (tx5, mut rx5) = oneshot::channel();
-    let (tx6, rx6) = oneshot::channel();
-    let clone = pool.clone();
-    tokio::spawn(async move {
-        let conn = clone.get().await;
-        tx5.send(()).unwrap();
-        let res = rx6
-            .then(|r| match r {
-                Ok(()) => ok(()),
-                Err(_) => err(Error),
-            })
+#[test]
+fn test_async_return() {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+    let (pool, conn) = rt.block_on(async {
+        let pool = Pool::builder()
+            .max_size(2)
+            .async_return(true)
+            .build(OkManager::<FakeConnection>::new())
             .await
-            .map(|_| ());
-        drop(conn);
-        res
+            .unwrap();
+
+        // Returned once the spawned task runs
+        drop(pool.get().await.unwrap());
+        assert_eq!(pool.state().idle_connections, 0);
+        tokio::task::yield_now().await;
+        assert_eq!(pool.state().idle_connections, 1);
+
+        let conn = pool.get_owned().await.unwrap();
+        (pool, conn)
     });
 
-    {
-        let rx5_ref = &mut rx5;
-        // NB: The channel needs to run on a Task, so shove it onto
-        // the tokio event loop with a lazy.
-        assert_eq!(lazy(|cx| Pin::new(rx5_ref).poll(cx)).await, Poll::Pending);
-    }
+    // Without a runtime, the connection is returned right away
+    drop(rt);
+    drop(conn);
+    assert_eq!(pool.state().idle_connections, 1);
+}
 
-    // Release the first connection.
-    tx2.send(()).unwrap();
+#[tokio::test]
+async fn test_spin_retries() {
+    let outcomes = Arc::new(Mutex::new(Vec::new()));
+    let recorded = outcomes.clone();
+    let pool = Pool::builder()
+        .max_size(1)
+        .spin_retries(10, Duration::from_millis(5))
+        .on_waiter_delivery(move |outcome| recorded.lock().unwrap().push(outcome))
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
 
-    rx5.await.unwrap();
-    tx4.send(()).unwrap();
-    tx6.send(()).unwrap();
+    let conn = pool.get().await.unwrap();
+    outcomes.lock().unwrap().clear();
+    let release = async {
+        tokio::time::sleep(Duration::from_millis(12)).await;
+        drop(conn);
+    };
+    let (res, ()) = tokio::join!(pool.get(), release);
+    let _conn = res.unwrap();
+
+    // The connection was picked up from the idle queue rather than handed to a waiter
+    let outcomes = outcomes.lock().unwrap();
+    assert!(matches!(outcomes[..], [WaiterDeliveryOutcome::Idle(_)]));
 }
 
+#[cfg(feature = "opentelemetry")]
 #[tokio::test]
-async fn test_customize_connection_acquire() {
-    #[derive(Debug, Default)]
-    struct Connection {
-        custom_field: usize,
+async fn test_register_otel() {
+    use std::collections::HashMap;
+
+    use opentelemetry::metrics::MeterProvider as _;
+    use opentelemetry::KeyValue;
+    use opentelemetry_sdk::metrics::data::{Gauge, ResourceMetrics, Sum, Temporality};
+    use opentelemetry_sdk::metrics::reader::{
+        AggregationSelector, MetricReader, TemporalitySelector,
+    };
+    use opentelemetry_sdk::metrics::{
+        Aggregation, InstrumentKind, ManualReader, Pipeline, SdkMeterProvider,
+    };
+    use opentelemetry_sdk::Resource;
+
+    // Keeps a handle on the reader owned by the provider, to collect from it
+    #[derive(Clone, Debug)]
+    struct SharedReader(Arc<ManualReader>);
+
+    impl TemporalitySelector for SharedReader {
+        fn temporality(&self, kind: InstrumentKind) -> Temporality {
+            self.0.temporality(kind)
+        }
     }
 
-    #[derive(Debug, Default)]
-    struct CountingCustomizer {
-        count: AtomicUsize,
+    impl AggregationSelector for SharedReader {
+        fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+            self.0.aggregation(kind)
+        }
     }
 
-    #[async_trait]
-    impl<E: 'static> CustomizeConnection<Connection, E> for CountingCustomizer {
-        async fn on_acquire(&self, connection: &mut Connection) -> Result<(), E> {
-            connection.custom_field = 1 + self.count.fetch_add(1, Ordering::SeqCst);
-            Ok(())
+    impl MetricReader for SharedReader {
+        fn register_pipeline(&self, pipeline: std::sync::Weak<Pipeline>) {
+            self.0.register_pipeline(pipeline)
+        }
+
+        fn collect(&self, rm: &mut ResourceMetrics) -> opentelemetry::metrics::Result<()> {
+            self.0.collect(rm)
+        }
+
+        fn force_flush(&self) -> opentelemetry::metrics::Result<()> {
+            self.0.force_flush()
+        }
+
+        fn shutdown(&self) -> opentelemetry::metrics::Result<()> {
+            self.0.shutdown()
         }
     }
 
+    let reader = SharedReader(Arc::new(ManualReader::builder().build()));
+    let provider = SdkMeterProvider::builder()
+        .with_reader(reader.clone())
+        .build();
+    let collect = || {
+        let mut metrics = ResourceMetrics {
+            resource: Resource::empty(),
+            scope_metrics: Vec::new(),
+        };
+        reader.collect(&mut metrics).unwrap();
+        let metrics = metrics.scope_metrics.into_iter().flat_map(|s| s.metrics);
+        metrics
+            .map(|metric| {
+                let data = metric.data.as_any();
+                let points = match data.downcast_ref::<Gauge<u64>>() {
+                    Some(gauge) => &gauge.data_points,
+                    None => &data.downcast_ref::<Sum<u64>>().unwrap().data_points,
+                };
+                assert_eq!(points.len(), 1);
+                assert_eq!(points[0].attributes, [KeyValue::new("pool", "test")]);
+                (metric.name.into_owned(), points[0].value)
+            })
+            .collect::<HashMap<_, _>>()
+    };
+
     let pool = Pool::builder()
         .max_size(2)
-        .connection_customizer(Box::new(CountingCustomizer::default()))
-        .build(OkManager::<Connection>::new())
+        .connection_timeout(Duration::from_millis(100))
+        .build(OkManager::<FakeConnection>::new())
         .await
         .unwrap();
+    pool.register_otel(&provider.meter("bb8"), &[KeyValue::new("pool", "test")]);
 
-    // Each connection gets customized
-    {
-        let connection_1 = pool.get().await.unwrap();
-        assert_eq!(connection_1.custom_field, 1);
-        let connection_2 = pool.get().await.unwrap();
-        assert_eq!(connection_2.custom_field, 2);
+    let first = pool.get().await.unwrap();
+    let second = pool.get().await.unwrap();
+    let mut waiting = Box::pin(pool.get());
+    assert!(timeout(Duration::from_millis(20), &mut waiting)
+        .await
+        .is_err());
+
+    let metrics = collect();
+    assert_eq!(metrics["bb8.pool.connections"], 2);
+    assert_eq!(metrics["bb8.pool.idle_connections"], 0);
+    assert_eq!(metrics["bb8.pool.pending_connections"], 0);
+    assert_eq!(metrics["bb8.pool.waiters"], 1);
+    assert_eq!(metrics["bb8.pool.connections_created"], 2);
+    assert_eq!(metrics["bb8.pool.checkouts"], 2);
+    assert_eq!(metrics["bb8.pool.checkout_timeouts"], 0);
+
+    // The waiter gives up once the connection timeout elapses
+    assert!(waiting.await.is_err());
+    drop((first, second));
+    let metrics = collect();
+    assert_eq!(metrics["bb8.pool.idle_connections"], 2);
+    assert_eq!(metrics["bb8.pool.waiters"], 0);
+    assert_eq!(metrics["bb8.pool.checkout_timeouts"], 1);
+    assert_eq!(metrics["bb8.pool.connections_closed"], 0);
+
+    assert!(pool.drain_timeout(Duration::ZERO).await);
+    let metrics = collect();
+    assert_eq!(metrics["bb8.pool.connections"], 0);
+    assert_eq!(metrics["bb8.pool.connections_closed"], 2);
+
+    // The callbacks do not keep the pool alive
+    let weak = pool.downgrade();
+    drop(pool);
+    assert!(weak.upgrade().is_none());
+    assert_eq!(collect()["bb8.pool.connections_created"], 2);
+}
+
+#[tokio::test]
+async fn test_shards() {
+    let pool = Pool::builder()
+        .max_size(5)
+        .min_idle(Some(3))
+        .shards(2)
+        .connection_timeout(Duration::from_millis(100))
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+    let events = pool.events();
+    futures_util::pin_mut!(events);
+    let mut state = pool.subscribe();
+
+    // The configuration is split across the shards and reported for the whole pool
+    let info = pool.info();
+    assert_eq!((info.max_size, info.min_idle), (5, Some(3)));
+    assert_eq!(info.state.connections, 3);
+    assert_eq!(state.borrow().idle_connections, 3);
+
+    // Checkouts go to each shard in turn, and connection IDs are unique across shards
+    let conns = try_join_all((0..5).map(|_| pool.get())).await.unwrap();
+    let ids = conns.iter().map(|conn| conn.connection_id());
+    let ids = ids.collect::<std::collections::HashSet<_>>();
+    assert_eq!(ids.len(), 5);
+    assert_eq!(pool.state().connections, 5);
+
+    // The events of every shard are reported
+    let mut checked_out = std::collections::HashSet::new();
+    while checked_out.len() < 5 {
+        if let Some(PoolEvent::CheckedOut(id)) = events.next().await {
+            checked_out.insert(id);
+        }
     }
+    assert_eq!(checked_out, ids);
 
-    // Connections don't get customized again on re-use
-    let connection_1_or_2 = pool.get().await.unwrap();
-    assert!(connection_1_or_2.custom_field == 1 || connection_1_or_2.custom_field == 2);
+    // A caller waits once every shard is full
+    assert!(pool.get().await.is_err());
+    let statistics = pool.statistics();
+    assert_eq!(statistics.connections_created, 5);
+    assert_eq!(statistics.checkouts, 5);
+    assert_eq!(statistics.checkout_timeouts, 1);
+    drop(conns);
+    assert_eq!(pool.state().idle_connections, 5);
+
+    // Configuration changes are split across the shards too
+    let mut config = pool.export_config();
+    config.max_size = 3;
+    pool.apply_config(config);
+    assert_eq!(pool.export_config().max_size, 3);
+    assert_eq!(pool.state().connections, 3);
+    assert_eq!(pool.statistics().connections_closed, 2);
+
+    // Draining closes the connections of every shard
+    assert!(pool.drain_timeout(Duration::from_secs(1)).await);
+    assert_eq!(pool.state().connections, 0);
+    timeout(Duration::from_secs(1), async {
+        while state.borrow().connections > 0 {
+            state.changed().await.unwrap();
+        }
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_shards_take_idle_from_sibling() {
+    let pool = Pool::builder()
+        .max_size(2)
+        .min_idle(Some(2))
+        .shards(2)
+        .connection_timeout(Duration::from_millis(100))
+        .build(OkManager::<FakeConnection>::new())
+        .await
+        .unwrap();
+
+    // Checkouts alternate between the shards, one connection each
+    let first = pool.get().await.unwrap();
+    let second = pool.get().await.unwrap();
+    let id = second.connection_id();
+    drop(second);
+
+    // The first shard is full, so its next caller is served by the idle connection of the other
+    let start = Instant::now();
+    let third = pool.get().await.unwrap();
+    assert_eq!(third.connection_id(), id);
+    assert!(start.elapsed() < Duration::from_millis(100));
+    drop((first, third));
+    assert_eq!(pool.state().idle_connections, 2);
 }
@@ -71,6 +71,10 @@ where
     fn has_broken(&self, conn: &mut Self::Connection) -> bool {
         conn.is_closed()
     }
+
+    fn is_transient(&self, error: &Self::Error) -> bool {
+        error.is_closed()
+    }
 }
 
 impl<Tls> fmt::Debug for PostgresConnectionManager<Tls>
@@ -78,4 +78,8 @@ impl bb8::ManageConnection for RedisConnectionManager {
     fn has_broken(&self, _: &mut Self::Connection) -> bool {
         false
     }
+
+    fn is_transient(&self, error: &Self::Error) -> bool {
+        error.is_connection_dropped()
+    }
 }